@@ -0,0 +1,113 @@
+//! Measures the heap used by the in-memory transaction map per row of a generated workload
+//!
+//! cargo run --release --example transaction_map_memory -- <rows>
+//!
+//! The workload is 60% deposits, 30% withdrawals and 10% disputes, resolves and chargebacks of
+//! earlier rows, spread over every client. Only the bytes requested from the allocator are
+//! counted, the allocator's own overhead isn't
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use zzzzzzzzzzz::{
+    ZzPolicyOptions,
+    common::zz_amount::{ZzIAmount, ZzUAmount},
+    domain::{
+        client_balance::{ClientId, ZzClientBalance},
+        transaction::{TransactionHashMapImpl, TransactionMap, TxId, ZzTx, ZzTxType},
+    },
+};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            let grown = new_size - layout.size();
+            let allocated = ALLOCATED.fetch_add(grown, Ordering::Relaxed) + grown;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        } else {
+            ALLOCATED.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// The client of the row that created the tx id
+fn client_of(tx_id: TxId) -> ClientId {
+    ClientId::from(splitmix64(tx_id as u64) as u16)
+}
+
+fn main() {
+    let rows: u64 = std::env::args()
+        .nth(1)
+        .map_or(1_000_000, |rows| rows.parse().unwrap());
+
+    let policy_options = ZzPolicyOptions::default();
+    let mut balance = ZzClientBalance::new(0);
+    balance.available = ZzIAmount::new(u64::MAX.into(), 0).unwrap();
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let mut tx_map = TransactionHashMapImpl::default();
+
+    for row in 0..rows {
+        let random = splitmix64(row ^ 0x5eed);
+        let amount = ZzUAmount::new((random % 10_000).into(), (random >> 32) as u32 % 10_000);
+        let referenced = row.saturating_sub(1 + (random >> 16) % 1000) as TxId;
+
+        let (r#type, tx_id) = match random % 100 {
+            0..60 => (ZzTxType::Deposit(amount.unwrap()), row as TxId),
+            60..90 => (ZzTxType::Withdrawal(amount.unwrap()), row as TxId),
+            90..95 => (ZzTxType::Dispute(None), referenced),
+            95..99 => (ZzTxType::Resolve, referenced),
+            _ => (ZzTxType::Chargeback, referenced),
+        };
+        let tx = ZzTx {
+            r#type,
+            client_id: client_of(tx_id),
+            tx_id,
+            timestamp: None,
+        };
+        tx_map.insert_transaction(&policy_options, tx, Some(&balance));
+    }
+
+    let used = ALLOCATED.load(Ordering::Relaxed) - before;
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    println!("rows: {rows}");
+    println!(
+        "heap: {used} bytes ({:.1} bytes/row)",
+        used as f64 / rows as f64
+    );
+    println!(
+        "peak: {peak} bytes ({:.1} bytes/row)",
+        peak as f64 / rows as f64
+    );
+
+    drop(tx_map);
+}
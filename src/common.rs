@@ -1 +1,3 @@
+pub mod encoding;
+pub mod signature;
 pub mod zz_amount;
@@ -0,0 +1,96 @@
+use std::io::{self, Read, Write};
+
+use crate::common::zz_amount::{ZzIAmount, ZzUAmount};
+
+pub fn write_u8(w: &mut (impl Write + ?Sized), value: u8) -> io::Result<()> {
+    w.write_all(&[value])
+}
+
+pub fn write_u32(w: &mut (impl Write + ?Sized), value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64(w: &mut (impl Write + ?Sized), value: u64) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+/// The bytes prefixed by their length
+pub fn write_bytes(w: &mut (impl Write + ?Sized), bytes: &[u8]) -> io::Result<()> {
+    write_u32(w, bytes.len() as u32)?;
+    w.write_all(bytes)
+}
+
+pub fn write_u_amount(w: &mut (impl Write + ?Sized), amount: &ZzUAmount) -> io::Result<()> {
+    write_bytes(w, &amount.to_bytes_le())
+}
+
+pub fn write_i_amount(w: &mut (impl Write + ?Sized), amount: &ZzIAmount) -> io::Result<()> {
+    write_bytes(w, &amount.to_signed_bytes_le())
+}
+
+fn read_array<const N: usize>(r: &mut (impl Read + ?Sized)) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub fn read_u8(r: &mut (impl Read + ?Sized)) -> io::Result<u8> {
+    Ok(read_array::<1>(r)?[0])
+}
+
+pub fn read_u32(r: &mut (impl Read + ?Sized)) -> io::Result<u32> {
+    read_array(r).map(u32::from_le_bytes)
+}
+
+pub fn read_u64(r: &mut (impl Read + ?Sized)) -> io::Result<u64> {
+    read_array(r).map(u64::from_le_bytes)
+}
+
+pub fn read_bytes(r: &mut (impl Read + ?Sized)) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)?;
+    let mut bytes = vec![0; len as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn read_u_amount(r: &mut (impl Read + ?Sized)) -> io::Result<ZzUAmount> {
+    read_bytes(r).map(|bytes| ZzUAmount::from_bytes_le(&bytes))
+}
+
+pub fn read_i_amount(r: &mut (impl Read + ?Sized)) -> io::Result<ZzIAmount> {
+    read_bytes(r).map(|bytes| ZzIAmount::from_signed_bytes_le(&bytes))
+}
+
+/// An error for bytes that weren't written by this module
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let u_amount = ZzUAmount::new(u64::MAX.into(), 5).unwrap();
+        let i_amount = ZzIAmount::new((-12).into(), 3400).unwrap();
+
+        let mut buf = vec![];
+        write_u8(&mut buf, 7).unwrap();
+        write_u32(&mut buf, 65535).unwrap();
+        write_u64(&mut buf, 1 << 40).unwrap();
+        write_u_amount(&mut buf, &u_amount).unwrap();
+        write_i_amount(&mut buf, &i_amount).unwrap();
+        write_i_amount(&mut buf, &ZzIAmount::zero()).unwrap();
+
+        let r = &mut buf.as_slice();
+        assert_eq!(read_u8(r).unwrap(), 7);
+        assert_eq!(read_u32(r).unwrap(), 65535);
+        assert_eq!(read_u64(r).unwrap(), 1 << 40);
+        assert_eq!(read_u_amount(r).unwrap(), u_amount);
+        assert_eq!(read_i_amount(r).unwrap(), i_amount);
+        assert_eq!(read_i_amount(r).unwrap(), ZzIAmount::zero());
+        assert!(r.is_empty());
+        assert!(read_u8(r).is_err());
+    }
+}
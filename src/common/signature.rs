@@ -0,0 +1,132 @@
+use std::{io, os::unix::fs::FileExt, path::Path};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// # Errors
+///
+/// Failed to read the file
+fn file_mac(key: &[u8], file: &std::fs::File) -> io::Result<HmacSha256> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    let buf = &mut vec![0; 1024 * 1024];
+    let mut offset = 0;
+
+    loop {
+        let size = file.read_at(buf, offset)?;
+        if size == 0 {
+            break;
+        }
+        offset += size as u64;
+        mac.update(&buf[..size]);
+    }
+
+    Ok(mac)
+}
+
+/// Computes the hex encoded HMAC-SHA256 of the whole file, this is the same output as
+/// `openssl dgst -sha256 -hmac <key>`
+///
+/// # Errors
+///
+/// Failed to read the file
+pub fn sign_file(key: &[u8], file: &std::fs::File) -> io::Result<String> {
+    Ok(hex::encode(file_mac(key, file)?.finalize().into_bytes()))
+}
+
+/// The decoded signature, None if the file is missing or malformed
+fn read_signature(signature_path: &Path) -> Option<Vec<u8>> {
+    let signature = std::fs::read_to_string(signature_path).ok()?;
+    hex::decode(signature.trim()).ok()
+}
+
+/// Checks if the signature file contains the hex encoded HMAC-SHA256 of the file. A missing or
+/// malformed signature file is considered an invalid signature
+///
+/// # Errors
+///
+/// Failed to read the file
+pub fn verify_file_signature(
+    key: &[u8],
+    file: &std::fs::File,
+    signature_path: &Path,
+) -> io::Result<bool> {
+    let Some(signature) = read_signature(signature_path) else {
+        return Ok(false);
+    };
+
+    Ok(file_mac(key, file)?.verify_slice(&signature).is_ok())
+}
+
+/// Computes the hex encoded HMAC-SHA256 of the bytes
+pub fn sign(key: &[u8], bytes: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(bytes);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Same as verify_file_signature for bytes in memory
+pub fn verify_signature(key: &[u8], bytes: &[u8], signature_path: &Path) -> bool {
+    let Some(signature) = read_signature(signature_path) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(bytes);
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_sign_file_matches_known_hmac() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known");
+        std::fs::write(&path, "The quick brown fox jumps over the lazy dog").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+
+        assert_eq!(
+            sign_file(b"key", &file).unwrap(),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_verify_file_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let signature_path = dir.path().join("input.csv.sig");
+        std::fs::write(&path, "type,client,tx,amount\nfreeze,1,1,\n").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+
+        // missing signature
+        assert!(!verify_file_signature(b"key", &file, &signature_path).unwrap());
+
+        let mut signature = std::fs::File::create(&signature_path).unwrap();
+        writeln!(signature, "{}", sign_file(b"key", &file).unwrap()).unwrap();
+        assert!(verify_file_signature(b"key", &file, &signature_path).unwrap());
+
+        // wrong key
+        assert!(!verify_file_signature(b"other", &file, &signature_path).unwrap());
+
+        // malformed signature
+        std::fs::write(&signature_path, "not hex").unwrap();
+        assert!(!verify_file_signature(b"key", &file, &signature_path).unwrap());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let signature_path = dir.path().join("digest.sig");
+        std::fs::write(&signature_path, sign(b"key", b"digest")).unwrap();
+
+        assert!(verify_signature(b"key", b"digest", &signature_path));
+        assert!(!verify_signature(b"key", b"other", &signature_path));
+        assert!(!verify_signature(b"other", b"digest", &signature_path));
+    }
+}
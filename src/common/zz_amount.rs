@@ -7,6 +7,8 @@ use fake::Faker;
 use num_bigint::{BigInt, BigUint, Sign};
 use serde::Serialize;
 
+use crate::RoundingOptions;
+
 /// Adaptor trait to allow creating a DRY generic ZzAmount<Int>
 #[doc(hidden)]
 pub trait IntFromBytes: Add + Sub + Serialize + Sized + Display {
@@ -35,7 +37,7 @@ impl IntFromBytes for BigUint {
 /// A simple struct implementation for the use case of unbounded integer part and up to 4 digits of
 /// precision for decimal. This is done by serializing/deserializing the struct into the big int
 /// divided by 10_000
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ZzAmount<Int: IntFromBytes> {
     integer: Int,
 }
@@ -66,6 +68,73 @@ impl ZzUAmount {
             integer: num_bigint::BigInt::from_biguint(num_bigint::Sign::Plus, self.integer),
         }
     }
+
+    pub fn zero() -> Self {
+        Self {
+            integer: BigUint::ZERO,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.integer == BigUint::ZERO
+    }
+
+    /// The little endian bytes of the scaled integer, see from_bytes_le
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        self.integer.to_bytes_le()
+    }
+
+    pub fn from_bytes_le(bytes: &[u8]) -> Self {
+        Self {
+            integer: BigUint::from_bytes_le(bytes),
+        }
+    }
+
+    /// The scaled integer (the amount times 10_000) if it fits in a u64
+    pub fn to_scaled_u64(&self) -> Option<u64> {
+        u64::try_from(&self.integer).ok()
+    }
+
+    pub fn from_scaled_u64(scaled: u64) -> Self {
+        Self {
+            integer: scaled.into(),
+        }
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.integer += &other.integer;
+    }
+
+    /// # Panics
+    ///
+    /// If other is greater than self
+    pub fn sub(&mut self, other: &Self) {
+        self.integer -= &other.integer;
+    }
+
+    /// Computes percentage% of self. The result is exact up to the 4th decimal and rounded
+    /// according to rounding
+    pub fn percentage(&self, percentage: &ZzUAmount, rounding: RoundingOptions) -> ZzUAmount {
+        // both are already multiplied by 10_000, so dividing by 10_000 * 100 keeps the scale
+        let numerator = &self.integer * &percentage.integer;
+        let denominator = BigUint::from(10_000u32 * 100);
+        let quotient = &numerator / &denominator;
+        let double_remainder = (&numerator % &denominator) * 2u32;
+
+        let round_up = match rounding {
+            RoundingOptions::Down => false,
+            RoundingOptions::Up => double_remainder != BigUint::ZERO,
+            RoundingOptions::HalfUp => double_remainder >= denominator,
+            RoundingOptions::HalfEven => {
+                double_remainder > denominator
+                    || (double_remainder == denominator && quotient.bit(0))
+            }
+        };
+
+        Self {
+            integer: if round_up { quotient + 1u32 } else { quotient },
+        }
+    }
 }
 
 impl ZzIAmount {
@@ -94,6 +163,17 @@ impl ZzIAmount {
         Self { integer: 0.into() }
     }
 
+    /// The little endian two's complement bytes of the scaled integer, see from_signed_bytes_le
+    pub fn to_signed_bytes_le(&self) -> Vec<u8> {
+        self.integer.to_signed_bytes_le()
+    }
+
+    pub fn from_signed_bytes_le(bytes: &[u8]) -> Self {
+        Self {
+            integer: BigInt::from_signed_bytes_le(bytes),
+        }
+    }
+
     pub fn add(&mut self, other: &Self) {
         self.integer += &other.integer;
     }
@@ -102,6 +182,23 @@ impl ZzIAmount {
         self.integer -= &other.integer;
     }
 
+    pub fn abs(&self) -> ZzUAmount {
+        ZzUAmount {
+            integer: self.integer.magnitude().clone(),
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.integer.sign() == Sign::Minus
+    }
+
+    /// The amount if it isn't negative, otherwise zero
+    pub fn to_u_amount_saturating(&self) -> ZzUAmount {
+        ZzUAmount {
+            integer: self.integer.to_biguint().unwrap_or_default(),
+        }
+    }
+
     pub fn greater_eq_than(&self, other: ZzUAmount) -> bool {
         if self.integer.sign() != Sign::Minus {
             let other_int: BigInt = other.integer.into();
@@ -275,4 +372,84 @@ mod tests {
         a.sub(&b);
         assert_eq!(a.to_string(), "-0.0001");
     }
+
+    #[test]
+    fn test_to_u_amount_saturating() {
+        assert_eq!(
+            amt_i_parts(3, 1234).to_u_amount_saturating(),
+            ZzUAmount::new(3u32.into(), 1234).unwrap()
+        );
+        assert_eq!(
+            amt_i_parts(-3, 1234).to_u_amount_saturating(),
+            ZzUAmount::zero()
+        );
+    }
+
+    #[test]
+    fn test_percentage_rounding() {
+        let amt = |int: u32, dec: u32| ZzUAmount::new(int.into(), dec).unwrap();
+
+        // exact
+        assert_eq!(
+            amt(200, 0)
+                .percentage(&amt(1, 5000), RoundingOptions::Down)
+                .to_string(),
+            "3"
+        );
+
+        // 1.5% of 0.0100 = 0.00015
+        let cases = [
+            (RoundingOptions::Down, "0.0001"),
+            (RoundingOptions::Up, "0.0002"),
+            (RoundingOptions::HalfUp, "0.0002"),
+            (RoundingOptions::HalfEven, "0.0002"),
+        ];
+        for (rounding, expected) in cases {
+            assert_eq!(
+                amt(0, 100).percentage(&amt(1, 5000), rounding).to_string(),
+                expected
+            );
+        }
+
+        // 2.5% of 0.0100 = 0.00025
+        let cases = [
+            (RoundingOptions::Down, "0.0002"),
+            (RoundingOptions::Up, "0.0003"),
+            (RoundingOptions::HalfUp, "0.0003"),
+            (RoundingOptions::HalfEven, "0.0002"),
+        ];
+        for (rounding, expected) in cases {
+            assert_eq!(
+                amt(0, 100).percentage(&amt(2, 5000), rounding).to_string(),
+                expected
+            );
+        }
+
+        // 1% of 0.0040 = 0.00004
+        let cases = [
+            (RoundingOptions::Down, "0"),
+            (RoundingOptions::Up, "0.0001"),
+            (RoundingOptions::HalfUp, "0"),
+            (RoundingOptions::HalfEven, "0"),
+        ];
+        for (rounding, expected) in cases {
+            assert_eq!(
+                amt(0, 40).percentage(&amt(1, 0), rounding).to_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_u_amount_add_sub_and_ordering() {
+        let mut a = ZzUAmount::new(10u32.into(), 5000).unwrap();
+        let b = ZzUAmount::new(0u32.into(), 5001).unwrap();
+
+        assert!(b < a);
+        a.sub(&b);
+        assert_eq!(a.to_string(), "9.9999");
+        a.add(&b);
+        assert_eq!(a.to_string(), "10.5000");
+        assert!(ZzUAmount::zero().is_zero());
+    }
 }
@@ -1,2 +1,8 @@
 pub mod client_balance;
+pub mod client_store;
+pub mod engine;
+pub mod fee;
+pub mod journal;
+pub mod observer;
+pub mod risk;
 pub mod transaction;
@@ -1,9 +1,21 @@
 use fake::Dummy;
 use serde::Serialize;
 
-use crate::{common::zz_amount::ZzIAmount, domain::transaction::ZzTxEffect};
+use std::io::{self, Read, Write};
 
-pub type ClientId = u16;
+use crate::{
+    common::{
+        encoding::{
+            read_i_amount, read_u8, read_u32, read_u64, write_i_amount, write_u8, write_u32,
+            write_u64,
+        },
+        zz_amount::ZzIAmount,
+    },
+    domain::transaction::{TxId, ZzTxEffect, ZzTxTypeDiscriminants},
+};
+
+/// A numeric client id or the id of a string client id, see ClientIdOptions
+pub type ClientId = u64;
 
 /// Represents the current state of a client's balance.
 #[derive(Debug, Clone, PartialEq, Serialize, Dummy)]
@@ -14,16 +26,45 @@ pub struct ZzClientBalance {
     pub held: ZzIAmount,
     pub total: ZzIAmount,
     pub locked: bool,
+    /// Amount owed by the client that couldn't be held by a dispute, see
+    /// NegativeBalanceOptions::Cap. It isn't part of the balance sheet
+    #[serde(skip)]
+    pub receivable: ZzIAmount,
+    /// The tx id and row number of the transaction that locked the account, it isn't part of
+    /// the balance sheet
+    #[serde(skip)]
+    pub locked_by: Option<(TxId, u64)>,
 }
 
 impl ZzClientBalance {
-    /// Mutates the client's balance depending on the effect of a transaction
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            available: ZzIAmount::zero(),
+            held: ZzIAmount::zero(),
+            total: ZzIAmount::zero(),
+            locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
+        }
+    }
+
+    /// Mutates the client's balance depending on the effect of a transaction of the given type
     ///
     /// # Panics
     ///
-    /// Calling this function with locked = true will panic
-    pub fn process_tx_effect(&mut self, effect: ZzTxEffect) {
-        assert!(!self.locked, "Called process tx effect with locked Balance");
+    /// Calling this function with locked = true will panic, unless the effect unlocks the account
+    /// or it's a resolve or chargeback that releases held funds (settles a dispute, see
+    /// ZzPolicyOptions::settle_disputes_after_lock)
+    pub fn process_tx_effect(&mut self, r#type: ZzTxTypeDiscriminants, effect: &ZzTxEffect) {
+        let settles_dispute = matches!(
+            r#type,
+            ZzTxTypeDiscriminants::Resolve | ZzTxTypeDiscriminants::Chargeback
+        ) && effect.held == Some(false);
+        assert!(
+            !self.locked || effect.locked == Some(false) || settles_dispute,
+            "Called process tx effect with locked Balance"
+        );
 
         fn apply(b: bool, cur: &mut ZzIAmount, other: &ZzIAmount) {
             if b {
@@ -33,7 +74,7 @@ impl ZzClientBalance {
             }
         }
 
-        let amount = effect.amount.to_i_amount();
+        let amount = effect.amount.clone().to_i_amount();
 
         if let Some(change_available) = effect.available {
             apply(change_available, &mut self.available, &amount);
@@ -43,7 +84,21 @@ impl ZzClientBalance {
             apply(change_held, &mut self.held, &amount);
         }
 
-        self.locked |= effect.locked;
+        if let Some((change_receivable, receivable)) = &effect.receivable {
+            apply(
+                *change_receivable,
+                &mut self.receivable,
+                &receivable.clone().to_i_amount(),
+            );
+        }
+
+        if let Some((_, fee)) = &effect.fee {
+            self.available.sub(&fee.clone().to_i_amount());
+        }
+
+        if let Some(locked) = effect.locked {
+            self.locked = locked;
+        }
     }
 
     pub fn compute_total(&mut self) {
@@ -51,6 +106,48 @@ impl ZzClientBalance {
         total.add(&self.held);
         self.total = total;
     }
+
+    /// If the client ended up owing money, either through a negative available fund or through
+    /// a receivable
+    pub fn is_negative(&self) -> bool {
+        self.available.is_negative() || self.receivable != ZzIAmount::zero()
+    }
+
+    /// Writes the balance for a checkpoint, see read
+    pub fn write(&self, w: &mut (impl Write + ?Sized)) -> io::Result<()> {
+        write_u64(w, self.client_id)?;
+        write_i_amount(w, &self.available)?;
+        write_i_amount(w, &self.held)?;
+        write_i_amount(w, &self.total)?;
+        write_u8(w, self.locked as u8)?;
+        write_i_amount(w, &self.receivable)?;
+        match self.locked_by {
+            Some((tx_id, row)) => {
+                write_u8(w, 1)?;
+                write_u32(w, tx_id)?;
+                write_u64(w, row)
+            }
+            None => write_u8(w, 0),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// The bytes weren't written by ZzClientBalance::write
+    pub fn read(r: &mut (impl Read + ?Sized)) -> io::Result<Self> {
+        Ok(Self {
+            client_id: read_u64(r)?,
+            available: read_i_amount(r)?,
+            held: read_i_amount(r)?,
+            total: read_i_amount(r)?,
+            locked: read_u8(r)? == 1,
+            receivable: read_i_amount(r)?,
+            locked_by: match read_u8(r)? {
+                1 => Some((read_u32(r)?, read_u64(r)?)),
+                _ => None,
+            },
+        })
+    }
 }
 
 #[cfg(test)]
@@ -77,7 +174,10 @@ mod tests {
             amount: make_uamount(amount),
             available,
             held,
-            locked,
+            locked: locked.then_some(true),
+            receivable: None,
+            counterparty: None,
+            fee: None,
         }
     }
 
@@ -89,10 +189,12 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(150),
             locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         let effect = make_tx_effect(25, Some(true), None, false);
-        balance.process_tx_effect(effect);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Deposit, &effect);
 
         assert_eq!(balance.available.to_string(), "125");
         assert_eq!(balance.held.to_string(), "50");
@@ -108,10 +210,12 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(150),
             locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         let effect = make_tx_effect(30, Some(false), None, false);
-        balance.process_tx_effect(effect);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Withdrawal, &effect);
 
         assert_eq!(balance.available.to_string(), "70");
         assert_eq!(balance.held.to_string(), "50");
@@ -125,10 +229,12 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(150),
             locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         let effect = make_tx_effect(20, None, Some(true), false);
-        balance.process_tx_effect(effect);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Dispute, &effect);
 
         assert_eq!(balance.available.to_string(), "100");
         assert_eq!(balance.held.to_string(), "70");
@@ -143,10 +249,24 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(150),
             locked: true,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         let effect = make_tx_effect(10, Some(true), None, false);
-        balance.process_tx_effect(effect);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Deposit, &effect);
+    }
+
+    #[test]
+    #[should_panic(expected = "Called process tx effect with locked Balance")]
+    fn test_process_tx_effect_locked_void_panics() {
+        let mut balance = ZzClientBalance::new(1);
+        balance.held = make_iamount(10);
+        balance.locked = true;
+
+        // it has the same effect as a resolve
+        let effect = make_tx_effect(10, Some(true), Some(false), false);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Void, &effect);
     }
 
     #[test]
@@ -157,13 +277,48 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(150),
             locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         let effect = make_tx_effect(10, Some(true), None, true);
-        balance.process_tx_effect(effect);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Freeze, &effect);
+        assert!(balance.locked);
+    }
+
+    #[test]
+    fn test_process_tx_effect_locked_releases_held() {
+        let mut balance = ZzClientBalance::new(1);
+        balance.held = make_iamount(15);
+        balance.locked = true;
+
+        // resolve
+        balance.process_tx_effect(
+            ZzTxTypeDiscriminants::Resolve,
+            &make_tx_effect(10, Some(true), Some(false), false),
+        );
+        // chargeback
+        balance.process_tx_effect(
+            ZzTxTypeDiscriminants::Chargeback,
+            &make_tx_effect(5, None, Some(false), true),
+        );
+
+        assert_eq!(balance.available.to_string(), "10");
+        assert_eq!(balance.held.to_string(), "0");
         assert!(balance.locked);
     }
 
+    #[test]
+    fn test_process_tx_effect_unlocking() {
+        let mut balance = ZzClientBalance::new(1);
+        balance.locked = true;
+
+        let mut effect = make_tx_effect(0, None, None, false);
+        effect.locked = Some(false);
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Unlock, &effect);
+        assert!(!balance.locked);
+    }
+
     #[test]
     fn test_total_balance_is_calculated_correctly() {
         let expected_balance = ZzClientBalance {
@@ -172,6 +327,8 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(150),
             locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         let mut balance = ZzClientBalance {
@@ -180,10 +337,29 @@ mod tests {
             held: make_iamount(50),
             total: make_iamount(0),
             locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
         };
 
         balance.compute_total();
 
         assert_eq!(balance, expected_balance);
     }
+
+    #[test]
+    fn test_process_tx_effect_receivable() {
+        let mut balance = ZzClientBalance::new(1);
+
+        let mut effect = make_tx_effect(0, None, None, false);
+        effect.receivable = Some((true, make_uamount(7)));
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Dispute, &effect);
+        assert_eq!(balance.receivable.to_string(), "7");
+        assert!(balance.is_negative());
+
+        let mut effect = make_tx_effect(0, None, None, false);
+        effect.receivable = Some((false, make_uamount(7)));
+        balance.process_tx_effect(ZzTxTypeDiscriminants::Resolve, &effect);
+        assert_eq!(balance.receivable, ZzIAmount::zero());
+        assert!(!balance.is_negative());
+    }
 }
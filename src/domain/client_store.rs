@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    common::encoding::{invalid_data, read_bytes, read_u64, write_bytes, write_u64},
+    domain::client_balance::{ClientId, ZzClientBalance},
+    error::{ZzError, ZzResult},
+};
+
+/// Where the balances are kept, by client id
+pub trait ClientStore {
+    fn get(&self, client_id: ClientId) -> Option<&ZzClientBalance>;
+
+    fn get_mut(&mut self, client_id: ClientId) -> Option<&mut ZzClientBalance>;
+
+    /// The balance of the client, a new one if the client didn't have any
+    fn get_or_insert(&mut self, client_id: ClientId) -> &mut ZzClientBalance;
+
+    /// Every balance, in no particular order
+    fn iter(&self) -> impl Iterator<Item = &ZzClientBalance>;
+
+    /// Every balance, sorted by client id (by name for string client ids)
+    fn into_sorted(self, client_names: &ZzClientNames) -> Vec<ZzClientBalance>;
+}
+
+/// A slot for every u16 client id, indexed by the id
+pub struct DenseClientStore {
+    balances: Vec<Option<ZzClientBalance>>,
+}
+
+impl Default for DenseClientStore {
+    fn default() -> Self {
+        Self {
+            balances: vec![None; u16::MAX as usize + 1],
+        }
+    }
+}
+
+impl ClientStore for DenseClientStore {
+    fn get(&self, client_id: ClientId) -> Option<&ZzClientBalance> {
+        self.balances.get(client_id as usize)?.as_ref()
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> Option<&mut ZzClientBalance> {
+        self.balances.get_mut(client_id as usize)?.as_mut()
+    }
+
+    /// # Panics
+    ///
+    /// The client id doesn't fit in a u16. The parser rejects those and a wider fee account
+    /// gets the sparse store
+    fn get_or_insert(&mut self, client_id: ClientId) -> &mut ZzClientBalance {
+        self.balances
+            .get_mut(client_id as usize)
+            .unwrap_or_else(|| panic!("Client id {client_id} doesn't fit in a u16"))
+            .get_or_insert_with(|| ZzClientBalance::new(client_id))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &ZzClientBalance> {
+        self.balances.iter().flatten()
+    }
+
+    fn into_sorted(self, _client_names: &ZzClientNames) -> Vec<ZzClientBalance> {
+        self.balances.into_iter().flatten().collect()
+    }
+}
+
+/// Only the clients that have a balance, for ids that are too wide to have a slot each
+#[derive(Default)]
+pub struct SparseClientStore {
+    balances: HashMap<ClientId, ZzClientBalance>,
+}
+
+impl ClientStore for SparseClientStore {
+    fn get(&self, client_id: ClientId) -> Option<&ZzClientBalance> {
+        self.balances.get(&client_id)
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> Option<&mut ZzClientBalance> {
+        self.balances.get_mut(&client_id)
+    }
+
+    fn get_or_insert(&mut self, client_id: ClientId) -> &mut ZzClientBalance {
+        self.balances
+            .entry(client_id)
+            .or_insert_with(|| ZzClientBalance::new(client_id))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &ZzClientBalance> {
+        self.balances.values()
+    }
+
+    fn into_sorted(self, client_names: &ZzClientNames) -> Vec<ZzClientBalance> {
+        let mut balances: Vec<_> = self.balances.into_values().collect();
+        balances
+            .sort_by_cached_key(|balance| (client_names.get(balance.client_id), balance.client_id));
+        balances
+    }
+}
+
+/// The names of the string client ids by the id they were interned as, see ClientIdOptions. The
+/// engine owns them, its clones share them with the parser, the journal, the observers and the
+/// output
+#[derive(Clone, Debug, Default)]
+pub struct ZzClientNames(Arc<RwLock<ClientNames>>);
+
+#[derive(Clone, Debug, Default)]
+struct ClientNames {
+    names: HashMap<ClientId, Box<str>>,
+    /// the first name that got the id of another name, see ZzClientNames::check
+    collision: Option<(Box<str>, Box<str>)>,
+}
+
+impl ZzClientNames {
+    /// The id of a string client id. It's the first 8 bytes of the name's SHA-256, so a name gets
+    /// the same id in every run and checkpoints or config files don't depend on the order names
+    /// are seen
+    pub fn id(name: &str) -> ClientId {
+        let digest = Sha256::digest(name.as_bytes());
+        ClientId::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    /// The id of the name, the name is kept so the id is displayed as the name. If another name
+    /// has the same id it's kept for check, which fails from then on
+    pub fn intern(&self, name: &str) -> ClientId {
+        let client_id = Self::id(name);
+
+        let known = self
+            .0
+            .read()
+            .unwrap()
+            .names
+            .get(&client_id)
+            .map(|known| **known == *name);
+        if known != Some(true) {
+            let mut names = self.0.write().unwrap();
+            let ClientNames { names, collision } = &mut *names;
+            let known = names.entry(client_id).or_insert_with(|| name.into());
+            if **known != *name && collision.is_none() {
+                *collision = Some((known.clone(), name.into()));
+            }
+        }
+
+        client_id
+    }
+
+    /// # Errors
+    ///
+    /// ZzError::Policy if a name got the id of another name
+    pub fn check(&self) -> ZzResult<()> {
+        match &self.0.read().unwrap().collision {
+            Some((known, name)) => Err(ZzError::Policy(format!(
+                "Client {name} has the same id as client {known}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// A copy of the names, what's interned in it isn't shared with these
+    pub fn fork(&self) -> Self {
+        Self(Arc::new(RwLock::new(self.0.read().unwrap().clone())))
+    }
+
+    /// The name of the client if its id was interned
+    pub fn get(&self, client_id: ClientId) -> Option<String> {
+        self.0
+            .read()
+            .unwrap()
+            .names
+            .get(&client_id)
+            .map(|name| name.to_string())
+    }
+
+    /// Displays the client id as it was in the input
+    pub fn display(&self, client_id: ClientId) -> ZzClientIdDisplay<'_> {
+        ZzClientIdDisplay(self, client_id)
+    }
+
+    /// Writes every name for a checkpoint, see read
+    pub fn write(&self, w: &mut (impl Write + ?Sized)) -> io::Result<()> {
+        let names = self.0.read().unwrap();
+        write_u64(w, names.names.len() as u64)?;
+        for name in names.names.values() {
+            write_bytes(w, name.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// The bytes weren't written by write
+    pub fn read(&self, r: &mut (impl Read + ?Sized)) -> io::Result<()> {
+        for _ in 0..read_u64(r)? {
+            let name =
+                String::from_utf8(read_bytes(r)?).map_err(|err| invalid_data(err.to_string()))?;
+            self.intern(&name);
+        }
+
+        Ok(())
+    }
+}
+
+/// The same names, wherever they're kept
+impl PartialEq for ZzClientNames {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+            || self.0.read().unwrap().names == other.0.read().unwrap().names
+    }
+}
+
+/// Displays a client id as it was in the input, its name for string client ids. It's
+/// serialized the same
+pub struct ZzClientIdDisplay<'a>(&'a ZzClientNames, ClientId);
+
+impl std::fmt::Display for ZzClientIdDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.0.read().unwrap().names.get(&self.1) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.1),
+        }
+    }
+}
+
+impl Serialize for ZzClientIdDisplay<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(store: &mut impl ClientStore, client_ids: &[ClientId]) {
+        for client_id in client_ids.iter().rev() {
+            store.get_or_insert(*client_id).locked = true;
+        }
+        assert!(store.get(client_ids[0]).unwrap().locked);
+        assert!(store.get(client_ids[0] + 1).is_none());
+        store.get_mut(client_ids[0]).unwrap().locked = false;
+        assert!(!store.get_or_insert(client_ids[0]).locked);
+    }
+
+    #[test]
+    fn test_dense_store() {
+        let mut store = DenseClientStore::default();
+        exercise(&mut store, &[2, 7, 65535]);
+        assert!(store.get(1 << 20).is_none());
+
+        let client_ids: Vec<_> = store
+            .into_sorted(&Default::default())
+            .iter()
+            .map(|x| x.client_id)
+            .collect();
+        assert_eq!(client_ids, vec![2, 7, 65535]);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit in a u16")]
+    fn test_dense_store_wide_id() {
+        DenseClientStore::default().get_or_insert(1 << 20);
+    }
+
+    #[test]
+    fn test_sparse_store() {
+        let mut store = SparseClientStore::default();
+        exercise(&mut store, &[2, 1 << 40, u64::MAX]);
+
+        let client_ids: Vec<_> = store
+            .into_sorted(&Default::default())
+            .iter()
+            .map(|x| x.client_id)
+            .collect();
+        assert_eq!(client_ids, vec![2, 1 << 40, u64::MAX]);
+    }
+
+    #[test]
+    fn test_client_names() {
+        let client_names = ZzClientNames::default();
+        let bob = client_names.intern("bob");
+        let alice = client_names.intern("alice");
+        assert_eq!(client_names.intern("bob"), bob);
+        assert_eq!(ZzClientNames::id("bob"), bob);
+        assert_ne!(alice, bob);
+        assert_eq!(client_names.display(alice).to_string(), "alice");
+        assert_eq!(client_names.display(7).to_string(), "7");
+        assert!(client_names.check().is_ok());
+
+        // the names aren't shared with other engines
+        assert_eq!(ZzClientNames::default().get(bob), None);
+
+        // sorted by name, not id
+        let mut store = SparseClientStore::default();
+        store.get_or_insert(bob);
+        store.get_or_insert(alice);
+        let client_ids: Vec<_> = store
+            .into_sorted(&client_names)
+            .iter()
+            .map(|x| x.client_id)
+            .collect();
+        assert_eq!(client_ids, vec![alice, bob]);
+
+        let mut names = vec![];
+        client_names.write(&mut names).unwrap();
+        let read = ZzClientNames::default();
+        read.read(&mut names.as_slice()).unwrap();
+        assert_eq!(read, client_names);
+        assert_eq!(read.get(bob).as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn test_client_name_collision() {
+        let client_names = ZzClientNames::default();
+        // no two names are known to collide, so one is put in the place of the other
+        client_names
+            .0
+            .write()
+            .unwrap()
+            .names
+            .insert(ZzClientNames::id("bob"), "alice".into());
+
+        assert_eq!(client_names.intern("bob"), ZzClientNames::id("bob"));
+        let err = client_names.check().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Client bob has the same id as client alice"
+        );
+        assert_eq!(err.exit_code(), 6);
+    }
+}
@@ -0,0 +1,986 @@
+use std::io::{self, Read, Write};
+
+use roaring::RoaringBitmap;
+use strum::{EnumCount, IntoDiscriminant, IntoEnumIterator};
+
+use crate::{
+    ClientIdOptions, DuplicateTxOptions, ZzParseOptions, ZzPolicyOptions,
+    common::{
+        encoding::{
+            invalid_data, read_bytes, read_u8, read_u32, read_u64, write_bytes, write_u8,
+            write_u32, write_u64,
+        },
+        zz_amount::ZzUAmount,
+    },
+    domain::{
+        client_balance::{ClientId, ZzClientBalance},
+        client_store::{ClientStore, SparseClientStore, ZzClientNames},
+        journal::{
+            JournalEntryKind, JournalHash, JournalPosition, ZzJournalEntry, ZzJournalWriter,
+        },
+        observer::EngineObserver,
+        risk::{RiskRule, ZzRiskRules, evaluate_risk_rules, record_risk_outcome},
+        transaction::{
+            Outcome, RejectReason, TransactionMap, TxId, ZzTx, ZzTxEffect, ZzTxSerializeCsv,
+            ZzTxType, ZzTxTypeDiscriminants,
+        },
+    },
+    error::{ZzError, ZzResult},
+    parsers::{
+        csv_parser::{CsvParserResult, CsvZzTxParserTrait},
+        nom::CsvZzTxParserNomImpl,
+    },
+};
+
+/// What's left when the engine is done, see ZzEngine::finish
+pub struct ZzEngineOutput {
+    /// Sorted by client id, see ClientStore::into_sorted
+    pub client_balances: Vec<ZzClientBalance>,
+    /// The transactions that reused a tx id, only tracked if DuplicateTxOptions is reject or warn
+    pub duplicate_txs: Vec<ZzTx>,
+    /// The transactions that hold funds, see TransactionMap::holds
+    pub holds: Vec<(ClientId, TxId, ZzUAmount)>,
+    /// How many transactions were dropped from the transaction map, see TransactionMap::evicted
+    pub evicted: u64,
+    /// How many transactions were rejected for each reason, the reasons that didn't happen
+    /// aren't included. The resolves of ZzPolicyOptions::dispute_resolve_deadline are counted
+    pub rejected: Vec<(RejectReason, u64)>,
+    /// How many disputes were resolved by ZzPolicyOptions::dispute_resolve_deadline
+    pub auto_resolved: u64,
+    /// The hash of the last journal entry, if there's a journal
+    pub journal_head: Option<JournalHash>,
+    /// The names of the string client ids, to write the output
+    pub client_names: ZzClientNames,
+}
+
+/// Applies transactions to the client balances in the order they're given, following the
+/// policy. This is what the csv loop runs for every row, it can be embedded to process
+/// transactions that don't come from a file
+pub struct ZzEngine<M, C = SparseClientStore, O = ()> {
+    policy_options: ZzPolicyOptions,
+    tx_map: M,
+    clients: C,
+    client_names: ZzClientNames,
+    /// the ids seen with --on-duplicate-tx, a bitmap so it stays compact however many there are
+    tx_ids: RoaringBitmap,
+    duplicate_txs: Vec<ZzTx>,
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    /// the clock of the dispute windows, transactions are counted if they don't have a timestamp
+    applied: u64,
+    /// if the first transaction had a timestamp, the others must be the same if the policy uses
+    /// the clock (see ZzPolicyOptions::uses_clock)
+    timestamped: Option<bool>,
+    /// indexed by RejectReason
+    rejected: [u64; RejectReason::COUNT],
+    auto_resolved: u64,
+    journal: Option<ZzJournalWriter>,
+    observer: O,
+}
+
+impl<M: TransactionMap> ZzEngine<M> {
+    /// An engine that keeps the balances in a hashmap, with the risk rules of the policy
+    pub fn new(tx_map: M, policy_options: ZzPolicyOptions) -> Self {
+        Self::with_clients(tx_map, SparseClientStore::default(), policy_options)
+    }
+}
+
+impl<M: TransactionMap, C: ClientStore> ZzEngine<M, C> {
+    /// An engine that keeps the balances in the given store, with the risk rules of the policy
+    pub fn with_clients(tx_map: M, clients: C, policy_options: ZzPolicyOptions) -> Self {
+        let risk_rules = policy_options
+            .risk_rules
+            .as_ref()
+            .map(ZzRiskRules::build)
+            .unwrap_or_default();
+        let client_names = policy_options.client_names.fork();
+
+        Self {
+            policy_options,
+            tx_map,
+            clients,
+            client_names,
+            tx_ids: RoaringBitmap::new(),
+            duplicate_txs: vec![],
+            risk_rules,
+            applied: 0,
+            timestamped: None,
+            rejected: [0; RejectReason::COUNT],
+            auto_resolved: 0,
+            journal: None,
+            observer: (),
+        }
+    }
+}
+
+impl<M: TransactionMap, C: ClientStore, O: EngineObserver> ZzEngine<M, C, O> {
+    /// Calls the observer with the events of the transactions, see EngineObserver
+    pub fn with_observer<P: EngineObserver>(self, observer: P) -> ZzEngine<M, C, P> {
+        ZzEngine {
+            policy_options: self.policy_options,
+            tx_map: self.tx_map,
+            clients: self.clients,
+            client_names: self.client_names,
+            tx_ids: self.tx_ids,
+            duplicate_txs: self.duplicate_txs,
+            risk_rules: self.risk_rules,
+            applied: self.applied,
+            timestamped: self.timestamped,
+            rejected: self.rejected,
+            auto_resolved: self.auto_resolved,
+            journal: self.journal,
+            observer,
+        }
+    }
+
+    /// Evaluates the given risk rules instead of the ones configured in the policy
+    pub fn with_rules(mut self, risk_rules: Vec<Box<dyn RiskRule>>) -> Self {
+        self.risk_rules = risk_rules;
+        self
+    }
+
+    /// Records every effect in the journal, see ZzJournalEntry
+    pub fn with_journal(mut self, journal: ZzJournalWriter) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Applies the transaction, the journal and the observer events refer to the transaction by
+    /// how many were applied before it (starting at 1). Returns the effect on the client of the
+    /// transaction or why it was rejected
+    ///
+    /// # Errors
+    ///
+    /// A duplicate tx id with DuplicateTxOptions::Fail, a transaction with a timestamp after
+    /// one without it (or the other way around) if the policy uses the clock, failed to write
+    /// the journal or the transaction map failed to read or write its spill files
+    pub fn apply(&mut self, zztx: ZzTx) -> ZzResult<Outcome> {
+        self.apply_at_row(zztx, self.applied + 1)
+    }
+
+    /// Same as apply but the transaction is referred to by the given row, the csv loop gives
+    /// the row number in the file (the header included)
+    pub fn apply_at_row(&mut self, zztx: ZzTx, row: u64) -> ZzResult<Outcome> {
+        let outcome = self.apply_tx_at_row(zztx, row)?;
+        // the map may have lost a transaction, so nothing after it can be trusted
+        self.tx_map.take_error()?;
+        if let Outcome::Rejected(reason) = outcome {
+            self.rejected[reason as usize] += 1;
+        }
+        Ok(outcome)
+    }
+
+    fn apply_tx_at_row(&mut self, zztx: ZzTx, row: u64) -> ZzResult<Outcome> {
+        let policy_options = &self.policy_options;
+
+        // row counts and timestamps can't be compared, so the clock would stop making sense
+        let has_timestamp = zztx.timestamp.is_some();
+        if policy_options.uses_clock()
+            && *self.timestamped.get_or_insert(has_timestamp) != has_timestamp
+        {
+            let (has, earlier) = if has_timestamp {
+                ("has", "don't")
+            } else {
+                ("has no", "do")
+            };
+            return Err(ZzError::Policy(format!(
+                "Row {row} {has} timestamp and the earlier rows {earlier}, the dispute windows \
+                 and the eviction horizon can't mix rows and timestamps"
+            )));
+        }
+        self.applied += 1;
+
+        if policy_options.on_duplicate_tx != DuplicateTxOptions::Allow
+            && !zztx.r#type.references_tx()
+            && !self.tx_ids.insert(zztx.tx_id)
+        {
+            match policy_options.on_duplicate_tx {
+                DuplicateTxOptions::Fail => return Err(ZzError::duplicate_tx(zztx.tx_id)),
+                DuplicateTxOptions::Reject => {
+                    self.duplicate_txs.push(zztx);
+                    return Ok(Outcome::Rejected(RejectReason::DuplicateTx));
+                }
+                DuplicateTxOptions::Warn => self.duplicate_txs.push(zztx.clone()),
+                DuplicateTxOptions::Allow => unreachable!(),
+            }
+        }
+
+        let now = zztx.timestamp.unwrap_or(self.applied);
+
+        let resolves = self.tx_map.advance_clock(policy_options, now);
+        self.auto_resolve(resolves, row)?;
+        let policy_options = &self.policy_options;
+
+        let client_id = zztx.client_id;
+        let tx_id = zztx.tx_id;
+        let r#type = zztx.r#type.discriminant();
+        let was_locked = self
+            .clients
+            .get(client_id)
+            .is_some_and(|balance| balance.locked);
+        let (allowed, lock) = if self.risk_rules.is_empty() {
+            (true, false)
+        } else {
+            evaluate_risk_rules(
+                &mut self.risk_rules,
+                &zztx,
+                self.clients.get(client_id),
+                &self.tx_map.open_disputes(client_id),
+            )
+        };
+
+        // the rules are told what happened to the transaction
+        let evaluated = (!self.risk_rules.is_empty()).then(|| zztx.clone());
+        let outcome = if allowed {
+            apply_tx(
+                &mut self.tx_map,
+                &mut self.clients,
+                &self.client_names,
+                &mut self.journal,
+                &mut self.observer,
+                policy_options,
+                zztx,
+                row,
+            )?
+        } else {
+            Outcome::Rejected(RejectReason::RiskRule)
+        };
+        if let Some(zztx) = evaluated {
+            record_risk_outcome(&mut self.risk_rules, &zztx, &outcome);
+        }
+
+        // a rejected transaction doesn't lock the account
+        if lock && matches!(outcome, Outcome::Applied(_)) {
+            let balance = self.clients.get_or_insert(client_id);
+            let before = balance.clone();
+            balance.locked = true;
+
+            if let Some(journal) = &mut self.journal
+                && !before.locked
+            {
+                let entry = ZzJournalEntry::new(
+                    row,
+                    tx_id,
+                    r#type,
+                    JournalEntryKind::RiskLock,
+                    ZzUAmount::zero(),
+                    &before,
+                    balance,
+                    &self.client_names,
+                );
+                journal.write(entry)?;
+            }
+        }
+
+        if let Some(balance) = self.clients.get_mut(client_id) {
+            if !balance.locked {
+                balance.locked_by = None;
+            } else if !was_locked {
+                balance.locked_by = Some((tx_id, row));
+                self.observer.account_locked(balance, tx_id, row);
+            }
+        }
+
+        // the disputes that couldn't be resolved while the account was locked
+        if r#type == ZzTxTypeDiscriminants::Unlock && matches!(outcome, Outcome::Applied(_)) {
+            let resolves = self
+                .tx_map
+                .overdue_disputes(&self.policy_options, client_id);
+            self.auto_resolve(resolves, row)?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Applies the resolves of the disputes past ZzPolicyOptions::dispute_resolve_deadline, they
+    /// are counted as resolved or rejected. A resolve rejected because the account is locked is
+    /// given again by TransactionMap::overdue_disputes when the account is unlocked
+    fn auto_resolve(&mut self, resolves: Vec<ZzTx>, row: u64) -> io::Result<()> {
+        for resolve in resolves {
+            let outcome = apply_tx(
+                &mut self.tx_map,
+                &mut self.clients,
+                &self.client_names,
+                &mut self.journal,
+                &mut self.observer,
+                &self.policy_options,
+                resolve,
+                row,
+            )?;
+            match outcome {
+                Outcome::Applied(_) => self.auto_resolved += 1,
+                Outcome::Rejected(reason) => self.rejected[reason as usize] += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// The balance of the client, None if no transaction was applied to it
+    pub fn balance(&self, client_id: ClientId) -> Option<&ZzClientBalance> {
+        self.clients.get(client_id)
+    }
+
+    /// Every balance, in no particular order
+    pub fn balances(&self) -> impl Iterator<Item = &ZzClientBalance> {
+        self.clients.iter()
+    }
+
+    /// How many transactions were rejected for the reason so far
+    pub fn rejected(&self, reason: RejectReason) -> u64 {
+        self.rejected[reason as usize]
+    }
+
+    /// Writes the buffered journal entries to the file, returns its length and last hash if
+    /// there's a journal
+    pub fn flush_journal(&mut self) -> io::Result<Option<JournalPosition>> {
+        match &mut self.journal {
+            Some(journal) => Ok(Some((journal.flush()?, journal.head()))),
+            None => Ok(None),
+        }
+    }
+
+    /// The names of the string client ids, the parser interns them here
+    pub fn client_names(&self) -> &ZzClientNames {
+        &self.client_names
+    }
+
+    /// The observer given to with_observer
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Writes what the observer buffered, see EngineObserver::flush
+    pub fn flush_observer(&mut self) -> io::Result<()> {
+        self.observer.flush()
+    }
+
+    /// Flushes the journal and the observer and returns the balances sorted by client id and what else is
+    /// reported at the end
+    ///
+    /// # Errors
+    ///
+    /// Failed to write the journal or the transaction map failed to read its spill files
+    pub fn finish(mut self) -> io::Result<ZzEngineOutput> {
+        let journal_head = self.flush_journal()?.map(|(_, head)| head);
+        self.observer.flush()?;
+        let holds = self.tx_map.holds();
+        self.tx_map.take_error()?;
+
+        Ok(ZzEngineOutput {
+            holds,
+            evicted: self.tx_map.evicted(),
+            rejected: RejectReason::iter()
+                .map(|reason| (reason, self.rejected[reason as usize]))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            auto_resolved: self.auto_resolved,
+            client_balances: self.clients.into_sorted(&self.client_names),
+            duplicate_txs: self.duplicate_txs,
+            journal_head,
+            client_names: self.client_names,
+        })
+    }
+
+    /// Writes everything the engine carries from one transaction to the next, except the
+    /// journal, for a checkpoint
+    pub(crate) fn write_checkpoint(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        write_u64(w, self.applied)?;
+        write_u8(
+            w,
+            match self.timestamped {
+                None => 0,
+                Some(false) => 1,
+                Some(true) => 2,
+            },
+        )?;
+        for count in self.rejected {
+            write_u64(w, count)?;
+        }
+        write_u64(w, self.auto_resolved)?;
+
+        self.tx_map.write_checkpoint(w)?;
+        self.tx_map.take_error()?;
+
+        // the names of string client ids are needed to write the output
+        self.client_names.write(w)?;
+
+        let balances: Vec<_> = self.clients.iter().collect();
+        write_u32(w, balances.len() as u32)?;
+        for balance in balances {
+            balance.write(w)?;
+        }
+
+        let mut tx_ids = vec![];
+        self.tx_ids.serialize_into(&mut tx_ids)?;
+        write_bytes(w, &tx_ids)?;
+
+        // the duplicates are only reported, so they're kept as the csv rows they came from
+        write_u64(w, self.duplicate_txs.len() as u64)?;
+        for zztx in &self.duplicate_txs {
+            let row = ZzTxSerializeCsv(zztx, &self.client_names).to_string();
+            write_bytes(w, row.as_bytes())?;
+        }
+
+        write_u32(w, self.risk_rules.len() as u32)?;
+        for rule in &self.risk_rules {
+            let mut state = vec![];
+            rule.write_state(&mut state)?;
+            write_bytes(w, &state)?;
+        }
+
+        Ok(())
+    }
+
+    /// Restores what write_checkpoint wrote into a new engine, client_ids are the ones the
+    /// duplicates were parsed with
+    pub(crate) fn read_checkpoint(
+        &mut self,
+        client_ids: ClientIdOptions,
+        r: &mut dyn Read,
+    ) -> io::Result<()> {
+        self.applied = read_u64(r)?;
+        self.timestamped = match read_u8(r)? {
+            0 => None,
+            timestamped => Some(timestamped == 2),
+        };
+        for count in &mut self.rejected {
+            *count = read_u64(r)?;
+        }
+        self.auto_resolved = read_u64(r)?;
+
+        self.tx_map.read_checkpoint(r)?;
+
+        self.client_names.read(r)?;
+
+        for _ in 0..read_u32(r)? {
+            let balance = ZzClientBalance::read(r)?;
+            let client_id = balance.client_id;
+            *self.clients.get_or_insert(client_id) = balance;
+        }
+
+        self.tx_ids = RoaringBitmap::deserialize_from(read_bytes(r)?.as_slice())?;
+
+        let parse_options = ZzParseOptions {
+            zz_amount_max_size: u16::MAX - 1,
+            client_ids,
+            ..Default::default()
+        };
+        for _ in 0..read_u64(r)? {
+            let row =
+                String::from_utf8(read_bytes(r)?).map_err(|err| invalid_data(err.to_string()))?;
+            match CsvZzTxParserNomImpl.deserialize_row(&parse_options, &self.client_names, &row) {
+                CsvParserResult::Parsed(zztx) => self.duplicate_txs.push(zztx),
+                _ => return Err(invalid_data(format!("Invalid duplicate tx {row}"))),
+            }
+        }
+
+        if read_u32(r)? as usize != self.risk_rules.len() {
+            return Err(invalid_data(
+                "The checkpoint was written with other risk rules".to_string(),
+            ));
+        }
+        for rule in &mut self.risk_rules {
+            rule.read_state(&mut read_bytes(r)?.as_slice())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Inserts the transaction and applies its effects to the clients, each effect is recorded in
+/// the journal with the row of the transaction and the observer is told what it caused
+#[allow(clippy::too_many_arguments)]
+fn apply_tx(
+    tx_map: &mut impl TransactionMap,
+    clients: &mut impl ClientStore,
+    client_names: &ZzClientNames,
+    journal: &mut Option<ZzJournalWriter>,
+    observer: &mut impl EngineObserver,
+    policy_options: &ZzPolicyOptions,
+    zztx: ZzTx,
+    row: u64,
+) -> io::Result<Outcome> {
+    let client_id = zztx.client_id;
+    let tx_id = zztx.tx_id;
+    let r#type = zztx.r#type.discriminant();
+
+    if zztx.involves_fee_account(policy_options) {
+        return Ok(Outcome::Rejected(RejectReason::FeeAccount));
+    }
+
+    // a transfer or a fee is applied to both clients or neither, so the other client can't
+    // be locked
+    if zztx
+        .counterparties(policy_options)
+        .iter()
+        .any(|counterparty_id| clients.get(*counterparty_id).is_some_and(|x| x.locked))
+    {
+        return Ok(Outcome::Rejected(RejectReason::CounterpartyLocked));
+    }
+
+    if clients.get(client_id).is_some_and(|x| x.locked)
+        && !matches!(zztx.r#type, ZzTxType::Unlock)
+        && !(policy_options.settle_disputes_after_lock
+            && matches!(zztx.r#type, ZzTxType::Resolve | ZzTxType::Chargeback))
+    {
+        return Ok(Outcome::Rejected(RejectReason::AccountLocked));
+    }
+
+    // the amount is only kept for the observer
+    let withdrawal = match &zztx.r#type {
+        ZzTxType::Withdrawal(amount) if observer.is_enabled() => Some(amount.clone()),
+        _ => None,
+    };
+
+    let effect = match tx_map.insert_transaction(policy_options, zztx, clients.get(client_id)) {
+        Outcome::Applied(effect) => effect,
+        rejected => {
+            if let (Some(amount), Outcome::Rejected(RejectReason::InsufficientFunds)) =
+                (withdrawal, &rejected)
+            {
+                observer.withdrawal_rejected(
+                    client_id,
+                    clients.get(client_id),
+                    tx_id,
+                    &amount,
+                    row,
+                );
+            }
+            return Ok(rejected);
+        }
+    };
+
+    // the total is kept up to date so the balances can be read between transactions
+    let mut apply_effect = |client_id: ClientId, kind: JournalEntryKind, effect: &ZzTxEffect| {
+        let balance = clients.get_or_insert(client_id);
+        let was_negative = observer.is_enabled() && balance.is_negative();
+        let before = journal.is_some().then(|| balance.clone());
+
+        balance.process_tx_effect(r#type, effect);
+        balance.compute_total();
+
+        if observer.is_enabled() && !was_negative && balance.is_negative() {
+            observer.balance_went_negative(balance, tx_id, row);
+        }
+
+        match (journal.as_mut(), before) {
+            (Some(journal), Some(before)) => {
+                let amount = effect.amount.clone();
+                let entry = ZzJournalEntry::new(
+                    row,
+                    tx_id,
+                    r#type,
+                    kind,
+                    amount,
+                    &before,
+                    balance,
+                    client_names,
+                );
+                journal.write(entry)
+            }
+            _ => Ok(()),
+        }
+    };
+
+    // the destination of a transfer comes before the fee account
+    let has_counterparty = effect.counterparty.is_some();
+    let side_effects = effect.side_effects();
+
+    apply_effect(client_id, JournalEntryKind::Tx, &effect)?;
+
+    for (i, (counterparty_id, side_effect)) in side_effects.into_iter().enumerate() {
+        let kind = if i == 0 && has_counterparty {
+            JournalEntryKind::Counterparty
+        } else {
+            JournalEntryKind::Fee
+        };
+        apply_effect(counterparty_id, kind, &side_effect)?;
+    }
+
+    if r#type == ZzTxTypeDiscriminants::Dispute {
+        observer.dispute_opened(client_id, tx_id, &effect.amount, row);
+    }
+
+    Ok(Outcome::Applied(effect))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches::assert_matches;
+
+    use super::*;
+    use crate::domain::transaction::TransactionHashMapImpl;
+    use crate::{
+        common::zz_amount::ZzIAmount,
+        domain::{fee::ZzFeeSchedule, observer::ZzEngineEvent},
+    };
+
+    fn make_uamount(val: u64) -> ZzUAmount {
+        ZzUAmount::new(val.into(), 0).unwrap()
+    }
+
+    fn make_tx(r#type: ZzTxType, client_id: ClientId, tx_id: TxId) -> ZzTx {
+        ZzTx {
+            r#type,
+            client_id,
+            tx_id,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), Default::default());
+
+        let effect = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 1))
+            .unwrap()
+            .applied()
+            .unwrap();
+        assert_eq!(effect.available, Some(true));
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Withdrawal(make_uamount(20)), 1, 2))
+            .unwrap();
+        assert_eq!(outcome, Outcome::Rejected(RejectReason::InsufficientFunds));
+        let effect = engine
+            .apply(make_tx(ZzTxType::Dispute(None), 1, 1))
+            .unwrap()
+            .applied()
+            .unwrap();
+        assert_eq!(effect.held, Some(true));
+
+        // the total is kept up to date between transactions
+        let balance = engine.balance(1).unwrap();
+        assert_eq!(balance.held.to_string(), "10");
+        assert_eq!(balance.total.to_string(), "10");
+        assert!(engine.balance(2).is_none());
+
+        let outcome = engine.apply(make_tx(ZzTxType::Chargeback, 1, 1)).unwrap();
+        assert!(outcome.applied().is_some());
+        assert_eq!(engine.balance(1).unwrap().locked_by, Some((1, 4)));
+
+        // nothing but an unlock is applied to a locked account, nor to the other side of a
+        // transfer
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 3))
+            .unwrap();
+        assert_eq!(outcome, Outcome::Rejected(RejectReason::AccountLocked));
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(5)), 2, 4))
+            .unwrap();
+        assert!(outcome.applied().is_some());
+        let transfer = ZzTxType::Transfer {
+            to: 1,
+            amount: make_uamount(1),
+        };
+        let outcome = engine.apply(make_tx(transfer, 2, 5)).unwrap();
+        assert_eq!(outcome, Outcome::Rejected(RejectReason::CounterpartyLocked));
+
+        assert_eq!(engine.balances().count(), 2);
+        assert_eq!(engine.rejected(RejectReason::AccountLocked), 1);
+
+        let output = engine.finish().unwrap();
+        let clients: Vec<_> = output
+            .client_balances
+            .iter()
+            .map(|balance| (balance.client_id, balance.total.to_string()))
+            .collect();
+        assert_eq!(clients, vec![(1, "0".to_string()), (2, "5".to_string())]);
+        assert!(output.holds.is_empty());
+        assert!(output.journal_head.is_none());
+        assert_eq!(
+            output.rejected,
+            vec![
+                (RejectReason::InsufficientFunds, 1),
+                (RejectReason::AccountLocked, 1),
+                (RejectReason::CounterpartyLocked, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_duplicate() {
+        let mut engine = ZzEngine::new(
+            TransactionHashMapImpl::default(),
+            ZzPolicyOptions {
+                on_duplicate_tx: DuplicateTxOptions::Reject,
+                ..Default::default()
+            },
+        );
+
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 1))
+            .unwrap();
+        assert!(outcome.applied().is_some());
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 2, 1))
+            .unwrap();
+        assert_eq!(outcome, Outcome::Rejected(RejectReason::DuplicateTx));
+
+        let output = engine.finish().unwrap();
+        assert_eq!(output.duplicate_txs.len(), 1);
+        assert_eq!(output.client_balances.len(), 1);
+        assert_eq!(output.rejected, vec![(RejectReason::DuplicateTx, 1)]);
+
+        // the seen ids are kept by the checkpoint
+        let policy_options = ZzPolicyOptions {
+            on_duplicate_tx: DuplicateTxOptions::Reject,
+            ..Default::default()
+        };
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), policy_options.clone());
+        for tx_id in [0, 1 << 16, TxId::MAX] {
+            engine
+                .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, tx_id))
+                .unwrap();
+        }
+        let mut checkpoint = vec![];
+        engine.write_checkpoint(&mut checkpoint).unwrap();
+        let mut resumed = ZzEngine::new(TransactionHashMapImpl::default(), policy_options);
+        resumed
+            .read_checkpoint(ClientIdOptions::U16, &mut checkpoint.as_slice())
+            .unwrap();
+        for tx_id in [0, 1 << 16, TxId::MAX] {
+            let outcome = resumed
+                .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 2, tx_id))
+                .unwrap();
+            assert_eq!(outcome, Outcome::Rejected(RejectReason::DuplicateTx));
+        }
+        let outcome = resumed
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 2, 2))
+            .unwrap();
+        assert!(outcome.applied().is_some());
+
+        let mut engine = ZzEngine::new(
+            TransactionHashMapImpl::default(),
+            ZzPolicyOptions {
+                on_duplicate_tx: DuplicateTxOptions::Fail,
+                ..Default::default()
+            },
+        );
+        engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 1))
+            .unwrap();
+        let err = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 2, 1))
+            .unwrap_err();
+        assert_matches!(err, ZzError::Policy(_));
+        assert_eq!(err.to_string(), "Duplicate tx id 1");
+    }
+
+    #[test]
+    fn test_risk_lock() {
+        let rules =
+            ZzRiskRules::from_reader("rule,limit,window\ndispute-velocity,2,100\n".as_bytes())
+                .unwrap();
+        let mut engine = ZzEngine::new(
+            TransactionHashMapImpl::default(),
+            ZzPolicyOptions {
+                risk_rules: Some(rules),
+                ..Default::default()
+            },
+        );
+
+        // disputes of unknown transactions are partner mistakes, they aren't counted and don't
+        // lock the account
+        engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 1))
+            .unwrap();
+        for tx_id in [99, 98] {
+            let outcome = engine
+                .apply(make_tx(ZzTxType::Dispute(None), 1, tx_id))
+                .unwrap();
+            assert_eq!(outcome, Outcome::Rejected(RejectReason::UnknownTx));
+        }
+        assert!(!engine.balance(1).unwrap().locked);
+
+        engine
+            .apply(make_tx(ZzTxType::Dispute(None), 1, 1))
+            .unwrap();
+        engine.apply(make_tx(ZzTxType::Resolve, 1, 1)).unwrap();
+        assert!(!engine.balance(1).unwrap().locked);
+        engine
+            .apply(make_tx(ZzTxType::Dispute(None), 1, 1))
+            .unwrap();
+        assert_eq!(engine.balance(1).unwrap().locked_by, Some((1, 6)));
+    }
+
+    #[test]
+    fn test_mixed_clock() {
+        let deposit = |tx_id, timestamp| ZzTx {
+            timestamp,
+            ..make_tx(ZzTxType::Deposit(make_uamount(1)), 1, tx_id)
+        };
+
+        // only the windows use the clock
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), Default::default());
+        engine.apply(deposit(1, Some(100))).unwrap();
+        engine.apply(deposit(2, None)).unwrap();
+
+        let policy_options = ZzPolicyOptions {
+            dispute_window: Some(10),
+            ..Default::default()
+        };
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), policy_options.clone());
+        engine.apply(deposit(1, Some(100))).unwrap();
+        let err = engine.apply(deposit(2, None)).unwrap_err();
+        assert_matches!(err, ZzError::Policy(_));
+        assert_eq!(
+            err.to_string(),
+            "Row 2 has no timestamp and the earlier rows do, the dispute windows and the \
+             eviction horizon can't mix rows and timestamps"
+        );
+
+        // it's kept by the checkpoint
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), policy_options.clone());
+        engine.apply(deposit(1, None)).unwrap();
+        let mut checkpoint = vec![];
+        engine.write_checkpoint(&mut checkpoint).unwrap();
+        let mut resumed = ZzEngine::new(TransactionHashMapImpl::default(), policy_options);
+        resumed
+            .read_checkpoint(ClientIdOptions::U16, &mut checkpoint.as_slice())
+            .unwrap();
+        assert_matches!(
+            resumed.apply(deposit(2, Some(100))),
+            Err(ZzError::Policy(_))
+        );
+    }
+
+    #[test]
+    fn test_fee_account() {
+        let schedule = ZzFeeSchedule::from_reader(
+            "type,client,kind,value\nwithdrawal,,flat,1\n".as_bytes(),
+            ClientIdOptions::U16,
+            &ZzClientNames::default(),
+        )
+        .unwrap();
+        let mut engine = ZzEngine::new(
+            TransactionHashMapImpl::default(),
+            ZzPolicyOptions {
+                fee_schedule: Some(schedule),
+                fee_account: Some(0),
+                ..Default::default()
+            },
+        );
+
+        // the fee account only collects fees
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 0, 1))
+            .unwrap();
+        assert_eq!(outcome, Outcome::Rejected(RejectReason::FeeAccount));
+        engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 2))
+            .unwrap();
+        let transfer = ZzTxType::Transfer {
+            to: 0,
+            amount: make_uamount(1),
+        };
+        let outcome = engine.apply(make_tx(transfer, 1, 3)).unwrap();
+        assert_eq!(outcome, Outcome::Rejected(RejectReason::FeeAccount));
+
+        let outcome = engine
+            .apply(make_tx(ZzTxType::Withdrawal(make_uamount(5)), 1, 4))
+            .unwrap();
+        assert!(outcome.applied().is_some());
+        assert!(
+            engine
+                .balance(0)
+                .is_some_and(|x| x.available.to_string() == "1")
+        );
+        assert!(
+            engine
+                .balance(1)
+                .is_some_and(|x| x.available.to_string() == "4")
+        );
+    }
+
+    #[test]
+    fn test_observer() {
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), Default::default())
+            .with_observer(Vec::<ZzEngineEvent>::new());
+
+        for zztx in [
+            make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 1),
+            make_tx(ZzTxType::Withdrawal(make_uamount(20)), 1, 2),
+            make_tx(ZzTxType::Dispute(None), 1, 1),
+            make_tx(ZzTxType::Chargeback, 1, 1),
+            make_tx(ZzTxType::Deposit(make_uamount(5)), 2, 3),
+            make_tx(ZzTxType::Withdrawal(make_uamount(5)), 2, 4),
+            make_tx(ZzTxType::Dispute(None), 2, 3),
+            // it already owes money
+            make_tx(ZzTxType::Withdrawal(make_uamount(1)), 2, 5),
+        ] {
+            engine.apply(zztx).unwrap();
+        }
+
+        let mut minus_five = ZzIAmount::zero();
+        minus_five.sub(&make_uamount(5).to_i_amount());
+        assert_eq!(
+            engine.observer(),
+            &vec![
+                ZzEngineEvent::WithdrawalRejected {
+                    client: 1,
+                    tx: 2,
+                    row: 2,
+                    amount: make_uamount(20),
+                    available: make_uamount(10).to_i_amount(),
+                },
+                ZzEngineEvent::DisputeOpened {
+                    client: 1,
+                    tx: 1,
+                    row: 3,
+                    amount: make_uamount(10),
+                },
+                ZzEngineEvent::AccountLocked {
+                    client: 1,
+                    tx: 1,
+                    row: 4,
+                },
+                ZzEngineEvent::BalanceWentNegative {
+                    client: 2,
+                    tx: 3,
+                    row: 7,
+                    available: minus_five.clone(),
+                    receivable: ZzIAmount::zero(),
+                },
+                ZzEngineEvent::DisputeOpened {
+                    client: 2,
+                    tx: 3,
+                    row: 7,
+                    amount: make_uamount(5),
+                },
+                ZzEngineEvent::WithdrawalRejected {
+                    client: 2,
+                    tx: 5,
+                    row: 8,
+                    amount: make_uamount(1),
+                    available: minus_five.clone(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut engine = ZzEngine::new(TransactionHashMapImpl::default(), Default::default());
+        engine
+            .apply(make_tx(ZzTxType::Deposit(make_uamount(10)), 1, 1))
+            .unwrap();
+        engine.apply(make_tx(ZzTxType::Resolve, 1, 1)).unwrap();
+
+        let mut checkpoint = vec![];
+        engine.write_checkpoint(&mut checkpoint).unwrap();
+
+        let mut resumed = ZzEngine::new(TransactionHashMapImpl::default(), Default::default());
+        resumed
+            .read_checkpoint(ClientIdOptions::U16, &mut checkpoint.as_slice())
+            .unwrap();
+        assert_eq!(resumed.balance(1), engine.balance(1));
+        assert_eq!(resumed.rejected(RejectReason::NotDisputed), 1);
+
+        // the deposit is still there to be disputed
+        let outcome = resumed
+            .apply(make_tx(ZzTxType::Dispute(None), 1, 1))
+            .unwrap();
+        assert!(outcome.applied().is_some());
+        assert_eq!(resumed.balance(1).unwrap().held.to_string(), "10");
+    }
+}
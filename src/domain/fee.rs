@@ -0,0 +1,268 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    ClientIdOptions, RoundingOptions, ZzParseOptions,
+    common::zz_amount::ZzUAmount,
+    domain::{
+        client_balance::ClientId, client_store::ZzClientNames, transaction::ZzTxTypeDiscriminants,
+    },
+    parsers::nom::zz_amount::parse_zzamount_u,
+};
+
+/// The transaction types that can be charged
+const CHARGEABLE_TYPES: [ZzTxTypeDiscriminants; 4] = [
+    ZzTxTypeDiscriminants::Deposit,
+    ZzTxTypeDiscriminants::Withdrawal,
+    ZzTxTypeDiscriminants::Transfer,
+    ZzTxTypeDiscriminants::Chargeback,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZzFeeKind {
+    /// The value is charged as is
+    Flat,
+    /// The value is the percentage of the transaction amount that is charged
+    Percentage,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZzFee {
+    pub kind: ZzFeeKind,
+    pub value: ZzUAmount,
+}
+
+impl ZzFee {
+    pub fn compute(&self, amount: &ZzUAmount, rounding: RoundingOptions) -> ZzUAmount {
+        match self.kind {
+            ZzFeeKind::Flat => self.value.clone(),
+            ZzFeeKind::Percentage => amount.percentage(&self.value, rounding),
+        }
+    }
+}
+
+/// The fees charged per transaction type, a client may have its own fee for a type which
+/// overrides the default one
+#[derive(Debug, Clone, Default)]
+pub struct ZzFeeSchedule {
+    by_type: HashMap<ZzTxTypeDiscriminants, ZzFee>,
+    by_client: HashMap<(ClientId, ZzTxTypeDiscriminants), ZzFee>,
+}
+
+/// A row of the fee schedule csv, an empty client means the fee applies to every client
+#[derive(Deserialize)]
+struct ZzFeeRow {
+    r#type: ZzTxTypeDiscriminants,
+    client: Option<String>,
+    kind: ZzFeeKind,
+    value: String,
+}
+
+impl ZzFeeSchedule {
+    /// Loads a csv with the headers type,client,kind,value. The clients are parsed as the client
+    /// column of the input, string client ids are interned in client_names
+    ///
+    /// # Errors
+    ///
+    /// Failed to read or parse the file
+    pub fn from_path(
+        path: &Path,
+        client_ids: ClientIdOptions,
+        client_names: &ZzClientNames,
+    ) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::from_reader(file, client_ids, client_names)
+    }
+
+    /// # Errors
+    ///
+    /// Failed to read or parse the csv
+    pub fn from_reader<R: std::io::Read>(
+        reader: R,
+        client_ids: ClientIdOptions,
+        client_names: &ZzClientNames,
+    ) -> Result<Self, String> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut schedule = Self::default();
+
+        for row in rdr.deserialize::<ZzFeeRow>() {
+            let row = row.map_err(|err| err.to_string())?;
+
+            if !CHARGEABLE_TYPES.contains(&row.r#type) {
+                return Err(format!("Fees can't be charged on {}", row.r#type));
+            }
+
+            let value = match parse_zzamount_u(&ZzParseOptions::default(), &row.value) {
+                Ok(("", value)) => value,
+                _ => return Err(format!("Invalid fee value: {}", row.value)),
+            };
+
+            let client_id = match row.client {
+                Some(client) => Some(
+                    client_ids
+                        .parse(&client, client_names)
+                        .ok_or_else(|| format!("Invalid fee client: {client}"))?,
+                ),
+                None => None,
+            };
+
+            schedule.insert(
+                client_id,
+                row.r#type,
+                ZzFee {
+                    kind: row.kind,
+                    value,
+                },
+            );
+        }
+
+        Ok(schedule)
+    }
+
+    pub fn insert(
+        &mut self,
+        client_id: Option<ClientId>,
+        r#type: ZzTxTypeDiscriminants,
+        fee: ZzFee,
+    ) {
+        match client_id {
+            Some(client_id) => self.by_client.insert((client_id, r#type), fee),
+            None => self.by_type.insert(r#type, fee),
+        };
+    }
+
+    /// If the client may be charged when doing this type of transaction, a zero override means
+    /// it isn't
+    pub fn charges(&self, client_id: ClientId, r#type: ZzTxTypeDiscriminants) -> bool {
+        self.get(client_id, r#type)
+            .is_some_and(|fee| !fee.value.is_zero())
+    }
+
+    /// The fee the client is charged for the transaction, None if there's nothing to charge
+    pub fn fee(
+        &self,
+        client_id: ClientId,
+        r#type: ZzTxTypeDiscriminants,
+        amount: &ZzUAmount,
+        rounding: RoundingOptions,
+    ) -> Option<ZzUAmount> {
+        self.get(client_id, r#type)
+            .map(|fee| fee.compute(amount, rounding))
+            .filter(|fee| !fee.is_zero())
+    }
+
+    /// The fees as type,client,kind,value rows in a stable order, so schedules can be compared
+    pub fn rows(&self) -> Vec<String> {
+        let mut rows: Vec<String> = self
+            .by_type
+            .iter()
+            .map(|(r#type, fee)| format!("{type},,{:?},{:?}", fee.kind, fee.value))
+            .chain(self.by_client.iter().map(|((client_id, r#type), fee)| {
+                format!("{type},{client_id},{:?},{:?}", fee.kind, fee.value)
+            }))
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    /// The fee of the client for the type, its override or the default one
+    fn get(&self, client_id: ClientId, r#type: ZzTxTypeDiscriminants) -> Option<&ZzFee> {
+        self.by_client
+            .get(&(client_id, r#type))
+            .or_else(|| self.by_type.get(&r#type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_amount(val: u64) -> ZzUAmount {
+        ZzUAmount::new(val.into(), 0).unwrap()
+    }
+
+    #[test]
+    fn test_fee_schedule_from_reader() {
+        let schedule = ZzFeeSchedule::from_reader(
+            "type, client, kind, value\n\
+             withdrawal, , percentage, 1.5\n\
+             chargeback, , flat, 15\n\
+             withdrawal, 7, flat, 0\n\
+             withdrawal, 8, flat, 1\n"
+                .as_bytes(),
+            ClientIdOptions::U16,
+            &ZzClientNames::default(),
+        )
+        .unwrap();
+
+        let amount = make_amount(200);
+        let fee =
+            |client_id, r#type| schedule.fee(client_id, r#type, &amount, RoundingOptions::Down);
+
+        assert_eq!(
+            fee(1, ZzTxTypeDiscriminants::Withdrawal),
+            Some(make_amount(3))
+        );
+        assert_eq!(
+            fee(1, ZzTxTypeDiscriminants::Chargeback),
+            Some(make_amount(15))
+        );
+        assert_eq!(fee(1, ZzTxTypeDiscriminants::Deposit), None);
+        // overrides
+        assert_eq!(fee(7, ZzTxTypeDiscriminants::Withdrawal), None);
+        assert_eq!(
+            fee(8, ZzTxTypeDiscriminants::Withdrawal),
+            Some(make_amount(1))
+        );
+        assert_eq!(
+            fee(8, ZzTxTypeDiscriminants::Chargeback),
+            Some(make_amount(15))
+        );
+
+        assert!(schedule.charges(8, ZzTxTypeDiscriminants::Withdrawal));
+        assert!(schedule.charges(1, ZzTxTypeDiscriminants::Withdrawal));
+        assert!(!schedule.charges(7, ZzTxTypeDiscriminants::Withdrawal));
+        assert!(!schedule.charges(7, ZzTxTypeDiscriminants::Deposit));
+    }
+
+    #[test]
+    fn test_fee_schedule_string_clients() {
+        let client_names = ZzClientNames::default();
+        let schedule = ZzFeeSchedule::from_reader(
+            "type,client,kind,value\nwithdrawal,,flat,1\nwithdrawal,alice,flat,0\n".as_bytes(),
+            ClientIdOptions::String,
+            &client_names,
+        )
+        .unwrap();
+
+        let alice = ZzClientNames::id("alice");
+        assert_eq!(client_names.get(alice).as_deref(), Some("alice"));
+        assert!(!schedule.charges(alice, ZzTxTypeDiscriminants::Withdrawal));
+        assert!(schedule.charges(ZzClientNames::id("bob"), ZzTxTypeDiscriminants::Withdrawal));
+    }
+
+    #[test]
+    fn test_fee_schedule_invalid() {
+        for invalid in [
+            "type,client,kind,value\ndispute,,flat,1\n",
+            "type,client,kind,value\ndeposit,,flat,-1\n",
+            "type,client,kind,value\ndeposit,,other,1\n",
+            "type,client,kind,value\ndeposit,alice,flat,1\n",
+            "type,client,kind,value\ndeposit,70000,flat,1\n",
+        ] {
+            assert!(
+                ZzFeeSchedule::from_reader(
+                    invalid.as_bytes(),
+                    ClientIdOptions::U16,
+                    &ZzClientNames::default()
+                )
+                .is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    ClientIdOptions, JournalFormatOptions, ZzParseOptions,
+    common::zz_amount::{ZzIAmount, ZzUAmount},
+    domain::{
+        client_balance::ZzClientBalance,
+        client_store::{ClientStore, SparseClientStore, ZzClientNames},
+        transaction::{TxId, ZzTxTypeDiscriminants},
+    },
+    parsers::nom::zz_amount::{parse_zzamount_i, parse_zzamount_u},
+};
+
+/// Why a journal entry changed the balance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JournalEntryKind {
+    /// The effect of the transaction on its client
+    Tx,
+    /// The destination of a transfer
+    Counterparty,
+    /// The fee account, credited with the fee
+    Fee,
+    /// A risk rule locked the account after the transaction
+    RiskLock,
+}
+
+serde_plain::derive_display_from_serialize!(JournalEntryKind);
+
+/// A SHA-256 of the journal, see ZzJournalEntry::chain
+pub type JournalHash = [u8; 32];
+
+/// What the first entry is chained to
+pub const GENESIS_HASH: JournalHash = [0; 32];
+
+/// The length of the journal file and its last hash, what's needed to append to it
+pub type JournalPosition = (u64, JournalHash);
+
+/// A change to a client's balance and the balance that resulted from it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZzJournalEntry {
+    /// The row number in the input (the header included) of the transaction, automatic
+    /// resolves have the row that crossed the deadline
+    pub row: u64,
+    pub tx: TxId,
+    pub r#type: ZzTxTypeDiscriminants,
+    pub kind: JournalEntryKind,
+    /// The client as it was in the input, the name for string client ids
+    pub client: String,
+    /// The amount of the effect, the fee for JournalEntryKind::Fee
+    #[serde(deserialize_with = "deserialize_u_amount")]
+    pub amount: ZzUAmount,
+    /// How much available changed
+    #[serde(deserialize_with = "deserialize_i_amount")]
+    pub available: ZzIAmount,
+    /// How much held changed
+    #[serde(deserialize_with = "deserialize_i_amount")]
+    pub held: ZzIAmount,
+    /// Some(true) -> locked, Some(false) -> unlocked, None -> unchanged
+    pub locked: Option<bool>,
+    #[serde(deserialize_with = "deserialize_i_amount")]
+    pub balance_available: ZzIAmount,
+    #[serde(deserialize_with = "deserialize_i_amount")]
+    pub balance_held: ZzIAmount,
+    #[serde(deserialize_with = "deserialize_i_amount")]
+    pub balance_total: ZzIAmount,
+    pub balance_locked: bool,
+    /// The hex SHA-256 of the previous entry's hash and this entry, see ZzJournalEntry::chain.
+    /// It's set by the writer
+    #[serde(default)]
+    pub hash: String,
+}
+
+impl ZzJournalEntry {
+    /// The entry of a change from before to after, after must have its total computed
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        row: u64,
+        tx: TxId,
+        r#type: ZzTxTypeDiscriminants,
+        kind: JournalEntryKind,
+        amount: ZzUAmount,
+        before: &ZzClientBalance,
+        after: &ZzClientBalance,
+        client_names: &ZzClientNames,
+    ) -> Self {
+        let mut available = after.available.clone();
+        available.sub(&before.available);
+        let mut held = after.held.clone();
+        held.sub(&before.held);
+
+        Self {
+            row,
+            tx,
+            r#type,
+            kind,
+            client: client_names.display(after.client_id).to_string(),
+            amount,
+            available,
+            held,
+            locked: (before.locked != after.locked).then_some(after.locked),
+            balance_available: after.available.clone(),
+            balance_held: after.held.clone(),
+            balance_total: after.total.clone(),
+            balance_locked: after.locked,
+            hash: String::new(),
+        }
+    }
+
+    /// The hash of the entry chained to the previous one, the SHA-256 of the previous hash and
+    /// the fields of the entry (the hash excluded) as a csv row. Editing, removing or reordering
+    /// entries changes the hash of every entry after them
+    pub fn chain(&self, previous: &JournalHash) -> JournalHash {
+        let mut hasher = Sha256::new();
+        hasher.update(previous);
+        hasher.update(self.fields().as_bytes());
+        hasher.finalize().into()
+    }
+
+    /// The fields of the entry, the hash excluded, as a csv row
+    fn fields(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.row,
+            self.tx,
+            self.r#type,
+            self.kind,
+            self.client,
+            self.amount,
+            self.available,
+            self.held,
+            self.locked
+                .map_or(String::new(), |locked| locked.to_string()),
+            self.balance_available,
+            self.balance_held,
+            self.balance_total,
+            self.balance_locked,
+        )
+    }
+}
+
+/// What the signature of a run covers: the SHA-256 of the last journal hash and the balance
+/// sheet as it was written
+pub fn sheet_digest(head: &JournalHash, sheet: &[u8]) -> JournalHash {
+    let mut hasher = Sha256::new();
+    hasher.update(head);
+    hasher.update(sheet);
+    hasher.finalize().into()
+}
+
+/// Appends the entries to the journal file, chaining each one to the previous
+pub struct ZzJournalWriter {
+    sink: JournalSink,
+    head: JournalHash,
+}
+
+enum JournalSink {
+    Csv(Box<csv::Writer<File>>),
+    Jsonl(BufWriter<File>),
+}
+
+impl ZzJournalWriter {
+    /// Opens the journal keeping its first len bytes, the entries are appended after them and
+    /// chained to head. These are what a checkpoint recorded (see flush and head), a new
+    /// journal has a len of 0 and GENESIS_HASH
+    ///
+    /// # Errors
+    ///
+    /// Failed to open or truncate the file
+    pub fn open(
+        path: &Path,
+        format: JournalFormatOptions,
+        len: u64,
+        head: JournalHash,
+    ) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let sink = match format {
+            JournalFormatOptions::Csv => JournalSink::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(len == 0)
+                    .from_writer(file),
+            )),
+            JournalFormatOptions::Jsonl => JournalSink::Jsonl(BufWriter::new(file)),
+        };
+
+        Ok(Self { sink, head })
+    }
+
+    /// The hash of the last entry written
+    pub fn head(&self) -> JournalHash {
+        self.head
+    }
+
+    /// Sets the hash of the entry and writes it
+    ///
+    /// # Errors
+    ///
+    /// Failed to write the entry
+    pub fn write(&mut self, mut entry: ZzJournalEntry) -> io::Result<()> {
+        self.head = entry.chain(&self.head);
+        entry.hash = hex::encode(self.head);
+
+        match &mut self.sink {
+            JournalSink::Csv(wtr) => wtr.serialize(&entry).map_err(io::Error::other),
+            JournalSink::Jsonl(w) => {
+                serde_json::to_writer(&mut *w, &entry)?;
+                w.write_all(b"\n")
+            }
+        }
+    }
+
+    /// Flushes the entries to the file and returns its length
+    ///
+    /// # Errors
+    ///
+    /// Failed to write the entries
+    pub fn flush(&mut self) -> io::Result<u64> {
+        let mut file = match &mut self.sink {
+            JournalSink::Csv(wtr) => {
+                wtr.flush()?;
+                wtr.get_ref()
+            }
+            JournalSink::Jsonl(w) => {
+                w.flush()?;
+                w.get_ref()
+            }
+        };
+        file.sync_data()?;
+        file.stream_position()
+    }
+}
+
+/// Reads the entries of a journal in order
+///
+/// # Errors
+///
+/// Failed to open the file, reading each entry fails if it isn't valid
+pub fn read_journal(
+    path: &Path,
+    format: JournalFormatOptions,
+) -> io::Result<Box<dyn Iterator<Item = Result<ZzJournalEntry, String>>>> {
+    let file = File::open(path)?;
+
+    Ok(match format {
+        JournalFormatOptions::Csv => Box::new(
+            csv::Reader::from_reader(file)
+                .into_deserialize()
+                .map(|entry| entry.map_err(|err| err.to_string())),
+        ),
+        JournalFormatOptions::Jsonl => Box::new(BufReader::new(file).lines().map(|line| {
+            let line = line.map_err(|err| err.to_string())?;
+            serde_json::from_str(&line).map_err(|err| format!("{err}: {line}"))
+        })),
+    })
+}
+
+/// Rebuilds the balances from the changes in the journal alone and checks that each one
+/// matches the balance the entry recorded and that the hashes are chained. The balances are
+/// sorted like the balance sheet, they're returned with the names of the string client ids
+///
+/// # Errors
+///
+/// An entry isn't valid, isn't chained to the previous one or the rebuilt balance diverges
+/// from the recorded one
+pub fn replay_journal(
+    entries: impl Iterator<Item = Result<ZzJournalEntry, String>>,
+) -> Result<(Vec<ZzClientBalance>, ZzClientNames), String> {
+    let mut clients = SparseClientStore::default();
+    let client_names = ZzClientNames::default();
+    let mut head = GENESIS_HASH;
+
+    for entry in entries {
+        let entry = entry?;
+
+        head = entry.chain(&head);
+        if entry.hash != hex::encode(head) {
+            return Err(format!(
+                "Row {} tx {} client {}: the hash isn't chained to the previous entry",
+                entry.row, entry.tx, entry.client,
+            ));
+        }
+
+        // numbers are the id and anything else a string client id
+        let client_id = ClientIdOptions::U64
+            .parse(&entry.client, &client_names)
+            .or_else(|| ClientIdOptions::String.parse(&entry.client, &client_names))
+            .ok_or_else(|| format!("Row {} tx {}: empty client id", entry.row, entry.tx))?;
+        client_names.check().map_err(|err| err.to_string())?;
+
+        let balance = clients.get_or_insert(client_id);
+        balance.available.add(&entry.available);
+        balance.held.add(&entry.held);
+        if let Some(locked) = entry.locked {
+            balance.locked = locked;
+        }
+        balance.compute_total();
+
+        if (
+            &balance.available,
+            &balance.held,
+            &balance.total,
+            balance.locked,
+        ) != (
+            &entry.balance_available,
+            &entry.balance_held,
+            &entry.balance_total,
+            entry.balance_locked,
+        ) {
+            return Err(format!(
+                "Row {} tx {} client {}: replayed {},{},{},{} but the journal has {},{},{},{}",
+                entry.row,
+                entry.tx,
+                entry.client,
+                balance.available,
+                balance.held,
+                balance.total,
+                balance.locked,
+                entry.balance_available,
+                entry.balance_held,
+                entry.balance_total,
+                entry.balance_locked,
+            ));
+        }
+    }
+
+    Ok((clients.into_sorted(&client_names), client_names))
+}
+
+/// Compares the journal with the one recomputed from the input, returns how many entries they
+/// have and the last hash
+///
+/// # Errors
+///
+/// An entry isn't valid, or the first entry whose hash diverges (a missing or an extra entry
+/// included)
+pub fn verify_journal(
+    recomputed: impl Iterator<Item = Result<ZzJournalEntry, String>>,
+    mut journal: impl Iterator<Item = Result<ZzJournalEntry, String>>,
+) -> Result<(u64, JournalHash), String> {
+    let mut head = GENESIS_HASH;
+    let mut len = 0;
+
+    for expected in recomputed {
+        let expected = expected?;
+        let location = format!(
+            "Entry {} (row {} tx {} client {})",
+            len + 1,
+            expected.row,
+            expected.tx,
+            expected.client
+        );
+
+        let Some(entry) = journal.next().transpose()? else {
+            return Err(format!("{location}: missing from the journal"));
+        };
+        if entry != expected {
+            return Err(format!(
+                "{location}: the journal has {},{} but the input gives {},{}",
+                entry.fields(),
+                entry.hash,
+                expected.fields(),
+                expected.hash
+            ));
+        }
+
+        head = expected.chain(&head);
+        len += 1;
+    }
+
+    if let Some(entry) = journal.next().transpose()? {
+        return Err(format!(
+            "Entry {} (row {} tx {}): not produced by the input",
+            len + 1,
+            entry.row,
+            entry.tx
+        ));
+    }
+
+    Ok((len, head))
+}
+
+/// The journal is written by the program, so the amounts aren't limited like the input ones
+fn amount_parse_options() -> ZzParseOptions {
+    ZzParseOptions {
+        zz_amount_max_size: u16::MAX - 1,
+        ..Default::default()
+    }
+}
+
+fn deserialize_u_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ZzUAmount, D::Error> {
+    let amount = String::deserialize(deserializer)?;
+    match parse_zzamount_u(&amount_parse_options(), &amount) {
+        Ok(("", amount)) => Ok(amount),
+        _ => Err(serde::de::Error::custom(format!("Invalid amount {amount}"))),
+    }
+}
+
+fn deserialize_i_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ZzIAmount, D::Error> {
+    let amount = String::deserialize(deserializer)?;
+    match parse_zzamount_i(&amount_parse_options(), &amount) {
+        Ok(("", amount)) => Ok(amount),
+        _ => Err(serde::de::Error::custom(format!("Invalid amount {amount}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_iamount(val: i64) -> ZzIAmount {
+        ZzIAmount::new(val.into(), 0).unwrap()
+    }
+
+    fn make_entries() -> Vec<ZzJournalEntry> {
+        let mut balance = ZzClientBalance::new(3);
+        let mut entries = vec![];
+
+        let mut after = balance.clone();
+        after.available = make_iamount(10);
+        after.compute_total();
+        entries.push(ZzJournalEntry::new(
+            2,
+            1,
+            ZzTxTypeDiscriminants::Deposit,
+            JournalEntryKind::Tx,
+            ZzUAmount::new(10u32.into(), 0).unwrap(),
+            &balance,
+            &after,
+            &Default::default(),
+        ));
+        balance = after.clone();
+
+        after.available = ZzIAmount::new((-1).into(), 5).unwrap();
+        after.held = ZzIAmount::new(11u32.into(), 5).unwrap();
+        after.locked = true;
+        after.compute_total();
+        entries.push(ZzJournalEntry::new(
+            3,
+            1,
+            ZzTxTypeDiscriminants::Dispute,
+            JournalEntryKind::Tx,
+            ZzUAmount::new(11u32.into(), 5).unwrap(),
+            &balance,
+            &after,
+            &Default::default(),
+        ));
+
+        // what the writer does
+        let mut head = GENESIS_HASH;
+        for entry in &mut entries {
+            head = entry.chain(&head);
+            entry.hash = hex::encode(head);
+        }
+
+        entries
+    }
+
+    #[test]
+    fn test_write_read_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let entries = make_entries();
+        assert_eq!(entries[1].available.to_string(), "-11.0005");
+        assert_eq!(entries[1].locked, Some(true));
+
+        for format in [JournalFormatOptions::Csv, JournalFormatOptions::Jsonl] {
+            let mut journal = ZzJournalWriter::open(&path, format, 0, GENESIS_HASH).unwrap();
+            journal.write(entries[0].clone()).unwrap();
+            let (len, head) = (journal.flush().unwrap(), journal.head());
+            journal.write(entries[1].clone()).unwrap();
+            journal.flush().unwrap();
+
+            let read: Vec<_> = read_journal(&path, format).unwrap().collect();
+            assert_eq!(read, vec![Ok(entries[0].clone()), Ok(entries[1].clone())]);
+
+            // reopening at a length drops what came after it and keeps chaining
+            let mut journal = ZzJournalWriter::open(&path, format, len, head).unwrap();
+            journal.write(entries[1].clone()).unwrap();
+            journal.flush().unwrap();
+            let read: Vec<_> = read_journal(&path, format).unwrap().collect();
+            assert_eq!(read[1], Ok(entries[1].clone()), "{format}");
+
+            let (balances, _) = replay_journal(read.into_iter()).unwrap();
+            assert_eq!(balances.len(), 1);
+            assert_eq!(balances[0].total.to_string(), "10");
+            assert!(balances[0].locked);
+        }
+    }
+
+    #[test]
+    fn test_replay_divergence() {
+        let mut entries = make_entries();
+        entries[1].held = make_iamount(11);
+
+        let err = replay_journal(entries.into_iter().map(Ok)).unwrap_err();
+        assert_eq!(
+            err,
+            "Row 3 tx 1 client 3: the hash isn't chained to the previous entry"
+        );
+
+        // the hash is recomputed but the balance doesn't add up
+        let mut entries = make_entries();
+        entries[1].held = make_iamount(11);
+        entries[1].hash = hex::encode(entries[1].chain(&entries[0].chain(&GENESIS_HASH)));
+        let err = replay_journal(entries.into_iter().map(Ok)).unwrap_err();
+        assert!(err.starts_with("Row 3 tx 1 client 3: replayed"), "{err}");
+    }
+
+    #[test]
+    fn test_verify_journal() {
+        let entries = make_entries();
+        let verify = |journal: Vec<ZzJournalEntry>| {
+            verify_journal(
+                entries.clone().into_iter().map(Ok),
+                journal.into_iter().map(Ok),
+            )
+        };
+
+        let (len, head) = verify(entries.clone()).unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(hex::encode(head), entries[1].hash);
+
+        // an edited entry, even with its hash recomputed, diverges from the input
+        let mut edited = entries.clone();
+        edited[0].amount = ZzUAmount::new(9u32.into(), 0).unwrap();
+        edited[0].hash = hex::encode(edited[0].chain(&GENESIS_HASH));
+        let err = verify(edited).unwrap_err();
+        assert!(
+            err.starts_with("Entry 1 (row 2 tx 1 client 3): the journal has"),
+            "{err}"
+        );
+
+        // and so does one edited with its hash left as it was
+        let mut edited = entries.clone();
+        edited[1].row = 4;
+        let err = verify(edited).unwrap_err();
+        assert!(
+            err.starts_with("Entry 2 (row 3 tx 1 client 3): the journal has 4,"),
+            "{err}"
+        );
+
+        let err = verify(entries[..1].to_vec()).unwrap_err();
+        assert_eq!(
+            err,
+            "Entry 2 (row 3 tx 1 client 3): missing from the journal"
+        );
+
+        let mut extra = entries.clone();
+        extra.push(entries[1].clone());
+        let err = verify(extra).unwrap_err();
+        assert_eq!(err, "Entry 3 (row 3 tx 1): not produced by the input");
+    }
+
+    #[test]
+    fn test_sheet_digest() {
+        let head = make_entries()[1].chain(&GENESIS_HASH);
+        assert_ne!(sheet_digest(&head, b"a"), sheet_digest(&head, b"b"));
+        assert_ne!(sheet_digest(&head, b"a"), sheet_digest(&GENESIS_HASH, b"a"));
+    }
+}
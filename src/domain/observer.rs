@@ -0,0 +1,417 @@
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use serde::Serialize;
+
+use crate::{
+    common::zz_amount::{ZzIAmount, ZzUAmount},
+    domain::{
+        client_balance::{ClientId, ZzClientBalance},
+        client_store::{ZzClientIdDisplay, ZzClientNames},
+        transaction::TxId,
+    },
+};
+
+/// Something that happened to a client while applying a transaction, row is the one the
+/// transaction was applied at (see ZzEngine::apply_at_row). The client is its id, see named to
+/// have it as it was in the input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ZzEngineEvent<C = ClientId> {
+    AccountLocked {
+        client: C,
+        tx: TxId,
+        row: u64,
+    },
+    DisputeOpened {
+        client: C,
+        tx: TxId,
+        row: u64,
+        amount: ZzUAmount,
+    },
+    /// Rejected with RejectReason::InsufficientFunds
+    WithdrawalRejected {
+        client: C,
+        tx: TxId,
+        row: u64,
+        amount: ZzUAmount,
+        available: ZzIAmount,
+    },
+    /// See ZzClientBalance::is_negative
+    BalanceWentNegative {
+        client: C,
+        tx: TxId,
+        row: u64,
+        available: ZzIAmount,
+        receivable: ZzIAmount,
+    },
+}
+
+impl ZzEngineEvent {
+    /// The event with the client displayed as it was in the input
+    pub fn named<'a>(
+        &self,
+        client_names: &'a ZzClientNames,
+    ) -> ZzEngineEvent<ZzClientIdDisplay<'a>> {
+        match self {
+            ZzEngineEvent::AccountLocked { client, tx, row } => ZzEngineEvent::AccountLocked {
+                client: client_names.display(*client),
+                tx: *tx,
+                row: *row,
+            },
+            ZzEngineEvent::DisputeOpened {
+                client,
+                tx,
+                row,
+                amount,
+            } => ZzEngineEvent::DisputeOpened {
+                client: client_names.display(*client),
+                tx: *tx,
+                row: *row,
+                amount: amount.clone(),
+            },
+            ZzEngineEvent::WithdrawalRejected {
+                client,
+                tx,
+                row,
+                amount,
+                available,
+            } => ZzEngineEvent::WithdrawalRejected {
+                client: client_names.display(*client),
+                tx: *tx,
+                row: *row,
+                amount: amount.clone(),
+                available: available.clone(),
+            },
+            ZzEngineEvent::BalanceWentNegative {
+                client,
+                tx,
+                row,
+                available,
+                receivable,
+            } => ZzEngineEvent::BalanceWentNegative {
+                client: client_names.display(*client),
+                tx: *tx,
+                row: *row,
+                available: available.clone(),
+                receivable: receivable.clone(),
+            },
+        }
+    }
+}
+
+impl<C: fmt::Display> fmt::Display for ZzEngineEvent<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZzEngineEvent::AccountLocked { client, tx, row } => {
+                write!(f, "Row {row}: client {client} was locked by tx {tx}")
+            }
+            ZzEngineEvent::DisputeOpened {
+                client,
+                tx,
+                row,
+                amount,
+            } => {
+                write!(f, "Row {row}: client {client} disputed {amount} of tx {tx}")
+            }
+            ZzEngineEvent::WithdrawalRejected {
+                client,
+                tx,
+                row,
+                amount,
+                available,
+            } => {
+                write!(
+                    f,
+                    "Row {row}: withdrawal {tx} of {amount} by client {client} was rejected, \
+                     {available} is available"
+                )
+            }
+            ZzEngineEvent::BalanceWentNegative {
+                client,
+                tx,
+                row,
+                available,
+                receivable,
+            } => {
+                write!(
+                    f,
+                    "Row {row}: client {client} owes money after tx {tx}, available is \
+                     {available} and receivable is {receivable}"
+                )
+            }
+        }
+    }
+}
+
+/// Callbacks the engine calls while it applies transactions, e.g. to page someone when an
+/// account is locked. Each callback builds the event and gives it to on_event unless it's
+/// implemented, so an observer that handles every event the same way only implements on_event
+///
+/// The engine's observer is () by default, which doesn't observe anything and costs nothing
+pub trait EngineObserver {
+    /// If false the engine doesn't look for the events at all
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    /// The transaction locked the account of the client: a chargeback, a freeze or a risk rule
+    fn account_locked(&mut self, balance: &ZzClientBalance, tx_id: TxId, row: u64) {
+        self.on_event(&ZzEngineEvent::AccountLocked {
+            client: balance.client_id,
+            tx: tx_id,
+            row,
+        });
+    }
+
+    /// A dispute of the transaction held the amount
+    fn dispute_opened(&mut self, client_id: ClientId, tx_id: TxId, amount: &ZzUAmount, row: u64) {
+        self.on_event(&ZzEngineEvent::DisputeOpened {
+            client: client_id,
+            tx: tx_id,
+            row,
+            amount: amount.clone(),
+        });
+    }
+
+    /// A withdrawal was rejected because the available funds don't cover it, balance is None if
+    /// no transaction was applied to the client
+    fn withdrawal_rejected(
+        &mut self,
+        client_id: ClientId,
+        balance: Option<&ZzClientBalance>,
+        tx_id: TxId,
+        amount: &ZzUAmount,
+        row: u64,
+    ) {
+        self.on_event(&ZzEngineEvent::WithdrawalRejected {
+            client: client_id,
+            tx: tx_id,
+            row,
+            amount: amount.clone(),
+            available: balance.map_or_else(ZzIAmount::zero, |balance| balance.available.clone()),
+        });
+    }
+
+    /// The transaction left the client owing money and it didn't before, see
+    /// ZzClientBalance::is_negative. It can be the counterparty of the transaction
+    fn balance_went_negative(&mut self, balance: &ZzClientBalance, tx_id: TxId, row: u64) {
+        self.on_event(&ZzEngineEvent::BalanceWentNegative {
+            client: balance.client_id,
+            tx: tx_id,
+            row,
+            available: balance.available.clone(),
+            receivable: balance.receivable.clone(),
+        });
+    }
+
+    /// Every event whose callback isn't implemented
+    fn on_event(&mut self, _event: &ZzEngineEvent) {}
+
+    /// Writes what the observer buffered, the engine calls it at checkpoints and when it's
+    /// done
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// No observer, the default of ZzEngine
+impl EngineObserver for () {
+    fn is_enabled(&self) -> bool {
+        false
+    }
+}
+
+/// Every observer sees every event, in order
+impl EngineObserver for Vec<Box<dyn EngineObserver>> {
+    fn is_enabled(&self) -> bool {
+        self.iter().any(|observer| observer.is_enabled())
+    }
+
+    fn account_locked(&mut self, balance: &ZzClientBalance, tx_id: TxId, row: u64) {
+        for observer in self {
+            observer.account_locked(balance, tx_id, row);
+        }
+    }
+
+    fn dispute_opened(&mut self, client_id: ClientId, tx_id: TxId, amount: &ZzUAmount, row: u64) {
+        for observer in self {
+            observer.dispute_opened(client_id, tx_id, amount, row);
+        }
+    }
+
+    fn withdrawal_rejected(
+        &mut self,
+        client_id: ClientId,
+        balance: Option<&ZzClientBalance>,
+        tx_id: TxId,
+        amount: &ZzUAmount,
+        row: u64,
+    ) {
+        for observer in self {
+            observer.withdrawal_rejected(client_id, balance, tx_id, amount, row);
+        }
+    }
+
+    fn balance_went_negative(&mut self, balance: &ZzClientBalance, tx_id: TxId, row: u64) {
+        for observer in self {
+            observer.balance_went_negative(balance, tx_id, row);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for observer in self {
+            observer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the events, mostly for tests
+impl EngineObserver for Vec<ZzEngineEvent> {
+    fn on_event(&mut self, event: &ZzEngineEvent) {
+        self.push(event.clone());
+    }
+}
+
+/// Writes each event to stderr as a line of text
+pub struct ZzLogObserver {
+    client_names: ZzClientNames,
+}
+
+impl ZzLogObserver {
+    pub fn new(client_names: ZzClientNames) -> Self {
+        Self { client_names }
+    }
+}
+
+impl EngineObserver for ZzLogObserver {
+    fn on_event(&mut self, event: &ZzEngineEvent) {
+        eprintln!("{}", event.named(&self.client_names));
+    }
+}
+
+/// Writes each event as a json object per line, tagged by its kind in the event field (e.g.
+/// `{"event":"account-locked","client":"1","tx":3,"row":4}`)
+pub struct ZzJsonlObserver<W: Write> {
+    w: W,
+    client_names: ZzClientNames,
+    /// the first error, the callbacks can't return it so it's returned by flush
+    error: Option<io::Error>,
+}
+
+impl<W: Write> ZzJsonlObserver<W> {
+    pub fn new(w: W, client_names: ZzClientNames) -> Self {
+        Self {
+            w,
+            client_names,
+            error: None,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Failed to write an event
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<W: Write> EngineObserver for ZzJsonlObserver<W> {
+    fn on_event(&mut self, event: &ZzEngineEvent) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = serde_json::to_writer(&mut self.w, &event.named(&self.client_names))
+            .map_err(io::Error::from)
+            .and_then(|_| self.w.write_all(b"\n"));
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_events() -> Vec<ZzEngineEvent> {
+        vec![
+            ZzEngineEvent::AccountLocked {
+                client: 1,
+                tx: 3,
+                row: 4,
+            },
+            ZzEngineEvent::WithdrawalRejected {
+                client: 2,
+                tx: 5,
+                row: 6,
+                amount: ZzUAmount::new(15u32.into(), 0).unwrap(),
+                available: ZzIAmount::zero(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_jsonl_observer() {
+        let client_names = ZzClientNames::default();
+        let alice = client_names.intern("alice");
+        let mut observer = ZzJsonlObserver::new(vec![], client_names);
+        for event in make_events() {
+            observer.on_event(&event);
+        }
+        observer.on_event(&ZzEngineEvent::AccountLocked {
+            client: alice,
+            tx: 7,
+            row: 8,
+        });
+
+        assert_eq!(
+            String::from_utf8(observer.into_inner().unwrap()).unwrap(),
+            concat!(
+                r#"{"event":"account-locked","client":"1","tx":3,"row":4}"#,
+                "\n",
+                r#"{"event":"withdrawal-rejected","client":"2","tx":5,"row":6,"amount":"15","available":"0"}"#,
+                "\n",
+                r#"{"event":"account-locked","client":"alice","tx":7,"row":8}"#,
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let lines: Vec<_> = make_events()
+            .iter()
+            .map(|event| event.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Row 4: client 1 was locked by tx 3",
+                "Row 6: withdrawal 5 of 15 by client 2 was rejected, 0 is available",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vec_of_observers() {
+        let mut observers: Vec<Box<dyn EngineObserver>> = vec![];
+        assert!(!observers.is_enabled());
+        observers.push(Box::new(()));
+        assert!(!observers.is_enabled());
+        observers.push(Box::new(Vec::<ZzEngineEvent>::new()));
+        assert!(observers.is_enabled());
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    ZzParseOptions,
+    common::{
+        encoding::{read_u32, read_u64, write_u32, write_u64},
+        zz_amount::ZzUAmount,
+    },
+    domain::{
+        client_balance::{ClientId, ZzClientBalance},
+        transaction::{Outcome, TxId, ZzTx, ZzTxType},
+    },
+    parsers::nom::zz_amount::parse_zzamount_u,
+};
+
+/// What a risk rule decided for a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiskDecision {
+    Allow,
+    /// The transaction is ignored
+    Reject,
+    /// The transaction is applied and the account is locked afterwards, it isn't locked if the
+    /// transaction is rejected
+    Lock,
+}
+
+/// A rule evaluated before a transaction is inserted. Rules are evaluated in order and all of
+/// them see every transaction (so they can keep their own state), the transaction is ignored if
+/// any rule rejects it and the account is locked if any rule locks it. Rules are Send so an
+/// engine can move between threads, see process_stream
+pub trait RiskRule: Send {
+    /// open_disputes: the tx ids of the client's transactions that are currently disputed
+    fn evaluate(
+        &mut self,
+        transaction: &ZzTx,
+        balance: Option<&ZzClientBalance>,
+        open_disputes: &[TxId],
+    ) -> RiskDecision;
+
+    /// Called after the transaction that was evaluated is inserted, with what happened to it.
+    /// Rules that count transactions count them here, so the rejected ones aren't counted
+    fn after_apply(&mut self, _transaction: &ZzTx, _outcome: &Outcome) {}
+
+    /// Writes the state the rule keeps between transactions so a checkpoint can restore it,
+    /// stateless rules don't need to implement it
+    fn write_state(&self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Restores what write_state wrote
+    fn read_state(&mut self, _r: &mut dyn Read) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Rejects withdrawals and outgoing transfers while the client has an open dispute
+pub struct BlockWithdrawalsWhileDisputed;
+
+impl RiskRule for BlockWithdrawalsWhileDisputed {
+    fn evaluate(
+        &mut self,
+        transaction: &ZzTx,
+        _balance: Option<&ZzClientBalance>,
+        open_disputes: &[TxId],
+    ) -> RiskDecision {
+        match transaction.r#type {
+            ZzTxType::Withdrawal(_) | ZzTxType::Transfer { .. } if !open_disputes.is_empty() => {
+                RiskDecision::Reject
+            }
+            _ => RiskDecision::Allow,
+        }
+    }
+}
+
+/// Rejects withdrawals and outgoing transfers with an amount greater than the limit
+pub struct MaxWithdrawal {
+    pub limit: ZzUAmount,
+}
+
+impl RiskRule for MaxWithdrawal {
+    fn evaluate(
+        &mut self,
+        transaction: &ZzTx,
+        _balance: Option<&ZzClientBalance>,
+        _open_disputes: &[TxId],
+    ) -> RiskDecision {
+        match &transaction.r#type {
+            ZzTxType::Withdrawal(amount) | ZzTxType::Transfer { amount, .. }
+                if *amount > self.limit =>
+            {
+                RiskDecision::Reject
+            }
+            _ => RiskDecision::Allow,
+        }
+    }
+}
+
+/// Locks the account once the client has made max_disputes disputes within its last window
+/// transactions. Only the transactions that were applied are counted
+pub struct DisputeVelocity {
+    pub max_disputes: usize,
+    pub window: u64,
+    /// per client: how many transactions were applied and the positions of its recent disputes
+    clients: HashMap<ClientId, (u64, VecDeque<u64>)>,
+}
+
+impl DisputeVelocity {
+    pub fn new(max_disputes: usize, window: u64) -> Self {
+        Self {
+            max_disputes,
+            window,
+            clients: HashMap::new(),
+        }
+    }
+}
+
+impl RiskRule for DisputeVelocity {
+    /// Locks if the dispute would be the last one allowed in the window, the lock only happens
+    /// if it's applied
+    fn evaluate(
+        &mut self,
+        transaction: &ZzTx,
+        _balance: Option<&ZzClientBalance>,
+        _open_disputes: &[TxId],
+    ) -> RiskDecision {
+        if !matches!(transaction.r#type, ZzTxType::Dispute(_)) {
+            return RiskDecision::Allow;
+        }
+
+        let position = self
+            .clients
+            .get(&transaction.client_id)
+            .map_or(0, |(count, _)| *count)
+            + 1;
+        let recent = self
+            .clients
+            .get(&transaction.client_id)
+            .map_or(0, |(_, disputes)| {
+                disputes
+                    .iter()
+                    .filter(|dispute| position - *dispute < self.window)
+                    .count()
+            });
+
+        if recent + 1 >= self.max_disputes {
+            RiskDecision::Lock
+        } else {
+            RiskDecision::Allow
+        }
+    }
+
+    fn after_apply(&mut self, transaction: &ZzTx, outcome: &Outcome) {
+        if !matches!(outcome, Outcome::Applied(_)) {
+            return;
+        }
+
+        let (count, disputes) = self.clients.entry(transaction.client_id).or_default();
+        *count += 1;
+
+        if matches!(transaction.r#type, ZzTxType::Dispute(_)) {
+            disputes.push_back(*count);
+        }
+        while disputes
+            .front()
+            .is_some_and(|position| *count - position >= self.window)
+        {
+            disputes.pop_front();
+        }
+    }
+
+    fn write_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u32(w, self.clients.len() as u32)?;
+        for (client_id, (count, disputes)) in &self.clients {
+            write_u64(w, *client_id)?;
+            write_u64(w, *count)?;
+            write_u32(w, disputes.len() as u32)?;
+            for position in disputes {
+                write_u64(w, *position)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.clients.clear();
+        for _ in 0..read_u32(r)? {
+            let client_id = read_u64(r)?;
+            let count = read_u64(r)?;
+            let disputes = (0..read_u32(r)?)
+                .map(|_| read_u64(r))
+                .collect::<io::Result<_>>()?;
+            self.clients.insert(client_id, (count, disputes));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ZzRiskRuleKind {
+    BlockWithdrawalsWhileDisputed,
+    MaxWithdrawal,
+    DisputeVelocity,
+}
+
+/// A row of the risk rules csv, the meaning of limit and window depends on the rule
+#[derive(Deserialize)]
+struct ZzRiskRuleRow {
+    rule: ZzRiskRuleKind,
+    limit: Option<String>,
+    window: Option<u64>,
+}
+
+/// The configuration of a built-in rule
+#[derive(Debug, Clone, PartialEq)]
+enum ZzRiskRuleConfig {
+    BlockWithdrawalsWhileDisputed,
+    MaxWithdrawal(ZzUAmount),
+    DisputeVelocity { max_disputes: usize, window: u64 },
+}
+
+/// The built-in rules loaded from a file, see ZzRiskRules::from_reader
+#[derive(Debug, Clone, Default)]
+pub struct ZzRiskRules {
+    rules: Vec<ZzRiskRuleConfig>,
+}
+
+impl ZzRiskRules {
+    /// Loads a csv with the headers rule,limit,window
+    ///
+    /// # Errors
+    ///
+    /// Failed to read or parse the file
+    pub fn from_path(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|err| format!("{path}: {err}"))?;
+        Self::from_reader(file)
+    }
+
+    /// Each row is one of:
+    /// - block-withdrawals-while-disputed,,
+    /// - max-withdrawal,<amount>,
+    /// - dispute-velocity,<disputes>,<transactions>
+    ///
+    /// # Errors
+    ///
+    /// Failed to read or parse the csv
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self, String> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut rules = vec![];
+
+        for row in rdr.deserialize::<ZzRiskRuleRow>() {
+            let row = row.map_err(|err| err.to_string())?;
+            let limit = row.limit.filter(|limit| !limit.is_empty());
+
+            let rule = match (row.rule, limit, row.window) {
+                (ZzRiskRuleKind::BlockWithdrawalsWhileDisputed, None, None) => {
+                    ZzRiskRuleConfig::BlockWithdrawalsWhileDisputed
+                }
+                (ZzRiskRuleKind::MaxWithdrawal, Some(limit), None) => {
+                    match parse_zzamount_u(&ZzParseOptions::default(), &limit) {
+                        Ok(("", limit)) => ZzRiskRuleConfig::MaxWithdrawal(limit),
+                        _ => return Err(format!("Invalid max-withdrawal limit: {limit}")),
+                    }
+                }
+                (ZzRiskRuleKind::DisputeVelocity, Some(limit), Some(window)) if window > 0 => {
+                    match limit.parse() {
+                        Ok(max_disputes) if max_disputes > 0 => ZzRiskRuleConfig::DisputeVelocity {
+                            max_disputes,
+                            window,
+                        },
+                        _ => return Err(format!("Invalid dispute-velocity limit: {limit}")),
+                    }
+                }
+                (rule, _, _) => return Err(format!("Invalid parameters for {rule:?}")),
+            };
+            rules.push(rule);
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Instantiates the rules, each call starts with a clean state
+    pub fn build(&self) -> Vec<Box<dyn RiskRule>> {
+        self.rules
+            .iter()
+            .map(|rule| -> Box<dyn RiskRule> {
+                match rule {
+                    ZzRiskRuleConfig::BlockWithdrawalsWhileDisputed => {
+                        Box::new(BlockWithdrawalsWhileDisputed)
+                    }
+                    ZzRiskRuleConfig::MaxWithdrawal(limit) => Box::new(MaxWithdrawal {
+                        limit: limit.clone(),
+                    }),
+                    ZzRiskRuleConfig::DisputeVelocity {
+                        max_disputes,
+                        window,
+                    } => Box::new(DisputeVelocity::new(*max_disputes, *window)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Evaluates every rule and returns if the transaction is allowed and if the account must be
+/// locked
+pub fn evaluate_risk_rules(
+    rules: &mut [Box<dyn RiskRule>],
+    transaction: &ZzTx,
+    balance: Option<&ZzClientBalance>,
+    open_disputes: &[TxId],
+) -> (bool, bool) {
+    let mut allowed = true;
+    let mut lock = false;
+
+    for rule in rules {
+        match rule.evaluate(transaction, balance, open_disputes) {
+            RiskDecision::Allow => {}
+            RiskDecision::Reject => allowed = false,
+            RiskDecision::Lock => lock = true,
+        }
+    }
+
+    (allowed, lock)
+}
+
+/// Gives every rule the outcome of the transaction they evaluated, see RiskRule::after_apply
+pub fn record_risk_outcome(rules: &mut [Box<dyn RiskRule>], transaction: &ZzTx, outcome: &Outcome) {
+    for rule in rules {
+        rule.after_apply(transaction, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::transaction::{RejectReason, ZzTxEffect};
+
+    fn make_amount(val: u64) -> ZzUAmount {
+        ZzUAmount::new(val.into(), 0).unwrap()
+    }
+
+    fn make_tx(client_id: ClientId, r#type: ZzTxType) -> ZzTx {
+        ZzTx {
+            r#type,
+            client_id,
+            tx_id: 1,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_block_withdrawals_while_disputed() {
+        let rule = &mut BlockWithdrawalsWhileDisputed;
+        let withdrawal = make_tx(1, ZzTxType::Withdrawal(make_amount(1)));
+
+        assert_eq!(rule.evaluate(&withdrawal, None, &[]), RiskDecision::Allow);
+        assert_eq!(rule.evaluate(&withdrawal, None, &[3]), RiskDecision::Reject);
+        assert_eq!(
+            rule.evaluate(&make_tx(1, ZzTxType::Deposit(make_amount(1))), None, &[3]),
+            RiskDecision::Allow
+        );
+    }
+
+    /// Evaluates the transaction and records it as applied unless the rule rejects it
+    fn evaluate_applied(rule: &mut impl RiskRule, transaction: &ZzTx) -> RiskDecision {
+        let decision = rule.evaluate(transaction, None, &[]);
+        if decision != RiskDecision::Reject {
+            let effect = ZzTxEffect::credit(make_amount(0));
+            rule.after_apply(transaction, &Outcome::Applied(effect));
+        }
+        decision
+    }
+
+    #[test]
+    fn test_dispute_velocity() {
+        let rule = &mut DisputeVelocity::new(2, 3);
+        let dispute = make_tx(1, ZzTxType::Dispute(None));
+        let deposit = make_tx(1, ZzTxType::Deposit(make_amount(1)));
+
+        assert_eq!(evaluate_applied(rule, &dispute), RiskDecision::Allow);
+        assert_eq!(evaluate_applied(rule, &deposit), RiskDecision::Allow);
+        assert_eq!(evaluate_applied(rule, &deposit), RiskDecision::Allow);
+        // the first dispute is out of the window
+        assert_eq!(evaluate_applied(rule, &dispute), RiskDecision::Allow);
+        // other clients don't count
+        assert_eq!(
+            evaluate_applied(rule, &make_tx(2, ZzTxType::Dispute(None))),
+            RiskDecision::Allow
+        );
+        assert_eq!(rule.evaluate(&dispute, None, &[]), RiskDecision::Lock);
+
+        // a rejected dispute isn't counted
+        let rule = &mut DisputeVelocity::new(2, 100);
+        assert_eq!(rule.evaluate(&dispute, None, &[]), RiskDecision::Allow);
+        rule.after_apply(&dispute, &Outcome::Rejected(RejectReason::UnknownTx));
+        assert_eq!(evaluate_applied(rule, &dispute), RiskDecision::Allow);
+        assert_eq!(rule.evaluate(&dispute, None, &[]), RiskDecision::Lock);
+    }
+
+    #[test]
+    fn test_risk_rules_from_reader() {
+        let rules = ZzRiskRules::from_reader(
+            "rule, limit, window\n\
+             block-withdrawals-while-disputed, ,\n\
+             max-withdrawal, 100.5,\n\
+             dispute-velocity, 3, 100\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            rules.rules,
+            vec![
+                ZzRiskRuleConfig::BlockWithdrawalsWhileDisputed,
+                ZzRiskRuleConfig::MaxWithdrawal(ZzUAmount::new(100u32.into(), 5000).unwrap()),
+                ZzRiskRuleConfig::DisputeVelocity {
+                    max_disputes: 3,
+                    window: 100
+                },
+            ]
+        );
+
+        let mut built = rules.build();
+        let withdrawal = make_tx(1, ZzTxType::Withdrawal(make_amount(101)));
+        assert_eq!(
+            evaluate_risk_rules(&mut built, &withdrawal, None, &[]),
+            (false, false)
+        );
+
+        for invalid in [
+            "rule,limit,window\nmax-withdrawal,,\n",
+            "rule,limit,window\nmax-withdrawal,-1,\n",
+            "rule,limit,window\ndispute-velocity,3,\n",
+            "rule,limit,window\ndispute-velocity,0,10\n",
+            "rule,limit,window\nblock-withdrawals-while-disputed,1,\n",
+            "rule,limit,window\nother,,\n",
+        ] {
+            assert!(
+                ZzRiskRules::from_reader(invalid.as_bytes()).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
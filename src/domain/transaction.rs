@@ -1,10 +1,23 @@
 use serde::{Deserialize, Serialize};
 use strum::{EnumDiscriminants, IntoDiscriminant};
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Read, Write},
+};
 
-use crate::common::zz_amount::ZzUAmount;
+use crate::common::encoding::{
+    invalid_data, read_u_amount, read_u8, read_u32, read_u64, write_u_amount, write_u8, write_u32,
+    write_u64,
+};
+
+use crate::common::zz_amount::{ZzIAmount, ZzUAmount};
 use crate::domain::client_balance::{ClientId, ZzClientBalance};
+use crate::domain::client_store::ZzClientNames;
+use crate::{NegativeBalanceOptions, ZzPolicyOptions};
+
+pub mod disk_store;
+pub mod hash_map_store;
 
 pub type TxId = u32;
 
@@ -14,52 +27,171 @@ pub struct ZzTx {
     pub r#type: ZzTxType,
     pub client_id: ClientId,
     pub tx_id: TxId,
+    /// The optional 6th column, when present the dispute windows are measured with it instead of
+    /// the row count
+    pub timestamp: Option<u64>,
 }
 
-pub struct ZzTxSerializeCsv(pub ZzTx);
+/// Displays the transaction as the csv row it came from, with the names of string client ids
+pub struct ZzTxSerializeCsv<'a>(pub &'a ZzTx, pub &'a ZzClientNames);
 
-impl std::fmt::Display for ZzTxSerializeCsv {
+impl std::fmt::Display for ZzTxSerializeCsv<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.0.r#type {
-            ZzTxType::Withdrawal(zz_amount) | ZzTxType::Deposit(zz_amount) => write!(
-                f,
-                "{},{},{},{}",
-                self.0.r#type.discriminant(),
-                self.0.client_id,
-                self.0.tx_id,
-                zz_amount
-            ),
-            _ => write!(
-                f,
-                "{},{},{}",
-                self.0.r#type.discriminant(),
-                self.0.client_id,
-                self.0.tx_id
-            ),
+        let (tx, client_names) = (self.0, self.1);
+
+        write!(
+            f,
+            "{},{},{}",
+            tx.r#type.discriminant(),
+            client_names.display(tx.client_id),
+            tx.tx_id
+        )?;
+        // the amount and details columns that were written
+        let columns = match &tx.r#type {
+            ZzTxType::Withdrawal(zz_amount)
+            | ZzTxType::Deposit(zz_amount)
+            | ZzTxType::Dispute(Some(zz_amount))
+            | ZzTxType::Authorize(zz_amount)
+            | ZzTxType::Capture(Some(zz_amount)) => {
+                write!(f, ",{zz_amount}")?;
+                1
+            }
+            ZzTxType::Transfer { to, amount } => {
+                write!(f, ",{amount},{}", client_names.display(*to))?;
+                2
+            }
+            ZzTxType::Adjustment { amount, reason } => {
+                write!(f, ",{amount},{reason}")?;
+                2
+            }
+            _ => 0,
+        };
+
+        if let Some(timestamp) = tx.timestamp {
+            write!(f, "{},{timestamp}", ",".repeat(2 - columns))?;
         }
+
+        Ok(())
     }
 }
 
 /// Represents the different kinds of transactions a client can have
 #[derive(Debug, Clone, PartialEq, fake::Dummy, EnumDiscriminants)]
-#[strum_discriminants(derive(Serialize, Deserialize), serde(rename_all = "kebab-case"))]
+#[strum_discriminants(derive(Serialize, Deserialize, Hash), serde(rename_all = "kebab-case"))]
 pub enum ZzTxType {
     Withdrawal(ZzUAmount),
     Deposit(ZzUAmount),
-    Dispute,
+    /// Disputes the amount or the whole undisputed remainder of the deposit if it's None
+    Dispute(Option<ZzUAmount>),
     Resolve,
     Chargeback,
+    /// Moves funds from the client to another client, both sides are applied or neither
+    Transfer {
+        to: ClientId,
+        amount: ZzUAmount,
+    },
+    /// Moves the amount from available to held until it's captured or voided
+    Authorize(ZzUAmount),
+    /// References an authorization and finalizes the amount (or the whole hold if it's None) as
+    /// a withdrawal, the rest stays held until it's voided
+    Capture(Option<ZzUAmount>),
+    /// References an authorization and releases what's still held
+    Void,
+    /// Administrative: unlocks the account
+    Unlock,
+    /// Administrative: locks the account without a chargeback
+    Freeze,
+    /// Administrative: signed correction of the available funds
+    Adjustment {
+        amount: ZzIAmount,
+        #[dummy(faker = "fake::faker::lorem::en::Word()")]
+        reason: String,
+    },
 }
 
 serde_plain::derive_display_from_serialize!(ZzTxTypeDiscriminants);
 
+impl ZzTx {
+    /// Other clients that may be changed by this transaction, it must be ignored if any of them
+    /// is locked
+    pub fn counterparties(&self, policy_options: &ZzPolicyOptions) -> Vec<ClientId> {
+        let mut counterparties = vec![];
+
+        if let ZzTxType::Transfer { to, .. } = &self.r#type {
+            counterparties.push(*to);
+        }
+        if let Some((schedule, fee_account)) = policy_options.fees()
+            && schedule.charges(self.client_id, self.r#type.discriminant())
+        {
+            counterparties.push(fee_account);
+        }
+
+        counterparties
+    }
+
+    /// The client or the destination of the transfer is the fee account, which only collects
+    /// fees (see ZzPolicyOptions::fee_account)
+    pub fn involves_fee_account(&self, policy_options: &ZzPolicyOptions) -> bool {
+        let Some(fee_account) = policy_options.fee_account else {
+            return false;
+        };
+
+        self.client_id == fee_account
+            || matches!(self.r#type, ZzTxType::Transfer { to, .. } if to == fee_account)
+    }
+}
+
+impl ZzTxType {
+    /// Dispute, resolve, chargeback, capture and void reference another transaction by its tx
+    /// id, every other transaction uses its own tx id
+    pub fn references_tx(&self) -> bool {
+        matches!(
+            self,
+            ZzTxType::Dispute(_)
+                | ZzTxType::Resolve
+                | ZzTxType::Chargeback
+                | ZzTxType::Capture(_)
+                | ZzTxType::Void
+        )
+    }
+
+    /// Administrative transactions are issued by ops and may be restricted, see
+    /// ZzPolicyOptions::allow_admin_transactions
+    pub fn is_administrative(&self) -> bool {
+        matches!(self, Self::Unlock | Self::Freeze | Self::Adjustment { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransactionState {
     Deposit(ZzUAmount),
-    Withdrawal,
-    Dispute(ZzUAmount),
+    Withdrawal(ZzUAmount),
+    /// held + receivable is the disputed portion of the deposit amount, the rest can still be
+    /// disputed. The receivable is only non zero if the hold was capped by
+    /// NegativeBalanceOptions::Cap. The portions aren't kept apart, a resolve or chargeback
+    /// settles all of them
+    Dispute {
+        amount: ZzUAmount,
+        held: ZzUAmount,
+        receivable: ZzUAmount,
+    },
+    /// Only reachable if withdrawal disputes are allowed by the policy
+    WithdrawalDispute(ZzUAmount),
     Locked,
+    /// A withdrawal that was charged back, the funds were returned to the client
+    WithdrawalChargedBack,
+    /// Unlock, freeze or adjustment, these can't be referenced by other transactions
+    Administrative,
+    /// The amount is held until it's captured or voided
+    Authorized(ZzUAmount),
+    /// The authorization can't be captured again, the remainder is held until it's voided
+    Captured {
+        remainder: ZzUAmount,
+    },
+    Voided,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct ZzTxEffect {
     pub amount: ZzUAmount,
     /// Some(true) -> increase by amount
@@ -70,109 +202,1024 @@ pub struct ZzTxEffect {
     /// Some(false) -> decrease by amount
     /// None -> do nothing
     pub held: Option<bool>,
-    pub locked: bool,
+    /// Some(true) -> lock the account
+    /// Some(false) -> unlock the account
+    /// None -> do nothing
+    pub locked: Option<bool>,
+    /// Some((true, amount)) -> increase receivable by amount
+    /// Some((false, amount)) -> decrease receivable by amount
+    /// None -> do nothing
+    pub receivable: Option<(bool, ZzUAmount)>,
+    /// Some(client) -> increase the available of the other client by amount
+    /// None -> do nothing
+    pub counterparty: Option<ClientId>,
+    /// Some((client, fee)) -> decrease available by fee and increase the available of the fee
+    /// account (client) by fee
+    /// None -> do nothing
+    pub fee: Option<(ClientId, ZzUAmount)>,
+}
+
+impl ZzTxEffect {
+    pub(crate) fn credit(amount: ZzUAmount) -> ZzTxEffect {
+        ZzTxEffect {
+            amount,
+            available: Some(true),
+            held: None,
+            locked: None,
+            receivable: None,
+            counterparty: None,
+            fee: None,
+        }
+    }
+
+    /// The effects on other clients: the destination of a transfer and the fee account
+    pub fn side_effects(&self) -> Vec<(ClientId, ZzTxEffect)> {
+        let mut side_effects = vec![];
+
+        if let Some(client_id) = self.counterparty {
+            side_effects.push((client_id, Self::credit(self.amount.clone())));
+        }
+        if let Some((client_id, fee)) = &self.fee {
+            side_effects.push((*client_id, Self::credit(fee.clone())));
+        }
+
+        side_effects
+    }
+}
+
+/// Why a transaction didn't change any balance
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::EnumCount, strum::EnumIter, strum::FromRepr,
+)]
+#[serde(rename_all = "kebab-case")]
+#[repr(u8)]
+pub enum RejectReason {
+    /// The client has no transaction with the referenced tx id. Tx ids are per client, so this
+    /// includes referencing a transaction of another client, and withdrawals aren't kept unless
+    /// ZzPolicyOptions::allow_withdrawal_disputes so it includes disputing one then
+    UnknownTx,
+    /// The tx id is already used by the client (or by the destination of a transfer), or by
+    /// another client if DuplicateTxOptions is reject
+    DuplicateTx,
+    /// The available funds don't cover the amount and its fee
+    InsufficientFunds,
+    /// A zero amount or a portion bigger than what's left to dispute or capture
+    InvalidAmount,
+    /// A transfer to the same client
+    SelfTransfer,
+    /// A resolve or chargeback of a transaction that isn't under dispute
+    NotDisputed,
+    /// The referenced transaction was already charged back
+    AlreadyChargedBack,
+    /// The referenced transaction can't be disputed, captured or voided in its state, e.g. a
+    /// void of a deposit, a dispute of an authorization or a partial dispute of a withdrawal
+    InvalidReference,
+    /// The dispute came after ZzPolicyOptions::dispute_window
+    DisputeWindowClosed,
+    /// The dispute would make the available funds negative, see NegativeBalanceOptions::Reject
+    NegativeBalance,
+    /// An administrative transaction that isn't allowed, see
+    /// ZzPolicyOptions::allow_admin_transactions
+    Unauthorized,
+    /// The client's account is locked
+    AccountLocked,
+    /// The destination of the transfer or the fee account is locked
+    CounterpartyLocked,
+    /// The client or the destination of the transfer is the fee account, see
+    /// ZzTx::involves_fee_account
+    FeeAccount,
+    /// A risk rule rejected it, see RiskRule
+    RiskRule,
+}
+
+serde_plain::derive_display_from_serialize!(RejectReason);
+
+/// What inserting a transaction did, the effect to apply to the balances or why nothing is
+/// applied
+#[derive(Debug, PartialEq)]
+pub enum Outcome<T = ZzTxEffect> {
+    Applied(T),
+    Rejected(RejectReason),
+}
+
+impl<T> Outcome<T> {
+    /// The effect if the transaction was applied
+    pub fn applied(self) -> Option<T> {
+        match self {
+            Self::Applied(applied) => Some(applied),
+            Self::Rejected(_) => None,
+        }
+    }
+
+    /// The reason if the transaction was rejected
+    pub fn rejected(&self) -> Option<RejectReason> {
+        match self {
+            Self::Applied(_) => None,
+            Self::Rejected(reason) => Some(*reason),
+        }
+    }
 }
 
 pub trait TransactionMap {
     fn insert_transaction(
         &mut self,
+        policy_options: &ZzPolicyOptions,
         transaction: ZzTx,
         balance: Option<&ZzClientBalance>,
-    ) -> Option<ZzTxEffect>;
+    ) -> Outcome;
+
+    /// Moves the clock used by the dispute windows forward (it never goes back) and returns a
+    /// resolve for each dispute that has been open for longer than the resolve deadline. The
+    /// resolves must be inserted like any other transaction so their effects are emitted
+    fn advance_clock(&mut self, policy_options: &ZzPolicyOptions, now: u64) -> Vec<ZzTx>;
+
+    /// A resolve for each dispute of the client that is past the resolve deadline, oldest first.
+    /// advance_clock returned them once, they're only still open if their resolve was rejected
+    /// because the account was locked
+    fn overdue_disputes(
+        &mut self,
+        policy_options: &ZzPolicyOptions,
+        client_id: ClientId,
+    ) -> Vec<ZzTx>;
+
+    /// The tx ids of the client's transactions that are currently disputed
+    fn open_disputes(&self, client_id: ClientId) -> Vec<TxId>;
+
+    /// The transactions that hold funds (open disputes and authorizations) and how much each
+    /// one holds, sorted by client and tx id
+    fn holds(&self) -> Vec<(ClientId, TxId, ZzUAmount)>;
+
+    /// How many transactions were dropped from the map, see ZzPolicyOptions::evict_finalized and
+    /// ZzPolicyOptions::eviction_horizon
+    fn evicted(&self) -> u64;
+
+    /// Writes every transaction and the dispute clock, see read_checkpoint
+    fn write_checkpoint(&self, w: &mut dyn Write) -> io::Result<()>;
+
+    /// Restores what write_checkpoint wrote into an empty map
+    ///
+    /// # Errors
+    ///
+    /// Failed to read or the bytes weren't written by write_checkpoint
+    fn read_checkpoint(&mut self, r: &mut dyn Read) -> io::Result<()>;
+
+    /// The first IO error of the store since the last call, see TransactionStore::take_error.
+    /// The map may have lost transactions then
+    fn take_error(&mut self) -> io::Result<()>;
+}
+
+/// The state of a transaction and the clock values of when it was inserted and last disputed.
+/// For a deposit disputed in portions it's when the first open portion was disputed
+#[derive(Clone)]
+pub struct TransactionEntry {
+    state: TransactionState,
+    created_at: u64,
+    disputed_at: u64,
+}
+
+impl TransactionEntry {
+    pub fn write(&self, w: &mut (impl Write + ?Sized)) -> io::Result<()> {
+        write_u64(w, self.created_at)?;
+        write_u64(w, self.disputed_at)?;
+
+        match &self.state {
+            TransactionState::Deposit(amount) => {
+                write_u8(w, 0)?;
+                write_u_amount(w, amount)
+            }
+            TransactionState::Withdrawal(amount) => {
+                write_u8(w, 1)?;
+                write_u_amount(w, amount)
+            }
+            TransactionState::Dispute {
+                amount,
+                held,
+                receivable,
+            } => {
+                write_u8(w, 2)?;
+                write_u_amount(w, amount)?;
+                write_u_amount(w, held)?;
+                write_u_amount(w, receivable)
+            }
+            TransactionState::WithdrawalDispute(amount) => {
+                write_u8(w, 3)?;
+                write_u_amount(w, amount)
+            }
+            TransactionState::Locked => write_u8(w, 4),
+            TransactionState::WithdrawalChargedBack => write_u8(w, 5),
+            TransactionState::Administrative => write_u8(w, 6),
+            TransactionState::Authorized(amount) => {
+                write_u8(w, 7)?;
+                write_u_amount(w, amount)
+            }
+            TransactionState::Captured { remainder } => {
+                write_u8(w, 8)?;
+                write_u_amount(w, remainder)
+            }
+            TransactionState::Voided => write_u8(w, 9),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// The bytes weren't written by TransactionEntry::write
+    pub fn read(r: &mut (impl Read + ?Sized)) -> io::Result<Self> {
+        let created_at = read_u64(r)?;
+        let disputed_at = read_u64(r)?;
+
+        let state = match read_u8(r)? {
+            0 => TransactionState::Deposit(read_u_amount(r)?),
+            1 => TransactionState::Withdrawal(read_u_amount(r)?),
+            2 => TransactionState::Dispute {
+                amount: read_u_amount(r)?,
+                held: read_u_amount(r)?,
+                receivable: read_u_amount(r)?,
+            },
+            3 => TransactionState::WithdrawalDispute(read_u_amount(r)?),
+            4 => TransactionState::Locked,
+            5 => TransactionState::WithdrawalChargedBack,
+            6 => TransactionState::Administrative,
+            7 => TransactionState::Authorized(read_u_amount(r)?),
+            8 => TransactionState::Captured {
+                remainder: read_u_amount(r)?,
+            },
+            9 => TransactionState::Voided,
+            tag => return Err(invalid_data(format!("Invalid transaction state {tag}"))),
+        };
+
+        Ok(Self {
+            state,
+            created_at,
+            disputed_at,
+        })
+    }
+}
+
+/// Where TransactionMapImpl keeps the entry of each transaction
+pub trait TransactionStore {
+    /// Takes &mut self so the store may load the entry into memory. The dispute clock
+    /// (disputed_at) is only kept while the transaction is disputed
+    fn get(&mut self, key: &(ClientId, TxId)) -> Option<TransactionEntry>;
+
+    fn insert(&mut self, key: (ClientId, TxId), entry: TransactionEntry);
+
+    /// Does nothing if there's no entry for the key
+    fn remove(&mut self, key: &(ClientId, TxId));
+
+    /// Calls f once for every transaction with its latest entry, in no particular order
+    fn for_each(&self, f: impl FnMut(&(ClientId, TxId), &TransactionEntry));
+
+    /// The first IO error of a store that keeps its entries on disk, the other methods can't
+    /// return it so it's kept until this is called
+    fn take_error(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
-/// This is an implementation of the transaction map using a hashmap
+/// This is an implementation of the transaction map using a hashmap, see HashMapStore
+pub type TransactionHashMapImpl = TransactionMapImpl<hash_map_store::HashMapStore>;
+
+/// This is an implementation of the transaction map that spills to disk, see DiskStore
+pub type TransactionDiskMapImpl = TransactionMapImpl<disk_store::DiskStore>;
+
+/// The transaction map over a TransactionStore, only the disputes are tracked in memory apart
+/// from the store
+///
+/// A transfer is stored as a withdrawal of the source and a deposit of the destination with the
+/// same tx id, each side can be disputed by its own client. Withdrawals are only stored if they
+/// can be disputed (see ZzPolicyOptions::allow_withdrawal_disputes), nothing else references them
 #[derive(Default)]
-pub struct TransactionHashMapImpl {
-    map: HashMap<(ClientId, TxId), TransactionState>,
+pub struct TransactionMapImpl<S> {
+    map: S,
+    /// The disputes in the order they were opened, only tracked if there's a resolve deadline.
+    /// Entries aren't removed when the dispute is closed, they're checked against the map instead
+    open_disputes: VecDeque<(u64, ClientId, TxId)>,
+    /// The disputed transactions of each client
+    disputed: HashMap<ClientId, HashSet<TxId>>,
+    /// The deposits and withdrawals in the order they were inserted, only tracked if there's an
+    /// eviction horizon. Like open_disputes they're checked against the map when they expire
+    expiring: VecDeque<(u64, ClientId, TxId)>,
+    /// How many transactions were dropped, see ZzPolicyOptions::evict_finalized
+    evicted: u64,
+    now: u64,
+}
+
+impl<S> TransactionMapImpl<S> {
+    pub fn with_store(map: S) -> Self {
+        Self {
+            map,
+            open_disputes: VecDeque::new(),
+            disputed: HashMap::new(),
+            expiring: VecDeque::new(),
+            evicted: 0,
+            now: 0,
+        }
+    }
+}
+
+impl<S: TransactionStore> TransactionMapImpl<S> {
+    /// Stores the entry, or drops it if the policy evicts its state
+    fn store(
+        &mut self,
+        policy_options: &ZzPolicyOptions,
+        key: (ClientId, TxId),
+        entry: TransactionEntry,
+        existed: bool,
+    ) {
+        let is_finalized = match &entry.state {
+            TransactionState::Locked
+            | TransactionState::WithdrawalChargedBack
+            | TransactionState::Administrative
+            | TransactionState::Voided => true,
+            TransactionState::Captured { remainder } => remainder.is_zero(),
+            _ => false,
+        };
+        let is_settled = matches!(
+            entry.state,
+            TransactionState::Deposit(_) | TransactionState::Withdrawal(_)
+        );
+
+        if policy_options.evict_finalized && is_finalized
+            || is_settled
+                && policy_options
+                    .eviction_horizon
+                    .is_some_and(|horizon| self.now - entry.created_at > horizon)
+        {
+            if existed {
+                self.map.remove(&key);
+            }
+            self.evicted += 1;
+            return;
+        }
+
+        if is_settled && !existed && policy_options.eviction_horizon.is_some() {
+            self.expiring.push_back((entry.created_at, key.0, key.1));
+        }
+        self.map.insert(key, entry);
+    }
 }
 
-impl TransactionMap for TransactionHashMapImpl {
+impl<S: TransactionStore> TransactionMap for TransactionMapImpl<S> {
     fn insert_transaction(
         &mut self,
+        policy_options: &ZzPolicyOptions,
         transaction: ZzTx,
         balance: Option<&ZzClientBalance>,
-    ) -> Option<ZzTxEffect> {
+    ) -> Outcome {
         let tx_id = transaction.tx_id;
         let client_id = transaction.client_id;
 
-        let (state, effect) =
-            produce_effect(self.map.get(&(client_id, tx_id)), transaction, balance)?;
+        if let ZzTxType::Transfer { to, .. } = &transaction.r#type
+            && self.map.get(&(*to, tx_id)).is_some()
+        {
+            return Outcome::Rejected(RejectReason::DuplicateTx);
+        }
+
+        let cur = self.map.get(&(client_id, tx_id));
+        let is_dispute = matches!(transaction.r#type, ZzTxType::Dispute(_));
+
+        if is_dispute
+            && let (Some(window), Some(cur)) = (policy_options.dispute_window, &cur)
+            && self.now - cur.created_at > window
+        {
+            return Outcome::Rejected(RejectReason::DisputeWindowClosed);
+        }
+
+        let created_at = cur.as_ref().map_or(self.now, |x| x.created_at);
+        let mut disputed_at = cur.as_ref().map_or(0, |x| x.disputed_at);
+        // the portions of a deposit are settled together, so they share the clock of the first
+        let was_disputed = cur
+            .as_ref()
+            .is_some_and(|x| matches!(x.state, TransactionState::Dispute { .. }));
+        let (state, effect) = match produce_effect(
+            policy_options,
+            cur.as_ref().map(|x| &x.state),
+            transaction,
+            balance,
+        ) {
+            Outcome::Applied(applied) => applied,
+            Outcome::Rejected(reason) => return Outcome::Rejected(reason),
+        };
+
+        if is_dispute && !was_disputed {
+            disputed_at = self.now;
+            if policy_options.dispute_resolve_deadline.is_some() {
+                self.open_disputes.push_back((self.now, client_id, tx_id));
+            }
+        }
+
+        if matches!(
+            state,
+            TransactionState::Dispute { .. } | TransactionState::WithdrawalDispute(_)
+        ) {
+            self.disputed.entry(client_id).or_default().insert(tx_id);
+        } else if let Some(disputed) = self.disputed.get_mut(&client_id) {
+            disputed.remove(&tx_id);
+            if disputed.is_empty() {
+                self.disputed.remove(&client_id);
+            }
+        }
+
+        if policy_options.allow_withdrawal_disputes
+            || !matches!(state, TransactionState::Withdrawal(_))
+        {
+            self.store(
+                policy_options,
+                (client_id, tx_id),
+                TransactionEntry {
+                    state,
+                    created_at,
+                    disputed_at,
+                },
+                cur.is_some(),
+            );
+        }
+        if let Some(to) = effect.counterparty {
+            self.store(
+                policy_options,
+                (to, tx_id),
+                TransactionEntry {
+                    state: TransactionState::Deposit(effect.amount.clone()),
+                    created_at,
+                    disputed_at: 0,
+                },
+                false,
+            );
+        }
+        Outcome::Applied(effect)
+    }
+
+    fn advance_clock(&mut self, policy_options: &ZzPolicyOptions, now: u64) -> Vec<ZzTx> {
+        self.now = self.now.max(now);
+
+        if let Some(horizon) = policy_options.eviction_horizon {
+            while let Some(&(created_at, client_id, tx_id)) = self.expiring.front()
+                && self.now - created_at > horizon
+            {
+                self.expiring.pop_front();
+
+                // the transaction may be disputed or already evicted
+                if self.map.get(&(client_id, tx_id)).is_some_and(|entry| {
+                    entry.created_at == created_at
+                        && matches!(
+                            entry.state,
+                            TransactionState::Deposit(_) | TransactionState::Withdrawal(_)
+                        )
+                }) {
+                    self.map.remove(&(client_id, tx_id));
+                    self.evicted += 1;
+                }
+            }
+        }
+
+        let Some(deadline) = policy_options.dispute_resolve_deadline else {
+            return vec![];
+        };
+
+        let mut expired = vec![];
+        while let Some(&(disputed_at, client_id, tx_id)) = self.open_disputes.front()
+            && self.now - disputed_at > deadline
+        {
+            self.open_disputes.pop_front();
+
+            // the dispute may have been closed or disputed again since
+            if self.map.get(&(client_id, tx_id)).is_some_and(|entry| {
+                entry.disputed_at == disputed_at
+                    && matches!(
+                        entry.state,
+                        TransactionState::Dispute { .. } | TransactionState::WithdrawalDispute(_)
+                    )
+            }) {
+                expired.push(ZzTx {
+                    r#type: ZzTxType::Resolve,
+                    client_id,
+                    tx_id,
+                    timestamp: None,
+                });
+            }
+        }
+
+        expired
+    }
+
+    fn overdue_disputes(
+        &mut self,
+        policy_options: &ZzPolicyOptions,
+        client_id: ClientId,
+    ) -> Vec<ZzTx> {
+        let Some(deadline) = policy_options.dispute_resolve_deadline else {
+            return vec![];
+        };
+
+        let mut overdue: Vec<_> = self
+            .open_disputes(client_id)
+            .into_iter()
+            .filter_map(|tx_id| {
+                let entry = self.map.get(&(client_id, tx_id))?;
+                (self.now - entry.disputed_at > deadline).then_some((entry.disputed_at, tx_id))
+            })
+            .collect();
+        overdue.sort_unstable();
+
+        overdue
+            .into_iter()
+            .map(|(_, tx_id)| ZzTx {
+                r#type: ZzTxType::Resolve,
+                client_id,
+                tx_id,
+                timestamp: None,
+            })
+            .collect()
+    }
+
+    fn open_disputes(&self, client_id: ClientId) -> Vec<TxId> {
+        self.disputed
+            .get(&client_id)
+            .map(|disputed| disputed.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    fn holds(&self) -> Vec<(ClientId, TxId, ZzUAmount)> {
+        let mut holds = vec![];
+        self.map.for_each(|&(client_id, tx_id), entry| {
+            let held = match &entry.state {
+                TransactionState::Dispute { held, .. } => held,
+                TransactionState::WithdrawalDispute(held) => held,
+                TransactionState::Authorized(held) => held,
+                TransactionState::Captured { remainder } => remainder,
+                _ => return,
+            };
+            if !held.is_zero() {
+                holds.push((client_id, tx_id, held.clone()));
+            }
+        });
+        holds.sort_by_key(|&(client_id, tx_id, _)| (client_id, tx_id));
+        holds
+    }
+
+    fn write_checkpoint(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u64(w, self.now)?;
+
+        // each entry is prefixed by 1 and the last one is followed by 0
+        let mut result = Ok(());
+        self.map.for_each(|&(client_id, tx_id), entry| {
+            if result.is_ok() {
+                result = write_u8(w, 1)
+                    .and_then(|_| write_u64(w, client_id))
+                    .and_then(|_| write_u32(w, tx_id))
+                    .and_then(|_| entry.write(w));
+            }
+        });
+        result?;
+        write_u8(w, 0)?;
+
+        write_u64(w, self.open_disputes.len() as u64)?;
+        for &(disputed_at, client_id, tx_id) in &self.open_disputes {
+            write_u64(w, disputed_at)?;
+            write_u64(w, client_id)?;
+            write_u32(w, tx_id)?;
+        }
+
+        write_u64(w, self.expiring.len() as u64)?;
+        for &(created_at, client_id, tx_id) in &self.expiring {
+            write_u64(w, created_at)?;
+            write_u64(w, client_id)?;
+            write_u32(w, tx_id)?;
+        }
+        write_u64(w, self.evicted)?;
+
+        Ok(())
+    }
+
+    fn read_checkpoint(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.now = read_u64(r)?;
+
+        while read_u8(r)? == 1 {
+            let client_id = read_u64(r)?;
+            let tx_id = read_u32(r)?;
+            let entry = TransactionEntry::read(r)?;
 
-        self.map.insert((client_id, tx_id), state);
-        Some(effect)
+            if matches!(
+                entry.state,
+                TransactionState::Dispute { .. } | TransactionState::WithdrawalDispute(_)
+            ) {
+                self.disputed.entry(client_id).or_default().insert(tx_id);
+            }
+            self.map.insert((client_id, tx_id), entry);
+        }
+
+        for _ in 0..read_u64(r)? {
+            self.open_disputes
+                .push_back((read_u64(r)?, read_u64(r)?, read_u32(r)?));
+        }
+
+        for _ in 0..read_u64(r)? {
+            self.expiring
+                .push_back((read_u64(r)?, read_u64(r)?, read_u32(r)?));
+        }
+        self.evicted = read_u64(r)?;
+
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> io::Result<()> {
+        self.map.take_error()
+    }
+}
+
+/// How much of a disputed portion of a deposit is held and how much becomes a receivable,
+/// according to NegativeBalanceOptions. None if the dispute is rejected
+fn hold_disputed(
+    policy_options: &ZzPolicyOptions,
+    balance_available: Option<&ZzIAmount>,
+    portion: &ZzUAmount,
+) -> Option<(ZzUAmount, ZzUAmount)> {
+    match policy_options.on_negative_balance {
+        NegativeBalanceOptions::Allow => Some((portion.clone(), ZzUAmount::zero())),
+        NegativeBalanceOptions::Reject => balance_available
+            .is_some_and(|available| available.greater_eq_than(portion.clone()))
+            .then(|| (portion.clone(), ZzUAmount::zero())),
+        NegativeBalanceOptions::Cap => {
+            let held = balance_available
+                .map(|available| available.to_u_amount_saturating())
+                .unwrap_or_else(ZzUAmount::zero)
+                .min(portion.clone());
+            let mut receivable = portion.clone();
+            receivable.sub(&held);
+            Some((held, receivable))
+        }
     }
 }
 
 // if an effect is produced, this means the transaction actually went through so it should be
 // inserted and the effect should be returned
 fn produce_effect(
+    policy_options: &ZzPolicyOptions,
     cur: Option<&TransactionState>,
     new: ZzTx,
     balance: Option<&ZzClientBalance>,
-) -> Option<(TransactionState, ZzTxEffect)> {
+) -> Outcome<(TransactionState, ZzTxEffect)> {
     let balance_available = balance.map(|x| &x.available);
 
+    let client_id = new.client_id;
+    let tx_type = new.r#type.discriminant();
+    let charge_fee = |amount: &ZzUAmount| {
+        let (schedule, fee_account) = policy_options.fees()?;
+        schedule
+            .fee(client_id, tx_type, amount, policy_options.fee_rounding)
+            .map(|fee| (fee_account, fee))
+    };
+    // a fee that isn't checked against the available funds is capped at the funds it's taken
+    // from, so it never makes the client owe money
+    let capped_fee = |amount: &ZzUAmount, funds: ZzUAmount| {
+        charge_fee(amount)
+            .map(|(fee_account, fee)| (fee_account, fee.min(funds)))
+            .filter(|(_, fee)| !fee.is_zero())
+    };
+    // the fee has to be covered by the available funds too
+    let with_fee = |amount: &ZzUAmount, fee: &Option<(ClientId, ZzUAmount)>| {
+        let mut amount = amount.clone();
+        if let Some((_, fee)) = fee {
+            amount.add(fee);
+        }
+        amount
+    };
+
     if let Some(cur) = cur {
         match (cur, new.r#type) {
-            (TransactionState::Deposit(zz_uint), ZzTxType::Dispute) => Some((
-                TransactionState::Dispute(zz_uint.clone()),
-                ZzTxEffect {
-                    amount: zz_uint.clone(),
-                    available: Some(false),
-                    held: Some(true),
-                    locked: false,
+            (TransactionState::Deposit(zz_uint), ZzTxType::Dispute(portion)) => {
+                let portion = portion.unwrap_or_else(|| zz_uint.clone());
+                if portion.is_zero() || portion > *zz_uint {
+                    return Outcome::Rejected(RejectReason::InvalidAmount);
+                }
+                let Some((held, receivable)) =
+                    hold_disputed(policy_options, balance_available, &portion)
+                else {
+                    return Outcome::Rejected(RejectReason::NegativeBalance);
+                };
+
+                Outcome::Applied((
+                    TransactionState::Dispute {
+                        amount: zz_uint.clone(),
+                        held: held.clone(),
+                        receivable: receivable.clone(),
+                    },
+                    ZzTxEffect {
+                        amount: held,
+                        available: Some(false),
+                        held: Some(true),
+                        locked: None,
+                        receivable: (!receivable.is_zero()).then_some((true, receivable)),
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            // another portion of a deposit that's already under dispute
+            (
+                TransactionState::Dispute {
+                    amount,
+                    held,
+                    receivable,
                 },
-            )),
-            (TransactionState::Dispute(zz_uint), ZzTxType::Resolve) => Some((
-                TransactionState::Deposit(zz_uint.clone()),
+                ZzTxType::Dispute(portion),
+            ) => {
+                let mut remainder = amount.clone();
+                remainder.sub(held);
+                remainder.sub(receivable);
+
+                let portion = portion.unwrap_or_else(|| remainder.clone());
+                if portion.is_zero() || portion > remainder {
+                    return Outcome::Rejected(RejectReason::InvalidAmount);
+                }
+                let Some((new_held, new_receivable)) =
+                    hold_disputed(policy_options, balance_available, &portion)
+                else {
+                    return Outcome::Rejected(RejectReason::NegativeBalance);
+                };
+
+                let mut held = held.clone();
+                held.add(&new_held);
+                let mut receivable = receivable.clone();
+                receivable.add(&new_receivable);
+
+                Outcome::Applied((
+                    TransactionState::Dispute {
+                        amount: amount.clone(),
+                        held,
+                        receivable,
+                    },
+                    ZzTxEffect {
+                        amount: new_held,
+                        available: Some(false),
+                        held: Some(true),
+                        locked: None,
+                        receivable: (!new_receivable.is_zero()).then_some((true, new_receivable)),
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            (
+                TransactionState::Dispute {
+                    amount,
+                    held,
+                    receivable,
+                },
+                ZzTxType::Resolve,
+            ) => Outcome::Applied((
+                TransactionState::Deposit(amount.clone()),
                 ZzTxEffect {
-                    amount: zz_uint.clone(),
+                    amount: held.clone(),
                     available: Some(true),
                     held: Some(false),
-                    locked: false,
+                    locked: None,
+                    receivable: (!receivable.is_zero()).then(|| (false, receivable.clone())),
+                    counterparty: None,
+                    fee: None,
                 },
             )),
-            (TransactionState::Dispute(zz_uint), ZzTxType::Chargeback) => Some((
-                TransactionState::Locked,
-                ZzTxEffect {
-                    amount: zz_uint.clone(),
-                    available: None,
-                    held: Some(false),
-                    locked: true,
+            // the receivable is kept, the client still owes it
+            (
+                TransactionState::Dispute {
+                    held, receivable, ..
                 },
-            )),
-            _ => None,
+                ZzTxType::Chargeback,
+            ) => {
+                // the fee is charged on the whole disputed amount, out of the available funds
+                let mut disputed = held.clone();
+                disputed.add(receivable);
+                let available =
+                    balance_available.map_or_else(ZzUAmount::zero, |x| x.to_u_amount_saturating());
+
+                Outcome::Applied((
+                    TransactionState::Locked,
+                    ZzTxEffect {
+                        amount: held.clone(),
+                        available: None,
+                        held: Some(false),
+                        locked: Some(true),
+                        receivable: None,
+                        counterparty: None,
+                        fee: capped_fee(&disputed, available),
+                    },
+                ))
+            }
+            // withdrawals can only be disputed as a whole
+            (TransactionState::Withdrawal(zz_uint), ZzTxType::Dispute(None))
+                if policy_options.allow_withdrawal_disputes =>
+            {
+                Outcome::Applied((
+                    TransactionState::WithdrawalDispute(zz_uint.clone()),
+                    ZzTxEffect {
+                        amount: zz_uint.clone(),
+                        available: None,
+                        held: Some(true),
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            (TransactionState::WithdrawalDispute(zz_uint), ZzTxType::Resolve) => {
+                Outcome::Applied((
+                    TransactionState::Withdrawal(zz_uint.clone()),
+                    ZzTxEffect {
+                        amount: zz_uint.clone(),
+                        available: None,
+                        held: Some(false),
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            (TransactionState::Authorized(zz_uint), ZzTxType::Capture(amount)) => {
+                let amount = amount.unwrap_or_else(|| zz_uint.clone());
+                if amount.is_zero() || amount > *zz_uint {
+                    return Outcome::Rejected(RejectReason::InvalidAmount);
+                }
+                let mut remainder = zz_uint.clone();
+                remainder.sub(&amount);
+
+                Outcome::Applied((
+                    TransactionState::Captured { remainder },
+                    ZzTxEffect {
+                        amount,
+                        available: None,
+                        held: Some(false),
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            (TransactionState::Authorized(zz_uint), ZzTxType::Void)
+            | (TransactionState::Captured { remainder: zz_uint }, ZzTxType::Void)
+                if !zz_uint.is_zero() =>
+            {
+                Outcome::Applied((
+                    TransactionState::Voided,
+                    ZzTxEffect {
+                        amount: zz_uint.clone(),
+                        available: Some(true),
+                        held: Some(false),
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            // the client is the one being refunded, so the account isn't locked
+            (TransactionState::WithdrawalDispute(zz_uint), ZzTxType::Chargeback) => {
+                Outcome::Applied((
+                    TransactionState::WithdrawalChargedBack,
+                    ZzTxEffect {
+                        amount: zz_uint.clone(),
+                        available: Some(true),
+                        held: Some(false),
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: capped_fee(zz_uint, zz_uint.clone()),
+                    },
+                ))
+            }
+            (_, r#type) if !r#type.references_tx() => Outcome::Rejected(RejectReason::DuplicateTx),
+            (TransactionState::Locked | TransactionState::WithdrawalChargedBack, _) => {
+                Outcome::Rejected(RejectReason::AlreadyChargedBack)
+            }
+            (_, ZzTxType::Resolve | ZzTxType::Chargeback) => {
+                Outcome::Rejected(RejectReason::NotDisputed)
+            }
+            _ => Outcome::Rejected(RejectReason::InvalidReference),
         }
     } else {
         match new.r#type {
             ZzTxType::Withdrawal(zz_uint) => {
+                let fee = charge_fee(&zz_uint);
+
                 if balance_available
-                    .is_some_and(|available| available.greater_eq_than(zz_uint.clone()))
+                    .is_some_and(|available| available.greater_eq_than(with_fee(&zz_uint, &fee)))
                 {
-                    Some((
-                        TransactionState::Withdrawal,
+                    Outcome::Applied((
+                        TransactionState::Withdrawal(zz_uint.clone()),
                         ZzTxEffect {
                             amount: zz_uint,
                             available: Some(false),
                             held: None,
-                            locked: false,
+                            locked: None,
+                            receivable: None,
+                            counterparty: None,
+                            fee,
                         },
                     ))
                 } else {
-                    None
+                    Outcome::Rejected(RejectReason::InsufficientFunds)
                 }
             }
-            ZzTxType::Deposit(zz_uint) => Some((
+            ZzTxType::Authorize(zz_uint) => {
+                if zz_uint.is_zero() {
+                    return Outcome::Rejected(RejectReason::InvalidAmount);
+                }
+                if !balance_available
+                    .is_some_and(|available| available.greater_eq_than(zz_uint.clone()))
+                {
+                    return Outcome::Rejected(RejectReason::InsufficientFunds);
+                }
+
+                Outcome::Applied((
+                    TransactionState::Authorized(zz_uint.clone()),
+                    ZzTxEffect {
+                        amount: zz_uint,
+                        available: Some(false),
+                        held: Some(true),
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            ZzTxType::Deposit(zz_uint) => Outcome::Applied((
                 TransactionState::Deposit(zz_uint.clone()),
                 ZzTxEffect {
                     amount: zz_uint.clone(),
                     available: Some(true),
                     held: None,
-                    locked: false,
+                    locked: None,
+                    receivable: None,
+                    counterparty: None,
+                    fee: capped_fee(&zz_uint, zz_uint.clone()),
                 },
             )),
-            _ => None,
+            ZzTxType::Transfer { to, amount } => {
+                let fee = charge_fee(&amount);
+
+                if to == new.client_id {
+                    return Outcome::Rejected(RejectReason::SelfTransfer);
+                }
+
+                if balance_available
+                    .is_some_and(|available| available.greater_eq_than(with_fee(&amount, &fee)))
+                {
+                    Outcome::Applied((
+                        TransactionState::Withdrawal(amount.clone()),
+                        ZzTxEffect {
+                            amount,
+                            available: Some(false),
+                            held: None,
+                            locked: None,
+                            receivable: None,
+                            counterparty: Some(to),
+                            fee,
+                        },
+                    ))
+                } else {
+                    Outcome::Rejected(RejectReason::InsufficientFunds)
+                }
+            }
+            ZzTxType::Freeze | ZzTxType::Unlock if policy_options.allow_admin_transactions => {
+                Outcome::Applied((
+                    TransactionState::Administrative,
+                    ZzTxEffect {
+                        amount: ZzUAmount::zero(),
+                        available: None,
+                        held: None,
+                        locked: Some(matches!(new.r#type, ZzTxType::Freeze)),
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            ZzTxType::Adjustment { amount, .. } if policy_options.allow_admin_transactions => {
+                let increase = !amount.is_negative();
+                Outcome::Applied((
+                    TransactionState::Administrative,
+                    ZzTxEffect {
+                        amount: amount.abs(),
+                        available: Some(increase),
+                        held: None,
+                        locked: None,
+                        receivable: None,
+                        counterparty: None,
+                        fee: None,
+                    },
+                ))
+            }
+            ZzTxType::Freeze | ZzTxType::Unlock | ZzTxType::Adjustment { .. } => {
+                Outcome::Rejected(RejectReason::Unauthorized)
+            }
+            _ => Outcome::Rejected(RejectReason::UnknownTx),
         }
     }
 }
@@ -180,7 +1227,9 @@ fn produce_effect(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ClientIdOptions;
     use crate::common::zz_amount::{ZzIAmount, ZzUAmount};
+    use crate::domain::{client_store::ZzClientNames, fee::ZzFeeSchedule};
 
     fn make_amount(val: u64) -> ZzUAmount {
         ZzUAmount::new(val.into(), 0).unwrap()
@@ -191,6 +1240,7 @@ mod tests {
             r#type: ZzTxType::Deposit(make_amount(amount)),
             client_id,
             tx_id,
+            timestamp: None,
         }
     }
 
@@ -199,14 +1249,16 @@ mod tests {
             r#type: ZzTxType::Withdrawal(make_amount(amount)),
             client_id,
             tx_id,
+            timestamp: None,
         }
     }
 
     fn make_dispute_tx(client_id: ClientId, tx_id: TxId) -> ZzTx {
         ZzTx {
-            r#type: ZzTxType::Dispute,
+            r#type: ZzTxType::Dispute(None),
             client_id,
             tx_id,
+            timestamp: None,
         }
     }
 
@@ -215,6 +1267,7 @@ mod tests {
             r#type: ZzTxType::Resolve,
             client_id,
             tx_id,
+            timestamp: None,
         }
     }
 
@@ -223,6 +1276,7 @@ mod tests {
             r#type: ZzTxType::Chargeback,
             client_id,
             tx_id,
+            timestamp: None,
         }
     }
 
@@ -230,25 +1284,31 @@ mod tests {
     fn test_insert_deposit() {
         let mut map = TransactionHashMapImpl {
             map: Default::default(),
+            ..Default::default()
         };
         let tx = make_deposit_tx(1, 100, 50);
 
-        let effect = map.insert_transaction(tx, None).unwrap();
+        let effect = map
+            .insert_transaction(&Default::default(), tx, None)
+            .applied()
+            .unwrap();
         assert_eq!(effect.amount.to_string(), "50");
         assert_eq!(effect.available, Some(true));
         assert_eq!(effect.held, None);
-        assert!(!effect.locked);
+        assert_eq!(effect.locked, None);
     }
 
     #[test]
     fn test_insert_withdraw() {
         let mut map = TransactionHashMapImpl {
             map: Default::default(),
+            ..Default::default()
         };
 
         let tx = make_withdraw_tx(1, 101, 30);
         let effect = map
             .insert_transaction(
+                &Default::default(),
                 tx,
                 Some(&ZzClientBalance {
                     client_id: 1,
@@ -256,91 +1316,1310 @@ mod tests {
                     held: ZzIAmount::zero(),
                     total: ZzIAmount::zero(),
                     locked: false,
+                    receivable: ZzIAmount::zero(),
+                    locked_by: None,
                 }),
             )
+            .applied()
             .unwrap();
         assert_eq!(effect.amount.to_string(), "30");
         assert_eq!(effect.available, Some(false));
         assert_eq!(effect.held, None);
-        assert!(!effect.locked);
+        assert_eq!(effect.locked, None);
     }
 
     #[test]
     fn test_insert_dispute_resolve_chargeback() {
         let mut map = TransactionHashMapImpl {
             map: Default::default(),
+            ..Default::default()
         };
 
         // Deposit first
         let deposit_tx = make_deposit_tx(1, 200, 100);
-        map.insert_transaction(deposit_tx, None).unwrap();
+        map.insert_transaction(&Default::default(), deposit_tx, None)
+            .applied()
+            .unwrap();
 
         // Dispute
         let dispute_tx = make_dispute_tx(1, 200);
-        let effect = map.insert_transaction(dispute_tx, None).unwrap();
+        let effect = map
+            .insert_transaction(&Default::default(), dispute_tx, None)
+            .applied()
+            .unwrap();
         assert_eq!(effect.available, Some(false));
         assert_eq!(effect.held, Some(true));
-        assert!(!effect.locked);
+        assert_eq!(effect.locked, None);
 
         // Resolve
         let resolve_tx = make_resolve_tx(1, 200);
-        let effect = map.insert_transaction(resolve_tx, None).unwrap();
+        let effect = map
+            .insert_transaction(&Default::default(), resolve_tx, None)
+            .applied()
+            .unwrap();
         assert_eq!(effect.available, Some(true));
         assert_eq!(effect.held, Some(false));
-        assert!(!effect.locked);
+        assert_eq!(effect.locked, None);
 
         // Dispute again
         let dispute_tx = make_dispute_tx(1, 200);
-        map.insert_transaction(dispute_tx, None).unwrap();
+        map.insert_transaction(&Default::default(), dispute_tx, None)
+            .applied()
+            .unwrap();
 
         // Chargeback
         let chargeback_tx = make_chargeback_tx(1, 200);
-        let effect = map.insert_transaction(chargeback_tx, None).unwrap();
+        let effect = map
+            .insert_transaction(&Default::default(), chargeback_tx, None)
+            .applied()
+            .unwrap();
         assert_eq!(effect.available, None);
         assert_eq!(effect.held, Some(false));
-        assert!(effect.locked);
+        assert_eq!(effect.locked, Some(true));
     }
 
     #[test]
     fn test_invalid_dispute_or_resolve_on_nonexistent_tx() {
         let mut map = TransactionHashMapImpl {
             map: Default::default(),
+            ..Default::default()
         };
 
         // No prior transaction exists
         let dispute_tx = make_dispute_tx(1, 300);
-        assert!(map.insert_transaction(dispute_tx, None).is_none());
+        assert_eq!(
+            map.insert_transaction(&Default::default(), dispute_tx, None)
+                .rejected(),
+            Some(RejectReason::UnknownTx)
+        );
 
         let resolve_tx = make_resolve_tx(1, 300);
-        assert!(map.insert_transaction(resolve_tx, None).is_none());
+        assert_eq!(
+            map.insert_transaction(&Default::default(), resolve_tx, None)
+                .rejected(),
+            Some(RejectReason::UnknownTx)
+        );
 
         let chargeback_tx = make_chargeback_tx(1, 300);
-        assert!(map.insert_transaction(chargeback_tx, None).is_none());
+        assert_eq!(
+            map.insert_transaction(&Default::default(), chargeback_tx, None)
+                .rejected(),
+            Some(RejectReason::UnknownTx)
+        );
     }
 
     #[test]
     fn test_tx_with_wrong_client_id_produces_no_effect() {
         let mut map = TransactionHashMapImpl {
             map: Default::default(),
+            ..Default::default()
         };
 
         // Client 1 deposits
         let deposit_tx = make_deposit_tx(1, 400, 100);
-        map.insert_transaction(deposit_tx, None).unwrap();
+        map.insert_transaction(&Default::default(), deposit_tx, None)
+            .applied()
+            .unwrap();
 
         // Client 1 disputes
         let dispute_tx = make_dispute_tx(1, 400);
-        map.insert_transaction(dispute_tx, None).unwrap();
+        map.insert_transaction(&Default::default(), dispute_tx, None)
+            .applied()
+            .unwrap();
 
         // Now a chargeback arrives but with the wrong client_id (2 instead of 1)
         let chargeback_tx = make_chargeback_tx(2, 400);
-        let effect = map.insert_transaction(chargeback_tx, None);
+        let effect = map.insert_transaction(&Default::default(), chargeback_tx, None);
 
         // Because client_id mismatches, nothing should happen
-        assert!(effect.is_none());
+        assert_eq!(effect, Outcome::Rejected(RejectReason::UnknownTx));
 
         // And the transaction map should still only contain the original client_id entry
-        assert!(map.map.contains_key(&(1, 400)));
-        assert!(!map.map.contains_key(&(2, 400)));
+        assert!(map.map.contains(&(1, 400)));
+        assert!(!map.map.contains(&(2, 400)));
+    }
+    fn make_balance(client_id: ClientId, available: i64) -> ZzClientBalance {
+        ZzClientBalance {
+            client_id,
+            available: ZzIAmount::new(available.into(), 0).unwrap(),
+            held: ZzIAmount::zero(),
+            total: ZzIAmount::zero(),
+            locked: false,
+            receivable: ZzIAmount::zero(),
+            locked_by: None,
+        }
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_ignored_by_default() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        map.insert_transaction(
+            &policy,
+            make_withdraw_tx(1, 500, 10),
+            Some(&make_balance(1, 10)),
+        )
+        .applied()
+        .unwrap();
+
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 500), None)
+                .rejected(),
+            Some(RejectReason::UnknownTx)
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_resolve_chargeback() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            allow_withdrawal_disputes: true,
+            ..Default::default()
+        };
+
+        map.insert_transaction(
+            &policy,
+            make_withdraw_tx(1, 600, 10),
+            Some(&make_balance(1, 10)),
+        )
+        .applied()
+        .unwrap();
+
+        // Dispute credits held, available was already debited by the withdrawal
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(1, 600), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "10");
+        assert_eq!(effect.available, None);
+        assert_eq!(effect.held, Some(true));
+        assert_eq!(effect.locked, None);
+
+        // Resolve reverses the hold
+        let effect = map
+            .insert_transaction(&policy, make_resolve_tx(1, 600), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.available, None);
+        assert_eq!(effect.held, Some(false));
+        assert_eq!(effect.locked, None);
+
+        // Chargeback returns the funds to available
+        map.insert_transaction(&policy, make_dispute_tx(1, 600), None)
+            .applied()
+            .unwrap();
+        let effect = map
+            .insert_transaction(&policy, make_chargeback_tx(1, 600), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.available, Some(true));
+        assert_eq!(effect.held, Some(false));
+        assert_eq!(effect.locked, None);
+
+        // A charged back withdrawal can't be disputed again
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 600), None)
+                .rejected(),
+            Some(RejectReason::AlreadyChargedBack)
+        );
+    }
+
+    #[test]
+    fn test_negative_balance_reject() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            on_negative_balance: NegativeBalanceOptions::Reject,
+            ..Default::default()
+        };
+
+        map.insert_transaction(&policy, make_deposit_tx(1, 700, 10), None)
+            .applied()
+            .unwrap();
+
+        // 10 was deposited but 4 were already withdrawn
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 700), Some(&make_balance(1, 6)))
+                .rejected(),
+            Some(RejectReason::NegativeBalance)
+        );
+
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(1, 700), Some(&make_balance(1, 10)))
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "10");
+        assert_eq!(effect.receivable, None);
+    }
+
+    #[test]
+    fn test_negative_balance_cap() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            on_negative_balance: NegativeBalanceOptions::Cap,
+            ..Default::default()
+        };
+
+        map.insert_transaction(&policy, make_deposit_tx(1, 800, 10), None)
+            .applied()
+            .unwrap();
+
+        // only 6 is available so the shortfall of 4 becomes a receivable
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(1, 800), Some(&make_balance(1, 6)))
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "6");
+        assert_eq!(effect.available, Some(false));
+        assert_eq!(effect.held, Some(true));
+        assert_eq!(effect.receivable, Some((true, make_amount(4))));
+
+        // resolve releases the capped hold and clears the receivable
+        let effect = map
+            .insert_transaction(&policy, make_resolve_tx(1, 800), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "6");
+        assert_eq!(effect.available, Some(true));
+        assert_eq!(effect.held, Some(false));
+        assert_eq!(effect.receivable, Some((false, make_amount(4))));
+
+        // the resolved deposit keeps its full amount
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(1, 800), Some(&make_balance(1, 20)))
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "10");
+        assert_eq!(effect.receivable, None);
+
+        // negative available means nothing can be held
+        map.insert_transaction(&policy, make_resolve_tx(1, 800), None)
+            .applied()
+            .unwrap();
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(1, 800), Some(&make_balance(1, -3)))
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "0");
+        assert_eq!(effect.receivable, Some((true, make_amount(10))));
+
+        // chargeback keeps the receivable
+        let effect = map
+            .insert_transaction(&policy, make_chargeback_tx(1, 800), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "0");
+        assert_eq!(effect.receivable, None);
+        assert_eq!(effect.locked, Some(true));
+    }
+
+    #[test]
+    fn test_admin_transactions() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            allow_admin_transactions: true,
+            ..Default::default()
+        };
+
+        let effect = map
+            .insert_transaction(
+                &policy,
+                ZzTx {
+                    r#type: ZzTxType::Freeze,
+                    client_id: 1,
+                    tx_id: 900,
+                    timestamp: None,
+                },
+                None,
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.locked, Some(true));
+        assert_eq!(effect.available, None);
+
+        let effect = map
+            .insert_transaction(
+                &policy,
+                ZzTx {
+                    r#type: ZzTxType::Unlock,
+                    client_id: 1,
+                    tx_id: 901,
+                    timestamp: None,
+                },
+                None,
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.locked, Some(false));
+
+        let effect = map
+            .insert_transaction(
+                &policy,
+                ZzTx {
+                    r#type: ZzTxType::Adjustment {
+                        amount: ZzIAmount::new((-5).into(), 0).unwrap(),
+                        reason: "typo".to_string(),
+                    },
+                    client_id: 1,
+                    tx_id: 902,
+                    timestamp: None,
+                },
+                None,
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "5");
+        assert_eq!(effect.available, Some(false));
+        assert_eq!(effect.locked, None);
+
+        // administrative transactions can't be disputed
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 902), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+    }
+
+    #[test]
+    fn test_admin_transactions_are_restricted_by_default() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        for r#type in [
+            ZzTxType::Freeze,
+            ZzTxType::Unlock,
+            ZzTxType::Adjustment {
+                amount: ZzIAmount::new(5.into(), 0).unwrap(),
+                reason: "bonus".to_string(),
+            },
+        ] {
+            assert_eq!(
+                map.insert_transaction(
+                    &policy,
+                    ZzTx {
+                        r#type,
+                        client_id: 1,
+                        tx_id: 1000,
+                        timestamp: None,
+                    },
+                    None,
+                )
+                .rejected(),
+                Some(RejectReason::Unauthorized)
+            );
+        }
+    }
+
+    fn make_transfer_tx(client_id: ClientId, tx_id: TxId, to: ClientId, amount: u64) -> ZzTx {
+        ZzTx {
+            r#type: ZzTxType::Transfer {
+                to,
+                amount: make_amount(amount),
+            },
+            client_id,
+            tx_id,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        // insufficient funds
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_transfer_tx(1, 1100, 2, 10),
+                Some(&make_balance(1, 9))
+            )
+            .rejected(),
+            Some(RejectReason::InsufficientFunds)
+        );
+        assert!(map.map.is_empty());
+
+        // to itself
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_transfer_tx(1, 1100, 1, 10),
+                Some(&make_balance(1, 10))
+            )
+            .rejected(),
+            Some(RejectReason::SelfTransfer)
+        );
+
+        let effect = map
+            .insert_transaction(
+                &policy,
+                make_transfer_tx(1, 1100, 2, 10),
+                Some(&make_balance(1, 10)),
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount.to_string(), "10");
+        assert_eq!(effect.available, Some(false));
+        assert_eq!(effect.counterparty, Some(2));
+
+        let mut side_effects = effect.side_effects();
+        assert_eq!(side_effects.len(), 1);
+        let (client_id, counterparty_effect) = side_effects.pop().unwrap();
+        assert_eq!(client_id, 2);
+        assert_eq!(counterparty_effect.amount.to_string(), "10");
+        assert_eq!(counterparty_effect.available, Some(true));
+        assert_eq!(counterparty_effect.counterparty, None);
+
+        // the tx id is taken by the destination, the source side is a withdrawal which is only
+        // stored if withdrawal disputes are allowed
+        assert_eq!(
+            map.insert_transaction(&policy, make_deposit_tx(2, 1100, 1), None)
+                .rejected(),
+            Some(RejectReason::DuplicateTx)
+        );
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_transfer_tx(3, 1100, 2, 1),
+                Some(&make_balance(3, 10))
+            )
+            .rejected(),
+            Some(RejectReason::DuplicateTx)
+        );
+
+        // the destination disputes it like a deposit
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(2, 1100), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.available, Some(false));
+        assert_eq!(effect.held, Some(true));
+
+        // the source disputes it like a withdrawal
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 1100), None)
+                .rejected(),
+            Some(RejectReason::UnknownTx)
+        );
+        let withdrawal_disputes = ZzPolicyOptions {
+            allow_withdrawal_disputes: true,
+            ..Default::default()
+        };
+        map.insert_transaction(
+            &withdrawal_disputes,
+            make_transfer_tx(1, 1200, 2, 10),
+            Some(&make_balance(1, 10)),
+        )
+        .applied()
+        .unwrap();
+        let effect = map
+            .insert_transaction(&withdrawal_disputes, make_dispute_tx(1, 1200), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.available, None);
+        assert_eq!(effect.held, Some(true));
+
+        // the source side wasn't stored without withdrawal disputes, so its tx id is free
+        assert!(
+            map.insert_transaction(&policy, make_deposit_tx(1, 1100, 1), None)
+                .applied()
+                .is_some()
+        );
+    }
+
+    fn make_fee_policy() -> ZzPolicyOptions {
+        let schedule = ZzFeeSchedule::from_reader(
+            "type,client,kind,value\n\
+             withdrawal,,flat,1\n\
+             transfer,,percentage,10\n\
+             chargeback,,flat,5\n"
+                .as_bytes(),
+            ClientIdOptions::U16,
+            &ZzClientNames::default(),
+        )
+        .unwrap();
+
+        ZzPolicyOptions {
+            fee_schedule: Some(schedule),
+            fee_account: Some(99),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = make_fee_policy();
+
+        // deposits aren't in the schedule
+        let effect = map
+            .insert_transaction(&policy, make_deposit_tx(1, 1, 20), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.fee, None);
+
+        // the fee must be covered by the available funds
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_withdraw_tx(1, 2, 10),
+                Some(&make_balance(1, 10))
+            )
+            .rejected(),
+            Some(RejectReason::InsufficientFunds)
+        );
+        let effect = map
+            .insert_transaction(
+                &policy,
+                make_withdraw_tx(1, 2, 10),
+                Some(&make_balance(1, 11)),
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.fee, Some((99, make_amount(1))));
+
+        let mut side_effects = effect.side_effects();
+        assert_eq!(side_effects.len(), 1);
+        let (client_id, fee_effect) = side_effects.pop().unwrap();
+        assert_eq!(client_id, 99);
+        assert_eq!(fee_effect.amount, make_amount(1));
+        assert_eq!(fee_effect.available, Some(true));
+
+        // percentage of the transferred amount
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_transfer_tx(1, 3, 2, 10),
+                Some(&make_balance(1, 10))
+            )
+            .rejected(),
+            Some(RejectReason::InsufficientFunds)
+        );
+        let effect = map
+            .insert_transaction(
+                &policy,
+                make_transfer_tx(1, 3, 2, 10),
+                Some(&make_balance(1, 11)),
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.fee, Some((99, make_amount(1))));
+        assert_eq!(
+            effect
+                .side_effects()
+                .into_iter()
+                .map(|(client_id, _)| client_id)
+                .collect::<Vec<_>>(),
+            vec![2, 99]
+        );
+
+        // a chargeback fee is capped at the available funds
+        for (tx_id, available, fee) in [(5, 0, None), (6, 3, Some(3)), (7, 10, Some(5))] {
+            map.insert_transaction(&policy, make_deposit_tx(1, tx_id, 20), None)
+                .applied()
+                .unwrap();
+            map.insert_transaction(&policy, make_dispute_tx(1, tx_id), None)
+                .applied()
+                .unwrap();
+            let effect = map
+                .insert_transaction(
+                    &policy,
+                    make_chargeback_tx(1, tx_id),
+                    Some(&make_balance(1, available)),
+                )
+                .applied()
+                .unwrap();
+            assert_eq!(effect.fee, fee.map(|fee| (99, make_amount(fee))));
+        }
+
+        // a deposit fee is capped at the deposit
+        let policy = ZzPolicyOptions {
+            fee_schedule: Some(
+                ZzFeeSchedule::from_reader(
+                    "type,client,kind,value\ndeposit,,flat,5\n".as_bytes(),
+                    ClientIdOptions::U16,
+                    &ZzClientNames::default(),
+                )
+                .unwrap(),
+            ),
+            ..make_fee_policy()
+        };
+        let effect = map
+            .insert_transaction(&policy, make_deposit_tx(2, 1, 1), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.fee, Some((99, make_amount(1))));
+
+        // nothing is charged without a fee account
+        let policy = ZzPolicyOptions {
+            fee_account: None,
+            ..make_fee_policy()
+        };
+        let effect = map
+            .insert_transaction(
+                &policy,
+                make_withdraw_tx(1, 4, 10),
+                Some(&make_balance(1, 10)),
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.fee, None);
+    }
+
+    #[test]
+    fn test_fee_counterparties() {
+        let policy = make_fee_policy();
+
+        assert_eq!(
+            make_transfer_tx(1, 1, 2, 10).counterparties(&policy),
+            vec![2, 99]
+        );
+        assert_eq!(make_withdraw_tx(1, 1, 10).counterparties(&policy), vec![99]);
+        assert!(make_deposit_tx(1, 1, 10).counterparties(&policy).is_empty());
+        assert_eq!(
+            make_withdraw_tx(1, 1, 10).counterparties(&ZzPolicyOptions::default()),
+            Vec::<ClientId>::new()
+        );
+
+        assert!(make_withdraw_tx(99, 1, 10).involves_fee_account(&policy));
+        assert!(make_transfer_tx(1, 1, 99, 10).involves_fee_account(&policy));
+        assert!(!make_transfer_tx(1, 1, 2, 10).involves_fee_account(&policy));
+        assert!(!make_withdraw_tx(99, 1, 10).involves_fee_account(&Default::default()));
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            dispute_window: Some(10),
+            ..Default::default()
+        };
+
+        map.advance_clock(&policy, 100);
+        map.insert_transaction(&policy, make_deposit_tx(1, 1, 10), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_deposit_tx(1, 2, 10), None)
+            .applied()
+            .unwrap();
+
+        map.advance_clock(&policy, 110);
+        assert!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+                .applied()
+                .is_some()
+        );
+        assert!(
+            map.insert_transaction(&policy, make_resolve_tx(1, 1), None)
+                .applied()
+                .is_some()
+        );
+
+        // the window is measured from the deposit, not the last dispute
+        map.advance_clock(&policy, 111);
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+                .rejected(),
+            Some(RejectReason::DisputeWindowClosed)
+        );
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 2), None)
+                .rejected(),
+            Some(RejectReason::DisputeWindowClosed)
+        );
+
+        // the clock doesn't go back
+        map.advance_clock(&policy, 0);
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 2), None)
+                .rejected(),
+            Some(RejectReason::DisputeWindowClosed)
+        );
+    }
+
+    #[test]
+    fn test_dispute_resolve_deadline() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            dispute_resolve_deadline: Some(5),
+            ..Default::default()
+        };
+
+        for tx_id in 1..=3 {
+            map.insert_transaction(&policy, make_deposit_tx(1, tx_id, 10), None)
+                .applied()
+                .unwrap();
+        }
+
+        map.advance_clock(&policy, 1);
+        map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_dispute_tx(1, 2), None)
+            .applied()
+            .unwrap();
+        map.advance_clock(&policy, 2);
+        map.insert_transaction(&policy, make_dispute_tx(1, 3), None)
+            .applied()
+            .unwrap();
+
+        // closed before the deadline
+        map.insert_transaction(&policy, make_resolve_tx(1, 2), None)
+            .applied()
+            .unwrap();
+
+        assert!(map.advance_clock(&policy, 6).is_empty());
+        assert_eq!(map.advance_clock(&policy, 7), vec![make_resolve_tx(1, 1)]);
+
+        // disputed again, the deadline starts over
+        map.insert_transaction(&policy, make_resolve_tx(1, 3), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_dispute_tx(1, 3), None)
+            .applied()
+            .unwrap();
+        assert!(map.advance_clock(&policy, 12).is_empty());
+
+        let expired = map.advance_clock(&policy, 13);
+        assert_eq!(expired, vec![make_resolve_tx(1, 3)]);
+
+        let effect = map
+            .insert_transaction(&policy, expired[0].clone(), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.available, Some(true));
+        assert_eq!(effect.held, Some(false));
+        assert!(map.advance_clock(&policy, 100).is_empty());
+
+        // another portion doesn't move the deadline of the first one
+        map.insert_transaction(&policy, make_partial_dispute_tx(1, 2, 4), None)
+            .applied()
+            .unwrap();
+        map.advance_clock(&policy, 103);
+        map.insert_transaction(&policy, make_partial_dispute_tx(1, 2, 4), None)
+            .applied()
+            .unwrap();
+        assert_eq!(map.advance_clock(&policy, 106), vec![make_resolve_tx(1, 2)]);
+    }
+
+    fn make_partial_dispute_tx(client_id: ClientId, tx_id: TxId, amount: u64) -> ZzTx {
+        ZzTx {
+            r#type: ZzTxType::Dispute(Some(make_amount(amount))),
+            client_id,
+            tx_id,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        map.insert_transaction(&policy, make_deposit_tx(1, 1, 10), None)
+            .applied()
+            .unwrap();
+
+        // more than the deposit
+        assert_eq!(
+            map.insert_transaction(&policy, make_partial_dispute_tx(1, 1, 11), None)
+                .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+        assert_eq!(
+            map.insert_transaction(&policy, make_partial_dispute_tx(1, 1, 0), None)
+                .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+
+        let effect = map
+            .insert_transaction(&policy, make_partial_dispute_tx(1, 1, 3), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(3));
+        assert_eq!(effect.available, Some(false));
+        assert_eq!(effect.held, Some(true));
+
+        // only the undisputed remainder (7) can be disputed
+        assert_eq!(
+            map.insert_transaction(&policy, make_partial_dispute_tx(1, 1, 8), None)
+                .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+        let effect = map
+            .insert_transaction(&policy, make_partial_dispute_tx(1, 1, 4), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(4));
+
+        // the resolve releases both portions
+        let effect = map
+            .insert_transaction(&policy, make_resolve_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(7));
+        assert_eq!(effect.available, Some(true));
+        assert_eq!(effect.held, Some(false));
+
+        // a dispute without amount takes the remainder, then nothing is left
+        map.insert_transaction(&policy, make_partial_dispute_tx(1, 1, 6), None)
+            .applied()
+            .unwrap();
+        let effect = map
+            .insert_transaction(&policy, make_dispute_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(4));
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+                .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+
+        let effect = map
+            .insert_transaction(&policy, make_chargeback_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(10));
+        assert_eq!(effect.locked, Some(true));
+    }
+
+    #[test]
+    fn test_partial_dispute_capped() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions {
+            on_negative_balance: NegativeBalanceOptions::Cap,
+            ..Default::default()
+        };
+
+        map.insert_transaction(&policy, make_deposit_tx(1, 1, 10), None)
+            .applied()
+            .unwrap();
+        let effect = map
+            .insert_transaction(
+                &policy,
+                make_partial_dispute_tx(1, 1, 5),
+                Some(&make_balance(1, 2)),
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(2));
+        assert_eq!(effect.receivable, Some((true, make_amount(3))));
+
+        // withdrawals can't be partially disputed
+        let withdrawal_policy = ZzPolicyOptions {
+            allow_withdrawal_disputes: true,
+            ..Default::default()
+        };
+        map.insert_transaction(
+            &withdrawal_policy,
+            make_withdraw_tx(1, 2, 1),
+            Some(&make_balance(1, 1)),
+        )
+        .applied()
+        .unwrap();
+        assert_eq!(
+            map.insert_transaction(&withdrawal_policy, make_partial_dispute_tx(1, 2, 1), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        for tx_id in 1..=2 {
+            map.insert_transaction(&policy, make_deposit_tx(1, tx_id, 10), None)
+                .applied()
+                .unwrap();
+            map.insert_transaction(&policy, make_dispute_tx(1, tx_id), None)
+                .applied()
+                .unwrap();
+        }
+        map.insert_transaction(&policy, make_deposit_tx(2, 3, 10), None)
+            .applied()
+            .unwrap();
+
+        let mut open_disputes = map.open_disputes(1);
+        open_disputes.sort();
+        assert_eq!(open_disputes, vec![1, 2]);
+        assert!(map.open_disputes(2).is_empty());
+
+        map.insert_transaction(&policy, make_resolve_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        assert_eq!(map.open_disputes(1), vec![2]);
+        map.insert_transaction(&policy, make_chargeback_tx(1, 2), None)
+            .applied()
+            .unwrap();
+        assert!(map.open_disputes(1).is_empty());
+    }
+
+    #[test]
+    fn test_holds() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+        map.insert_transaction(&policy, make_deposit_tx(1, 2, 10), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_partial_dispute_tx(1, 2, 4), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_deposit_tx(1, 1, 10), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(
+            &policy,
+            make_tx(2, 3, ZzTxType::Authorize(make_amount(7))),
+            Some(&make_balance(2, 100)),
+        )
+        .applied()
+        .unwrap();
+
+        assert_eq!(
+            map.holds(),
+            vec![
+                (1, 1, make_amount(10)),
+                (1, 2, make_amount(4)),
+                (2, 3, make_amount(7))
+            ]
+        );
+
+        map.insert_transaction(&policy, make_resolve_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_tx(2, 3, ZzTxType::Void), None)
+            .applied()
+            .unwrap();
+        assert_eq!(map.holds(), vec![(1, 2, make_amount(4))]);
+    }
+
+    fn make_tx(client_id: ClientId, tx_id: TxId, r#type: ZzTxType) -> ZzTx {
+        ZzTx {
+            r#type,
+            client_id,
+            tx_id,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_authorize_capture_void() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+        let authorize = make_tx(1, 1, ZzTxType::Authorize(make_amount(10)));
+
+        // insufficient funds
+        assert_eq!(
+            map.insert_transaction(&policy, authorize.clone(), Some(&make_balance(1, 9)))
+                .rejected(),
+            Some(RejectReason::InsufficientFunds)
+        );
+
+        let effect = map
+            .insert_transaction(&policy, authorize, Some(&make_balance(1, 10)))
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(10));
+        assert_eq!(effect.available, Some(false));
+        assert_eq!(effect.held, Some(true));
+
+        // partial capture, the remainder stays held
+        let effect = map
+            .insert_transaction(
+                &policy,
+                make_tx(1, 1, ZzTxType::Capture(Some(make_amount(4)))),
+                None,
+            )
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(4));
+        assert_eq!(effect.available, None);
+        assert_eq!(effect.held, Some(false));
+
+        // the remainder is released by the void
+        let effect = map
+            .insert_transaction(&policy, make_tx(1, 1, ZzTxType::Void), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(6));
+        assert_eq!(effect.available, Some(true));
+        assert_eq!(effect.held, Some(false));
+
+        assert_eq!(
+            map.insert_transaction(&policy, make_tx(1, 1, ZzTxType::Void), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+        assert_eq!(
+            map.insert_transaction(&policy, make_tx(1, 1, ZzTxType::Capture(None)), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+
+        // an authorization can't be disputed
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+    }
+
+    #[test]
+    fn test_over_capture() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        map.insert_transaction(
+            &policy,
+            make_tx(1, 1, ZzTxType::Authorize(make_amount(10))),
+            Some(&make_balance(1, 10)),
+        )
+        .applied()
+        .unwrap();
+
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_tx(1, 1, ZzTxType::Capture(Some(make_amount(11)))),
+                None
+            )
+            .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_tx(1, 1, ZzTxType::Capture(Some(make_amount(0)))),
+                None
+            )
+            .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+        // captures must reference an authorization
+        map.insert_transaction(&policy, make_deposit_tx(1, 2, 10), None)
+            .applied()
+            .unwrap();
+        assert_eq!(
+            map.insert_transaction(&policy, make_tx(1, 2, ZzTxType::Capture(None)), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+
+        let effect = map
+            .insert_transaction(&policy, make_tx(1, 1, ZzTxType::Capture(None)), None)
+            .applied()
+            .unwrap();
+        assert_eq!(effect.amount, make_amount(10));
+    }
+
+    #[test]
+    fn test_double_capture() {
+        let mut map = TransactionHashMapImpl::default();
+        let policy = ZzPolicyOptions::default();
+
+        map.insert_transaction(
+            &policy,
+            make_tx(1, 1, ZzTxType::Authorize(make_amount(10))),
+            Some(&make_balance(1, 10)),
+        )
+        .applied()
+        .unwrap();
+        map.insert_transaction(
+            &policy,
+            make_tx(1, 1, ZzTxType::Capture(Some(make_amount(3)))),
+            None,
+        )
+        .applied()
+        .unwrap();
+
+        // even if there's a remainder
+        assert_eq!(
+            map.insert_transaction(
+                &policy,
+                make_tx(1, 1, ZzTxType::Capture(Some(make_amount(3)))),
+                None
+            )
+            .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+        // a fully captured authorization has nothing to void
+        map.insert_transaction(
+            &policy,
+            make_tx(1, 2, ZzTxType::Authorize(make_amount(5))),
+            Some(&make_balance(1, 5)),
+        )
+        .applied()
+        .unwrap();
+        map.insert_transaction(&policy, make_tx(1, 2, ZzTxType::Capture(None)), None)
+            .applied()
+            .unwrap();
+        assert_eq!(
+            map.insert_transaction(&policy, make_tx(1, 2, ZzTxType::Void), None)
+                .rejected(),
+            Some(RejectReason::InvalidReference)
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let policy = ZzPolicyOptions {
+            dispute_resolve_deadline: Some(5),
+            ..Default::default()
+        };
+        let mut map = TransactionHashMapImpl::default();
+        for tx_id in 1..=3 {
+            map.insert_transaction(&policy, make_deposit_tx(1, tx_id, 10), None)
+                .applied()
+                .unwrap();
+        }
+        map.advance_clock(&policy, 2);
+        map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_partial_dispute_tx(1, 2, 4), None)
+            .applied()
+            .unwrap();
+
+        let mut checkpoint = vec![];
+        map.write_checkpoint(&mut checkpoint).unwrap();
+        let mut restored = TransactionHashMapImpl::default();
+        restored
+            .read_checkpoint(&mut checkpoint.as_slice())
+            .unwrap();
+
+        assert_eq!(restored.holds(), map.holds());
+        let mut open_disputes = restored.open_disputes(1);
+        open_disputes.sort();
+        assert_eq!(open_disputes, vec![1, 2]);
+        // the deadline of the open disputes is kept
+        assert!(restored.advance_clock(&policy, 7).is_empty());
+        assert_eq!(
+            restored.advance_clock(&policy, 8),
+            vec![make_resolve_tx(1, 1), make_resolve_tx(1, 2)]
+        );
+        // the disputed transaction can be resolved and the others disputed
+        restored
+            .insert_transaction(&policy, make_dispute_tx(1, 3), None)
+            .applied()
+            .unwrap();
+        assert_eq!(
+            restored
+                .insert_transaction(&policy, make_dispute_tx(1, 1), None)
+                .rejected(),
+            Some(RejectReason::InvalidAmount)
+        );
+    }
+
+    #[test]
+    fn test_evict_finalized() {
+        let policy = ZzPolicyOptions {
+            evict_finalized: true,
+            allow_admin_transactions: true,
+            ..Default::default()
+        };
+        let mut map = TransactionHashMapImpl::default();
+        map.insert_transaction(&policy, make_deposit_tx(1, 1, 10), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_deposit_tx(1, 2, 10), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_chargeback_tx(1, 1), None)
+            .applied()
+            .unwrap();
+        assert_eq!(map.evicted(), 1);
+        assert!(map.map.get(&(1, 1)).is_none());
+
+        // a resolved deposit can still be disputed
+        map.insert_transaction(&policy, make_dispute_tx(1, 2), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_resolve_tx(1, 2), None)
+            .applied()
+            .unwrap();
+        assert_eq!(map.evicted(), 1);
+        assert!(map.map.get(&(1, 2)).is_some());
+
+        // an administrative transaction is evicted without being stored
+        let freeze = ZzTx {
+            r#type: ZzTxType::Freeze,
+            client_id: 1,
+            tx_id: 3,
+            timestamp: None,
+        };
+        map.insert_transaction(&policy, freeze, None)
+            .applied()
+            .unwrap();
+        assert_eq!(map.evicted(), 2);
+
+        // the tx id of an evicted transaction can be reused
+        assert!(
+            map.insert_transaction(&policy, make_deposit_tx(1, 1, 5), None)
+                .applied()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_eviction_horizon() {
+        let policy = ZzPolicyOptions {
+            allow_withdrawal_disputes: true,
+            eviction_horizon: Some(5),
+            ..Default::default()
+        };
+        let mut balance = ZzClientBalance::new(1);
+        balance.available = ZzIAmount::new(100.into(), 0).unwrap();
+
+        let mut map = TransactionHashMapImpl::default();
+        map.insert_transaction(&policy, make_deposit_tx(1, 1, 10), None)
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_withdraw_tx(1, 2, 10), Some(&balance))
+            .applied()
+            .unwrap();
+        map.insert_transaction(&policy, make_deposit_tx(1, 3, 10), None)
+            .applied()
+            .unwrap();
+        map.advance_clock(&policy, 3);
+        map.insert_transaction(&policy, make_dispute_tx(1, 3), None)
+            .applied()
+            .unwrap();
+
+        assert!(map.advance_clock(&policy, 5).is_empty());
+        assert_eq!(map.evicted(), 0);
+
+        // the disputed deposit is kept
+        map.advance_clock(&policy, 6);
+        assert_eq!(map.evicted(), 2);
+        assert_eq!(
+            map.insert_transaction(&policy, make_dispute_tx(1, 1), None)
+                .rejected(),
+            Some(RejectReason::UnknownTx)
+        );
+
+        let mut checkpoint = vec![];
+        map.write_checkpoint(&mut checkpoint).unwrap();
+        let mut restored = TransactionHashMapImpl::default();
+        restored
+            .read_checkpoint(&mut checkpoint.as_slice())
+            .unwrap();
+        assert_eq!(restored.evicted(), 2);
+
+        // once resolved it's past the horizon
+        restored
+            .insert_transaction(&policy, make_resolve_tx(1, 3), None)
+            .applied()
+            .unwrap();
+        assert_eq!(restored.evicted(), 3);
+        assert!(restored.map.get(&(1, 3)).is_none());
     }
 }
@@ -0,0 +1,636 @@
+use std::{
+    cell::OnceCell,
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use crate::domain::{
+    client_balance::ClientId,
+    transaction::{TransactionEntry, TransactionStore, TxId, hash_map_store::HashMapStore},
+};
+
+type Key = (ClientId, TxId);
+
+/// A key of every INDEX_INTERVAL records of a run is kept in memory
+const INDEX_INTERVAL: usize = 64;
+/// Bits of the bloom filter per record of a run
+const BLOOM_BITS_PER_KEY: usize = 10;
+const BLOOM_HASHES: u64 = 7;
+/// client (8 bytes), tx (4 bytes) and payload length (4 bytes)
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Keeps up to hot_capacity entries in memory and spills them to sorted runs in a temporary
+/// directory when it's full, the directory is removed on drop
+///
+/// An entry read from a run is loaded into memory again, so the latest entry of a transaction is
+/// either in memory or in the newest run that has it. Runs of a similar size are merged after
+/// each spill, which keeps their count logarithmic to the number of transactions. A removed
+/// entry is written as a record without payload that shadows the older runs, until it's merged
+/// into the oldest one
+///
+/// The store's methods can't return the IO errors of the runs, the first one is kept for
+/// take_error and the store stops spilling after it
+pub struct DiskStore {
+    dir: tempfile::TempDir,
+    hot: HashMapStore,
+    /// The removed keys that may still be in a run, they count towards hot_capacity
+    removed: HashSet<Key>,
+    hot_capacity: usize,
+    /// Oldest first, a newer run shadows the entries of the older ones
+    runs: Vec<Run>,
+    next_run_id: u64,
+    /// The first IO error, for_each only borrows the store so it's set through a cell
+    error: OnceCell<io::Error>,
+}
+
+/// A file of records sorted by key, each key appears once
+struct Run {
+    path: PathBuf,
+    file: File,
+    /// How many records it has
+    len: usize,
+    size: u64,
+    /// The key of every INDEX_INTERVAL-th record and its offset
+    index: Vec<(Key, u64)>,
+    bloom: Bloom,
+}
+
+/// A bloom filter, it avoids reading a run to find out it doesn't have the key
+struct Bloom {
+    bits: Vec<u64>,
+}
+
+impl DiskStore {
+    /// # Errors
+    ///
+    /// Failed to create the temporary directory in spill_dir
+    pub fn new(spill_dir: &Path, hot_capacity: usize) -> io::Result<Self> {
+        Ok(Self {
+            dir: tempfile::TempDir::with_prefix_in("zz-transactions-", spill_dir)?,
+            hot: HashMapStore::default(),
+            removed: HashSet::new(),
+            hot_capacity: hot_capacity.max(1),
+            runs: vec![],
+            next_run_id: 0,
+            error: OnceCell::new(),
+        })
+    }
+
+    /// How many runs are on disk
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Keeps the error if it's the first one
+    fn keep_error<T>(&self, result: io::Result<T>) -> Option<T> {
+        result.map_err(|err| _ = self.error.set(err)).ok()
+    }
+
+    /// Spills once the hot entries and the removed keys don't fit in hot_capacity, unless an
+    /// IO error happened
+    fn spill_if_full(&mut self) {
+        if self.hot.len() + self.removed.len() > self.hot_capacity && self.error.get().is_none() {
+            let result = self.spill();
+            self.keep_error(result);
+        }
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let mut records = vec![];
+        for (key, entry) in self.hot.take_sorted() {
+            let mut payload = vec![];
+            entry.write(&mut payload)?;
+            records.push((key, payload));
+        }
+        records.extend(self.removed.drain().map(|key| (key, vec![])));
+        records.sort_unstable_by_key(|(key, _)| *key);
+
+        let run = self.write_run(records.len(), records.into_iter().map(Ok))?;
+        self.runs.push(run);
+
+        while let [.., older, newer] = self.runs.as_slice()
+            && newer.size * 2 >= older.size
+        {
+            let newer = self.runs.pop().unwrap();
+            let older = self.runs.pop().unwrap();
+
+            // nothing is left to shadow once the oldest run is merged
+            let is_oldest = self.runs.is_empty();
+            let merged = self.write_run(
+                older.len + newer.len,
+                merge_runs(&[&older, &newer])?.filter(
+                    |record| !matches!(record, Ok((_, payload)) if is_oldest && payload.is_empty()),
+                ),
+            )?;
+            self.runs.push(merged);
+
+            std::fs::remove_file(&older.path)?;
+            std::fs::remove_file(&newer.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the records, which must be sorted by key, to a new run. expected_len is used to
+    /// size the bloom filter, it may be greater than the actual count
+    fn write_run(
+        &mut self,
+        expected_len: usize,
+        records: impl Iterator<Item = io::Result<(Key, Vec<u8>)>>,
+    ) -> io::Result<Run> {
+        let path = self.dir.path().join(format!("{}.run", self.next_run_id));
+        self.next_run_id += 1;
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut len = 0;
+        let mut size = 0;
+        let mut index = vec![];
+        let mut bloom = Bloom::new(expected_len);
+
+        for (i, record) in records.enumerate() {
+            let (key, payload) = record?;
+            if i % INDEX_INTERVAL == 0 {
+                index.push((key, size));
+            }
+            bloom.insert(&key);
+
+            writer.write_all(&key.0.to_le_bytes())?;
+            writer.write_all(&key.1.to_le_bytes())?;
+            writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+            writer.write_all(&payload)?;
+            size += (RECORD_HEADER_LEN + payload.len()) as u64;
+            len += 1;
+        }
+        writer.flush()?;
+
+        Ok(Run {
+            file: File::open(&path)?,
+            path,
+            len,
+            size,
+            index,
+            bloom,
+        })
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn get(&mut self, key: &Key) -> Option<TransactionEntry> {
+        if self.removed.contains(key) {
+            return None;
+        }
+        if !self.hot.contains(key) {
+            let payload = self
+                .runs
+                .iter()
+                .rev()
+                .find_map(|run| run.get(key).transpose())?;
+            let entry = self
+                .keep_error(payload)
+                .filter(|payload| !payload.is_empty())
+                .and_then(|payload| {
+                    self.keep_error(TransactionEntry::read(&mut payload.as_slice()))
+                })?;
+            self.hot.insert(*key, entry);
+        }
+        self.hot.get(key)
+    }
+
+    fn insert(&mut self, key: Key, entry: TransactionEntry) {
+        self.removed.remove(&key);
+        self.hot.insert(key, entry);
+        self.spill_if_full();
+    }
+
+    fn remove(&mut self, key: &Key) {
+        self.hot.remove(key);
+        if self.runs.iter().any(|run| run.bloom.contains(key)) {
+            self.removed.insert(*key);
+            self.spill_if_full();
+        }
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&Key, &TransactionEntry)) {
+        let runs: Vec<_> = self.runs.iter().collect();
+        if let Some(records) = self.keep_error(merge_runs(&runs)) {
+            for record in records {
+                let Some((key, payload)) = self.keep_error(record) else {
+                    break;
+                };
+                if !payload.is_empty() && !self.hot.contains(&key) && !self.removed.contains(&key) {
+                    let Some(entry) =
+                        self.keep_error(TransactionEntry::read(&mut payload.as_slice()))
+                    else {
+                        break;
+                    };
+                    f(&key, &entry);
+                }
+            }
+        }
+        self.hot.for_each(f);
+    }
+
+    fn take_error(&mut self) -> io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+impl Run {
+    /// The payload of the key's record, None if the run doesn't have the key
+    fn get(&self, key: &Key) -> io::Result<Option<Vec<u8>>> {
+        if !self.bloom.contains(key) {
+            return Ok(None);
+        }
+
+        // the block between the closest indexed keys is the only place the key can be
+        let block = self.index.partition_point(|(indexed, _)| indexed <= key);
+        let Some(&(_, start)) = block.checked_sub(1).and_then(|i| self.index.get(i)) else {
+            return Ok(None);
+        };
+        let end = self
+            .index
+            .get(block)
+            .map_or(self.size, |(_, offset)| *offset);
+
+        let mut buf = vec![0; (end - start) as usize];
+        self.file.read_exact_at(&mut buf, start)?;
+
+        for record in RecordReader(buf.as_slice()) {
+            let (record_key, payload) = record?;
+            if record_key == *key {
+                return Ok(Some(payload));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Reads the records of a run sequentially
+struct RecordReader<R>(R);
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = io::Result<(Key, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        match self.0.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        }
+
+        let client_id = ClientId::from_le_bytes(header[..8].try_into().unwrap());
+        let tx_id = TxId::from_le_bytes(header[8..12].try_into().unwrap());
+        let len = u32::from_le_bytes(header[12..16].try_into().unwrap());
+
+        let mut payload = vec![0; len as usize];
+        Some(
+            self.0
+                .read_exact(&mut payload)
+                .map(|()| ((client_id, tx_id), payload)),
+        )
+    }
+}
+
+/// Merges runs (oldest first) into the sorted records of their latest entries, it ends after the
+/// first error
+///
+/// # Errors
+///
+/// Failed to open a run
+fn merge_runs(
+    runs: &[&Run],
+) -> io::Result<impl Iterator<Item = io::Result<(Key, Vec<u8>)>> + use<>> {
+    let mut readers = runs
+        .iter()
+        .map(|run| Ok(RecordReader(BufReader::new(File::open(&run.path)?)).peekable()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut failed = false;
+
+    Ok(std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        for reader in &mut readers {
+            if matches!(reader.peek(), Some(Err(_))) {
+                failed = true;
+                return reader.next();
+            }
+        }
+
+        let key = readers
+            .iter_mut()
+            .filter_map(|reader| match reader.peek() {
+                Some(Ok((key, _))) => Some(*key),
+                _ => None,
+            })
+            .min()?;
+
+        // every reader with the key moves forward, the newest one wins
+        readers
+            .iter_mut()
+            .filter_map(|reader| {
+                reader.next_if(|next| matches!(next, Ok((next, _)) if *next == key))
+            })
+            .last()
+    }))
+}
+
+impl Bloom {
+    fn new(expected_len: usize) -> Self {
+        Self {
+            bits: vec![0; (expected_len * BLOOM_BITS_PER_KEY).div_ceil(64).max(1)],
+        }
+    }
+
+    fn positions(&self, key: &Key) -> impl Iterator<Item = usize> + use<> {
+        let hash = splitmix64(key.0.rotate_left(32) ^ key.1 as u64);
+        let (h1, h2) = (hash & u32::MAX as u64, hash >> 32);
+        let len = self.bits.len() as u64 * 64;
+
+        (0..BLOOM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    fn insert(&mut self, key: &Key) {
+        for position in self.positions(key) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        self.positions(key)
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ZzPolicyOptions,
+        common::zz_amount::{ZzIAmount, ZzUAmount},
+        domain::{
+            client_balance::ZzClientBalance,
+            transaction::{
+                TransactionDiskMapImpl, TransactionHashMapImpl, TransactionMap, TransactionState,
+                ZzTx, ZzTxType,
+            },
+        },
+    };
+
+    fn make_amount(val: u64) -> ZzUAmount {
+        ZzUAmount::new(val.into(), 0).unwrap()
+    }
+
+    fn make_entry(state: TransactionState, created_at: u64) -> TransactionEntry {
+        TransactionEntry {
+            state,
+            created_at,
+            disputed_at: created_at + 1,
+        }
+    }
+
+    fn make_store(hot_capacity: usize) -> DiskStore {
+        DiskStore::new(&std::env::temp_dir(), hot_capacity).unwrap()
+    }
+
+    #[test]
+    fn test_encode_decode_entry() {
+        let states = [
+            TransactionState::Deposit(make_amount(10)),
+            TransactionState::Withdrawal(ZzUAmount::new(u64::MAX.into(), 5).unwrap()),
+            TransactionState::Dispute {
+                amount: make_amount(10),
+                held: make_amount(3),
+                receivable: ZzUAmount::zero(),
+            },
+            TransactionState::WithdrawalDispute(make_amount(1)),
+            TransactionState::Locked,
+            TransactionState::WithdrawalChargedBack,
+            TransactionState::Administrative,
+            TransactionState::Authorized(make_amount(7)),
+            TransactionState::Captured {
+                remainder: make_amount(2),
+            },
+            TransactionState::Voided,
+        ];
+
+        for state in states {
+            let mut buf = vec![];
+            make_entry(state.clone(), 4).write(&mut buf).unwrap();
+            let entry = TransactionEntry::read(&mut buf.as_slice()).unwrap();
+            assert_eq!(entry.state, state);
+            assert_eq!((entry.created_at, entry.disputed_at), (4, 5));
+        }
+    }
+
+    #[test]
+    fn test_spill_and_get() {
+        let mut store = make_store(4);
+
+        for tx_id in 0..100 {
+            store.insert(
+                (1 + (tx_id % 3) as ClientId, tx_id),
+                make_entry(TransactionState::Deposit(make_amount(tx_id.into())), 0),
+            );
+        }
+        assert!(store.runs() > 0);
+        // runs of a similar size are merged
+        assert!(store.runs() < 100 / 4);
+
+        // a newer entry shadows the spilled one
+        store.insert((2, 1), make_entry(TransactionState::Locked, 0));
+        for tx_id in 100..120 {
+            store.insert(
+                (1, tx_id),
+                make_entry(TransactionState::Deposit(make_amount(1)), 0),
+            );
+        }
+
+        for tx_id in 0..100 {
+            let state = &store
+                .get(&(1 + (tx_id % 3) as ClientId, tx_id))
+                .unwrap()
+                .state;
+            if tx_id == 1 {
+                assert_eq!(*state, TransactionState::Locked);
+            } else {
+                assert_eq!(*state, TransactionState::Deposit(make_amount(tx_id.into())));
+            }
+        }
+        assert!(store.get(&(1, 1)).is_none());
+        assert!(store.get(&(4, 0)).is_none());
+
+        let mut seen = vec![];
+        store.for_each(|key, entry| {
+            if *key == (2, 1) {
+                assert_eq!(entry.state, TransactionState::Locked);
+            }
+            seen.push(*key);
+        });
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 120);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut store = make_store(4);
+        for tx_id in 0..20 {
+            store.insert(
+                (1, tx_id),
+                make_entry(TransactionState::Deposit(make_amount(1)), 0),
+            );
+        }
+        assert!(store.runs() > 0);
+
+        // spilled entries are shadowed, the hot one is dropped
+        for tx_id in [0, 7, 19] {
+            store.remove(&(1, tx_id));
+        }
+        store.remove(&(2, 0));
+        assert!(store.get(&(1, 0)).is_none());
+
+        // the tombstones are spilled and merged too
+        for tx_id in 20..40 {
+            store.insert((1, tx_id), make_entry(TransactionState::Locked, 0));
+        }
+        store.insert((1, 7), make_entry(TransactionState::Voided, 0));
+
+        for tx_id in 0..40 {
+            assert_eq!(
+                store.get(&(1, tx_id)).is_some(),
+                ![0, 19].contains(&tx_id),
+                "{tx_id}"
+            );
+        }
+        assert_eq!(store.get(&(1, 7)).unwrap().state, TransactionState::Voided);
+
+        let mut seen = vec![];
+        store.for_each(|key, _| seen.push(*key));
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 38);
+    }
+
+    #[test]
+    fn test_io_error_is_kept() {
+        let mut store = make_store(4);
+        for tx_id in 0..10 {
+            store.insert(
+                (1, tx_id),
+                make_entry(TransactionState::Deposit(make_amount(1)), 0),
+            );
+        }
+        assert!(store.take_error().is_ok());
+
+        for run in &store.runs {
+            std::fs::remove_file(&run.path).unwrap();
+        }
+        let mut seen = 0;
+        store.for_each(|_, _| seen += 1);
+        assert!(seen < 10);
+
+        // only the first error is kept and no run is written after it
+        let runs = store.runs();
+        for tx_id in 10..20 {
+            store.insert(
+                (1, tx_id),
+                make_entry(TransactionState::Deposit(make_amount(1)), 0),
+            );
+        }
+        assert_eq!(
+            store.take_error().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(store.take_error().is_ok());
+        assert_eq!(runs, store.runs());
+    }
+
+    #[test]
+    fn test_temporary_directory_is_removed() {
+        let store = make_store(1);
+        let dir = store.dir.path().to_path_buf();
+        assert!(dir.exists());
+        drop(store);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_same_effects_as_hash_map() {
+        let policy = ZzPolicyOptions {
+            allow_withdrawal_disputes: true,
+            dispute_resolve_deadline: Some(40),
+            ..Default::default()
+        };
+        let mut balance = ZzClientBalance::new(1);
+        balance.available = ZzIAmount::new(1_000_000.into(), 0).unwrap();
+
+        let mut hash_map = TransactionHashMapImpl::default();
+        let mut disk_map = TransactionDiskMapImpl::with_store(make_store(8));
+
+        // a deterministic pseudo random sequence, tx ids are reused so most transactions
+        // reference an existing entry
+        let mut seed = 42u64;
+        let mut next = |max: u64| {
+            seed = splitmix64(seed);
+            seed % max
+        };
+
+        let mut applied = 0;
+        for now in 0..5000 {
+            let amount = make_amount(1 + next(20));
+            let r#type = match next(11) {
+                0 | 1 => ZzTxType::Deposit(amount),
+                2 => ZzTxType::Withdrawal(amount),
+                3 => ZzTxType::Dispute(None),
+                4 => ZzTxType::Dispute(Some(amount)),
+                5 => ZzTxType::Resolve,
+                6 => ZzTxType::Chargeback,
+                7 => ZzTxType::Transfer {
+                    to: 1 + next(4) as ClientId,
+                    amount,
+                },
+                8 => ZzTxType::Authorize(amount),
+                9 => ZzTxType::Capture(None),
+                _ => ZzTxType::Void,
+            };
+            let tx = ZzTx {
+                r#type,
+                client_id: 1 + next(4) as ClientId,
+                tx_id: next(500) as TxId,
+                timestamp: None,
+            };
+
+            assert_eq!(
+                hash_map.advance_clock(&policy, now),
+                disk_map.advance_clock(&policy, now)
+            );
+            let expected = hash_map.insert_transaction(&policy, tx.clone(), Some(&balance));
+            let effect = disk_map.insert_transaction(&policy, tx, Some(&balance));
+            assert_eq!(expected, effect);
+            applied += effect.applied().is_some() as usize;
+        }
+
+        assert!(applied > 1000, "{applied}");
+        assert!(disk_map.map.runs() > 1);
+        assert_eq!(hash_map.holds(), disk_map.holds());
+        for client_id in 1..=4 {
+            let mut expected = hash_map.open_disputes(client_id);
+            let mut open_disputes = disk_map.open_disputes(client_id);
+            expected.sort();
+            open_disputes.sort();
+            assert_eq!(expected, open_disputes);
+        }
+    }
+}
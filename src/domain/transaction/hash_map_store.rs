@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use crate::{
+    common::zz_amount::ZzUAmount,
+    domain::{
+        client_balance::ClientId,
+        transaction::{TransactionEntry, TransactionState, TransactionStore, TxId},
+    },
+};
+
+type Key = (ClientId, TxId);
+
+/// The state of an entry stored inline, Full means the entry is in HashMapStore::full
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+enum CompactState {
+    Deposit,
+    Withdrawal,
+    Locked,
+    WithdrawalChargedBack,
+    Administrative,
+    Authorized,
+    Captured,
+    Voided,
+    Full,
+}
+
+/// Keeps every entry in memory
+///
+/// Each transaction gets a slot in columns of fixed width: its state, its amount as the scaled
+/// u64 and its creation clock, which is 17 bytes plus the 12 bytes of the hashmap entry (24 if
+/// the client id doesn't fit in a u32). Disputes and amounts that don't fit in a u64 are kept
+/// whole in a side table, they're expected to be rare
+#[derive(Default)]
+pub struct HashMapStore {
+    /// The slots of the keys whose client id fits in a u32
+    narrow_slots: HashMap<(u32, TxId), u32>,
+    wide_slots: HashMap<Key, u32>,
+    states: Vec<CompactState>,
+    amounts: Vec<u64>,
+    created_at: Vec<u64>,
+    /// The entries that can't be stored inline, by slot
+    full: HashMap<u32, TransactionEntry>,
+    /// The slots of removed entries, they're reused before adding new ones
+    free: Vec<u32>,
+}
+
+impl HashMapStore {
+    pub fn len(&self) -> usize {
+        self.narrow_slots.len() + self.wide_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.slot(key).is_some()
+    }
+
+    fn slot(&self, &(client_id, tx_id): &Key) -> Option<u32> {
+        match u32::try_from(client_id) {
+            Ok(client_id) => self.narrow_slots.get(&(client_id, tx_id)).copied(),
+            Err(_) => self.wide_slots.get(&(client_id, tx_id)).copied(),
+        }
+    }
+
+    /// Removes every entry and returns them sorted by key
+    pub fn take_sorted(&mut self) -> Vec<(Key, TransactionEntry)> {
+        let mut entries = Vec::with_capacity(self.len());
+        self.for_each(|key, entry| entries.push((*key, entry.clone())));
+        entries.sort_unstable_by_key(|(key, _)| *key);
+
+        *self = Self::default();
+        entries
+    }
+
+    fn entry(&self, slot: u32) -> TransactionEntry {
+        let i = slot as usize;
+        let amount = || ZzUAmount::from_scaled_u64(self.amounts[i]);
+
+        let state = match self.states[i] {
+            CompactState::Deposit => TransactionState::Deposit(amount()),
+            CompactState::Withdrawal => TransactionState::Withdrawal(amount()),
+            CompactState::Locked => TransactionState::Locked,
+            CompactState::WithdrawalChargedBack => TransactionState::WithdrawalChargedBack,
+            CompactState::Administrative => TransactionState::Administrative,
+            CompactState::Authorized => TransactionState::Authorized(amount()),
+            CompactState::Captured => TransactionState::Captured {
+                remainder: amount(),
+            },
+            CompactState::Voided => TransactionState::Voided,
+            CompactState::Full => return self.full[&slot].clone(),
+        };
+
+        // the dispute clock only matters while the transaction is disputed
+        TransactionEntry {
+            state,
+            created_at: self.created_at[i],
+            disputed_at: 0,
+        }
+    }
+}
+
+/// The state and amount to store inline, None if the entry must be stored whole
+fn compact(state: &TransactionState) -> Option<(CompactState, u64)> {
+    Some(match state {
+        TransactionState::Deposit(amount) => (CompactState::Deposit, amount.to_scaled_u64()?),
+        TransactionState::Withdrawal(amount) => (CompactState::Withdrawal, amount.to_scaled_u64()?),
+        TransactionState::Locked => (CompactState::Locked, 0),
+        TransactionState::WithdrawalChargedBack => (CompactState::WithdrawalChargedBack, 0),
+        TransactionState::Administrative => (CompactState::Administrative, 0),
+        TransactionState::Authorized(amount) => (CompactState::Authorized, amount.to_scaled_u64()?),
+        TransactionState::Captured { remainder } => {
+            (CompactState::Captured, remainder.to_scaled_u64()?)
+        }
+        TransactionState::Voided => (CompactState::Voided, 0),
+        TransactionState::Dispute { .. } | TransactionState::WithdrawalDispute(_) => return None,
+    })
+}
+
+impl TransactionStore for HashMapStore {
+    fn get(&mut self, key: &Key) -> Option<TransactionEntry> {
+        self.slot(key).map(|slot| self.entry(slot))
+    }
+
+    /// # Panics
+    ///
+    /// More than u32::MAX transactions are stored
+    fn insert(&mut self, key: Key, entry: TransactionEntry) {
+        let next = self
+            .free
+            .last()
+            .map_or(self.states.len(), |slot| *slot as usize);
+        let new_slot = || u32::try_from(next).expect("Too many transactions");
+        let slot = match u32::try_from(key.0) {
+            Ok(client_id) => *self
+                .narrow_slots
+                .entry((client_id, key.1))
+                .or_insert_with(new_slot),
+            Err(_) => *self.wide_slots.entry(key).or_insert_with(new_slot),
+        };
+        let i = slot as usize;
+        if i == self.states.len() {
+            self.states.push(CompactState::Full);
+            self.amounts.push(0);
+            self.created_at.push(0);
+        } else if i == next {
+            self.free.pop();
+        }
+
+        self.created_at[i] = entry.created_at;
+        match compact(&entry.state) {
+            Some((state, amount)) => {
+                if self.states[i] == CompactState::Full {
+                    self.full.remove(&slot);
+                }
+                self.states[i] = state;
+                self.amounts[i] = amount;
+            }
+            None => {
+                self.states[i] = CompactState::Full;
+                self.full.insert(slot, entry);
+            }
+        }
+    }
+
+    fn remove(&mut self, &(client_id, tx_id): &Key) {
+        let slot = match u32::try_from(client_id) {
+            Ok(client_id) => self.narrow_slots.remove(&(client_id, tx_id)),
+            Err(_) => self.wide_slots.remove(&(client_id, tx_id)),
+        };
+
+        if let Some(slot) = slot {
+            self.full.remove(&slot);
+            self.states[slot as usize] = CompactState::Full;
+            self.free.push(slot);
+        }
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&Key, &TransactionEntry)) {
+        for (&(client_id, tx_id), slot) in &self.narrow_slots {
+            f(&(client_id.into(), tx_id), &self.entry(*slot));
+        }
+        for (key, slot) in &self.wide_slots {
+            f(key, &self.entry(*slot));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_amount(val: u64) -> ZzUAmount {
+        ZzUAmount::new(val.into(), 0).unwrap()
+    }
+
+    fn make_entry(state: TransactionState) -> TransactionEntry {
+        TransactionEntry {
+            state,
+            created_at: 3,
+            disputed_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_insert_get() {
+        let mut store = HashMapStore::default();
+        let huge = ZzUAmount::new(u64::MAX.into(), 1).unwrap();
+        let states = [
+            TransactionState::Deposit(make_amount(10)),
+            TransactionState::Withdrawal(make_amount(1)),
+            TransactionState::Deposit(huge.clone()),
+            TransactionState::Locked,
+            TransactionState::WithdrawalChargedBack,
+            TransactionState::Administrative,
+            TransactionState::Authorized(make_amount(7)),
+            TransactionState::Captured {
+                remainder: make_amount(2),
+            },
+            TransactionState::Voided,
+        ];
+
+        for (tx_id, state) in states.iter().enumerate() {
+            store.insert((1, tx_id as TxId), make_entry(state.clone()));
+        }
+        for (tx_id, state) in states.iter().enumerate() {
+            let entry = store.get(&(1, tx_id as TxId)).unwrap();
+            assert_eq!(entry.state, *state);
+            assert_eq!(entry.created_at, 3);
+        }
+        assert!(store.get(&(2, 0)).is_none());
+        assert_eq!(store.full.len(), 1);
+
+        // wide client ids have their own slots
+        let wide = 1 << 40 | 1;
+        store.insert((wide, 0), make_entry(TransactionState::Locked));
+        assert_eq!(
+            store.get(&(wide, 0)).unwrap().state,
+            TransactionState::Locked
+        );
+        assert_eq!(store.get(&(1, 0)).unwrap().state, states[0]);
+        assert_eq!(store.len(), states.len() + 1);
+        assert_eq!(store.take_sorted().last().unwrap().0, (wide, 0));
+    }
+
+    #[test]
+    fn test_disputes_are_stored_whole() {
+        let mut store = HashMapStore::default();
+        let dispute = TransactionEntry {
+            state: TransactionState::Dispute {
+                amount: make_amount(10),
+                held: make_amount(4),
+                receivable: ZzUAmount::zero(),
+            },
+            created_at: 1,
+            disputed_at: 5,
+        };
+
+        store.insert(
+            (1, 1),
+            make_entry(TransactionState::Deposit(make_amount(10))),
+        );
+        store.insert((1, 1), dispute.clone());
+        let entry = store.get(&(1, 1)).unwrap();
+        assert_eq!(entry.state, dispute.state);
+        assert_eq!((entry.created_at, entry.disputed_at), (1, 5));
+
+        // resolved
+        store.insert(
+            (1, 1),
+            make_entry(TransactionState::Deposit(make_amount(10))),
+        );
+        assert_eq!(
+            store.get(&(1, 1)).unwrap().state,
+            TransactionState::Deposit(make_amount(10))
+        );
+        assert!(store.full.is_empty());
+        assert_eq!(store.len(), 1);
+
+        let entries = store.take_sorted();
+        assert_eq!(entries.len(), 1);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut store = HashMapStore::default();
+        for tx_id in 0..3 {
+            store.insert(
+                (1, tx_id),
+                make_entry(TransactionState::Deposit(make_amount(tx_id.into()))),
+            );
+        }
+        store.insert(
+            (1, 1),
+            TransactionEntry {
+                state: TransactionState::WithdrawalDispute(make_amount(1)),
+                created_at: 3,
+                disputed_at: 4,
+            },
+        );
+
+        store.remove(&(1, 1));
+        store.remove(&(1, 7));
+        assert!(store.get(&(1, 1)).is_none());
+        assert!(store.full.is_empty());
+        assert_eq!(store.len(), 2);
+
+        // the slot is reused
+        store.insert((2, 9), make_entry(TransactionState::Voided));
+        assert_eq!(store.states.len(), 3);
+        assert_eq!(store.get(&(2, 9)).unwrap().state, TransactionState::Voided);
+        assert_eq!(
+            store.get(&(1, 2)).unwrap().state,
+            TransactionState::Deposit(make_amount(2))
+        );
+    }
+}
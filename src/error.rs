@@ -0,0 +1,110 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::domain::transaction::TxId;
+
+/// Why a row was refused by the parser, see CsvParserResult
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    MissingRequiredField,
+    ContainsExcessiveFields,
+    Failed,
+}
+
+/// Everything that stops the processing of an input
+#[derive(Debug)]
+pub enum ZzError {
+    /// Reading the input, a key or a checkpoint, writing the journal or a checkpoint, or reading
+    /// or writing the spill files failed
+    Io(io::Error),
+    /// A row couldn't be split from the input, it's longer than ZzParseOptions::max_line_width
+    /// or isn't utf-8. The row number counts the header as row 1
+    Framing { row_number: u64, reason: String },
+    /// A row couldn't be parsed and the ParsingStrictnessOptions for it is fail
+    Parse {
+        row_number: u64,
+        row: String,
+        kind: ParseErrorKind,
+    },
+    /// The input breaks the policy or the options don't go together, e.g. a duplicate tx id with
+    /// DuplicateTxOptions::Fail
+    Policy(String),
+    /// Writing the balance sheet or a report failed
+    Output(io::Error),
+    /// The journal or the balance sheet doesn't match what the input produces, see replay and
+    /// verify
+    Verification(String),
+    /// ZzCheckpointOptions::interrupt was set, the checkpoint can be resumed
+    Interrupted { checkpoint: PathBuf },
+}
+
+impl ZzError {
+    /// A duplicate tx id with DuplicateTxOptions::Fail
+    pub fn duplicate_tx(tx_id: TxId) -> Self {
+        ZzError::Policy(format!("Duplicate tx id {tx_id}"))
+    }
+
+    /// The exit code of the process for the error, 1 is a failed verification, 2 is left to
+    /// clap for invalid arguments and 143 is the one of SIGTERM
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ZzError::Verification(_) => 1,
+            ZzError::Io(_) => 3,
+            ZzError::Framing { .. } => 4,
+            ZzError::Parse { .. } => 5,
+            ZzError::Policy(_) => 6,
+            ZzError::Output(_) => 7,
+            ZzError::Interrupted { .. } => 143,
+        }
+    }
+}
+
+impl fmt::Display for ZzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZzError::Io(err) => write!(f, "{err}"),
+            ZzError::Framing { row_number, reason } => write!(f, "Row {row_number}: {reason}"),
+            ZzError::Parse {
+                row_number,
+                row,
+                kind,
+            } => {
+                let reason = match kind {
+                    ParseErrorKind::MissingRequiredField => "missing a required field",
+                    ParseErrorKind::ContainsExcessiveFields => "too many fields",
+                    ParseErrorKind::Failed => "invalid",
+                };
+                write!(
+                    f,
+                    "Failed to parse csv. Row {row_number} is {reason}: {row}"
+                )
+            }
+            ZzError::Policy(reason) => write!(f, "{reason}"),
+            ZzError::Output(err) => write!(f, "Failed to write the output: {err}"),
+            ZzError::Verification(reason) => write!(f, "{reason}"),
+            ZzError::Interrupted { checkpoint } => {
+                write!(
+                    f,
+                    "Interrupted, continue with --resume {}",
+                    checkpoint.display()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ZzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ZzError::Io(err) | ZzError::Output(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ZzError {
+    fn from(err: io::Error) -> Self {
+        ZzError::Io(err)
+    }
+}
+
+pub type ZzResult<T> = Result<T, ZzError>;
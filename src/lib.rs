@@ -2,20 +2,40 @@
 
 pub mod common;
 pub mod domain;
+pub mod error;
 pub mod parsers;
 pub mod utils;
 // pub(crate) mod utils;
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::Serialize;
-use std::{io::stdout, num::NonZeroU8, path::PathBuf};
+use std::{
+    io::{Write, stderr, stdout},
+    num::NonZeroU8,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::AtomicBool},
+};
 
 use crate::{
+    common::signature::{sign, verify_file_signature, verify_signature},
+    domain::{
+        client_balance::ClientId,
+        client_store::ZzClientNames,
+        fee::ZzFeeSchedule,
+        journal::{read_journal, replay_journal, sheet_digest, verify_journal},
+        risk::ZzRiskRules,
+    },
+    error::{ZzError, ZzResult},
     parsers::{
-        csv_parser::csv_zztx_parser_streaming, nom::CsvZzTxParserNomImpl,
+        csv_parser::{CsvZzTxParserOutput, csv_zztx_parser_streaming},
+        nom::CsvZzTxParserNomImpl,
         serde_parser::CsvZzTxParserSerdeImpl,
     },
-    utils::write_csv_client_balance_sheet,
+    utils::{
+        write_csv_client_balance_sheet, write_csv_detailed_client_balance_sheet,
+        write_csv_duplicate_tx_report, write_csv_negative_balance_report,
+        write_csv_rejection_report,
+    },
 };
 
 #[derive(Clone, Copy, Serialize, ValueEnum)]
@@ -41,6 +61,80 @@ pub enum ParserImplOptions {
     Serde,
 }
 
+/// What to do when a dispute would make the client's available funds negative
+#[derive(Clone, Copy, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NegativeBalanceOptions {
+    /// Hold the whole disputed amount even if available becomes negative
+    Allow,
+    /// Ignore disputes that would make available negative
+    Reject,
+    /// Hold at most the available amount and record the shortfall as a receivable
+    Cap,
+}
+
+/// What to do with a transaction that reuses a tx id, tx ids are referenced by disputes,
+/// resolves and chargebacks so these aren't considered
+#[derive(Clone, Copy, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateTxOptions {
+    /// Tx ids are only unique per client, a repeated id of the same client is ignored and
+    /// nothing is reported
+    Allow,
+    /// Tx ids are globally unique, duplicates are ignored and reported
+    Reject,
+    /// Tx ids are globally unique, the program fails on the first duplicate
+    Fail,
+    /// Duplicates are processed as with allow but reported
+    Warn,
+}
+
+/// Where the state of the transactions is kept
+#[derive(Clone, Copy, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransactionMapOptions {
+    /// Everything is kept in memory
+    HashMap,
+    /// Only the most recently used transactions are kept in memory, the rest is spilled to disk
+    Disk,
+}
+
+/// What the client column holds
+#[derive(Clone, Copy, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientIdOptions {
+    /// Numbers up to 65535, the balances are kept in a slot per id
+    U16,
+    U32,
+    U64,
+    /// Any text without commas or spaces, interned as a 64 bit id (see ZzClientNames)
+    String,
+}
+
+/// How the journal is written, both have the same fields
+#[derive(Clone, Copy, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum JournalFormatOptions {
+    /// A csv with a header
+    Csv,
+    /// A json object per line
+    Jsonl,
+}
+
+/// How to round amounts that have more than 4 decimal digits
+#[derive(Clone, Copy, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoundingOptions {
+    /// Towards zero
+    Down,
+    /// Away from zero
+    Up,
+    /// To the nearest, ties away from zero
+    HalfUp,
+    /// To the nearest, ties to the even digit
+    HalfEven,
+}
+
 impl ParsingStrictnessOptions {
     pub fn fail(&self) -> bool {
         matches!(self, Self::Fail)
@@ -55,18 +149,128 @@ impl ParsingStrictnessOptions {
     }
 }
 
+impl ClientIdOptions {
+    /// The client id of a client column, None if it isn't valid for these options. String
+    /// client ids are interned in client_names
+    pub fn parse(&self, client: &str, client_names: &ZzClientNames) -> Option<ClientId> {
+        let is_number = !client.is_empty() && client.bytes().all(|x| x.is_ascii_digit());
+        match self {
+            Self::U16 if is_number => client.parse::<u16>().ok().map(ClientId::from),
+            Self::U32 if is_number => client.parse::<u32>().ok().map(ClientId::from),
+            Self::U64 if is_number => client.parse().ok(),
+            Self::String if !client.is_empty() => Some(client_names.intern(client)),
+            _ => None,
+        }
+    }
+}
+
+impl ZzPolicyOptions {
+    /// The fee schedule and the account that collects the fees, nothing is charged unless both
+    /// are set
+    pub fn fees(&self) -> Option<(&ZzFeeSchedule, ClientId)> {
+        Some((self.fee_schedule.as_ref()?, self.fee_account?))
+    }
+
+    /// If an option is measured with the clock of the rows (or their timestamps): the dispute
+    /// window, the resolve deadline or the eviction horizon
+    pub fn uses_clock(&self) -> bool {
+        self.dispute_window.is_some()
+            || self.dispute_resolve_deadline.is_some()
+            || self.eviction_horizon.is_some()
+    }
+}
+
 serde_plain::derive_display_from_serialize!(ParsingStrictnessOptions);
 serde_plain::derive_display_from_serialize!(ParserImplOptions);
+serde_plain::derive_display_from_serialize!(NegativeBalanceOptions);
+serde_plain::derive_display_from_serialize!(RoundingOptions);
+serde_plain::derive_display_from_serialize!(DuplicateTxOptions);
+serde_plain::derive_display_from_serialize!(TransactionMapOptions);
+serde_plain::derive_display_from_serialize!(ClientIdOptions);
+serde_plain::derive_display_from_serialize!(JournalFormatOptions);
+
+/// The zzzzzzzzzzz program, it processes a csv unless a subcommand is given
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ZzCli {
+    #[command(subcommand)]
+    command: Option<ZzCommand>,
+    #[clap(flatten)]
+    process: ZzProcessCsvInput,
+}
+
+#[derive(Subcommand)]
+pub enum ZzCommand {
+    /// Rebuild the balances from a journal and check them against the balances it recorded
+    Replay(ZzReplayInput),
+    /// Recompute the journal from the input with the same options and report the first entry
+    /// that diverges, the signature of the balance sheet is checked too with --signing-key
+    Verify(Box<ZzVerifyInput>),
+}
+
+/// Input for the replay subcommand
+#[derive(Parser)]
+pub struct ZzReplayInput {
+    /// The journal written with --journal
+    journal: PathBuf,
+    #[arg(long, default_value_t = JournalFormatOptions::Csv)]
+    journal_format: JournalFormatOptions,
+}
+
+/// Input for the verify subcommand
+#[derive(Parser)]
+pub struct ZzVerifyInput {
+    #[clap(flatten)]
+    process: ZzProcessCsvInput,
+    /// The balance sheet the signature is checked against, the one recomputed from the input
+    /// by default
+    #[arg(long)]
+    sheet: Option<PathBuf>,
+}
 
 /// Input for the zzzzzzzzzzz program
 #[derive(Parser)]
 pub struct ZzProcessCsvInput {
     /// The relative path of the csv file
-    file: PathBuf,
+    #[arg(required = true)]
+    file: Option<PathBuf>,
     #[clap(flatten)]
     parse_options: ZzParseOptions,
     #[arg(long, default_value_t = ParserImplOptions::Nom)]
     parser: ParserImplOptions,
+    #[clap(flatten)]
+    policy_options: ZzPolicyOptions,
+    #[clap(flatten)]
+    storage_options: ZzStorageOptions,
+    #[clap(flatten)]
+    checkpoint_options: ZzCheckpointOptions,
+    #[clap(flatten)]
+    journal_options: ZzJournalOptions,
+    /// Only apply administrative transactions (unlock, freeze and adjustment) if the file is
+    /// signed with this key. The signature is the hex HMAC-SHA256 of the file contents and must
+    /// be in the same path as the file with an added .sig extension
+    #[arg(long)]
+    admin_key: Option<PathBuf>,
+    /// Apply administrative transactions without --admin-key, so any input can unlock accounts
+    /// or adjust balances. Without either they're rejected
+    #[arg(long, conflicts_with = "admin_key")]
+    allow_unsigned_admin: bool,
+    /// A csv with the fees charged per transaction type, see the README for its format. It
+    /// requires --fee-account
+    #[arg(long, requires = "fee_account")]
+    fee_schedule: Option<PathBuf>,
+    /// The client that collects the fees, in the format of --client-ids. It only receives fees,
+    /// the rows of the fee account and the transfers to it are rejected
+    #[arg(long)]
+    fee_account: Option<String>,
+    /// Adds to the balance sheet the transactions that make up held (held_by, as tx:amount
+    /// separated by ;) and the tx id and row number of the transaction that locked the account
+    #[arg(long)]
+    detailed_output: bool,
+    /// Writes how many transactions were rejected for each reason (e.g. insufficient-funds) to
+    /// stderr as a csv, after the other reports
+    #[arg(long)]
+    rejection_report: bool,
     // #[clap(flatten)]
     // execute_options: ZzExecuteOptions,
 }
@@ -75,22 +279,165 @@ pub struct ZzProcessCsvInput {
 pub struct ZzParseOptions {
     /// The maximum size of the integer part of a decimal which can be parsed
     #[arg(short, long, default_value_t = 200)]
-    zz_amount_max_size: u16,
+    pub zz_amount_max_size: u16,
     /// What to do if found a row with a missing field
     #[arg(long, default_value_t = ParsingStrictnessOptions::Fail)]
-    on_missing_field: ParsingStrictnessOptions,
+    pub on_missing_field: ParsingStrictnessOptions,
     /// What to do if found a row with a missing field
     #[arg(long, default_value_t = ParsingStrictnessOptions::Fail)]
-    on_excessive_field: ParsingStrictnessOptions,
+    pub on_excessive_field: ParsingStrictnessOptions,
     /// What to do if some parse error happens
     #[arg(long, default_value_t = ParsingStrictnessOptions::Fail)]
-    on_parse_error: ParsingStrictnessOptions,
+    pub on_parse_error: ParsingStrictnessOptions,
     /// The maximum line width, anything over this will fail
     #[arg(long, default_value_t = 4096)]
-    max_line_width: usize,
+    pub max_line_width: usize,
     /// If space trimming should be applied
     #[arg(long, default_value_t = false)]
-    dont_trim_spaces: bool,
+    pub dont_trim_spaces: bool,
+    /// What the client column holds, ids wider than u16 keep the balances in a hashmap
+    #[arg(long, default_value_t = ClientIdOptions::U16)]
+    pub client_ids: ClientIdOptions,
+    /// Stop processing after this row (the header is row 1), or before the first row with a
+    /// later timestamp if it's given as @<timestamp>. The whole file is processed by default
+    #[arg(long, value_parser = ZzAsOf::parse)]
+    pub as_of: Option<ZzAsOf>,
+}
+
+/// The point where processing stops, see --as-of
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZzAsOf {
+    /// The last row that is processed, counting the header
+    Row(u64),
+    /// The last timestamp that is processed, rows without a timestamp don't stop it
+    Timestamp(u64),
+}
+
+impl ZzAsOf {
+    /// A row number or @ followed by a timestamp
+    pub fn parse(as_of: &str) -> Result<Self, String> {
+        match as_of.strip_prefix('@') {
+            Some(timestamp) => timestamp.parse().map(Self::Timestamp),
+            None => as_of.parse().map(Self::Row),
+        }
+        .map_err(|_| format!("Expected a row number or @<timestamp>, got {as_of}"))
+    }
+}
+
+/// Business rules that deviate from the default behavior described in the README
+#[derive(Clone, Parser)]
+pub struct ZzPolicyOptions {
+    /// Allow disputes that reference a withdrawal. The disputed amount is held while under
+    /// review, a resolve releases it and a chargeback returns it to available
+    #[arg(long, default_value_t = false)]
+    pub allow_withdrawal_disputes: bool,
+    /// What to do if a dispute would make the available funds negative
+    #[arg(long, default_value_t = NegativeBalanceOptions::Allow)]
+    pub on_negative_balance: NegativeBalanceOptions,
+    /// Locked accounts still accept resolves and chargebacks of the disputes that were already
+    /// open, anything else is still ignored
+    #[arg(long, default_value_t = false)]
+    pub settle_disputes_after_lock: bool,
+    /// If unlock, freeze and adjustment transactions are applied, they're rejected as
+    /// unauthorized by default. The CLI sets this according to --admin-key or
+    /// --allow-unsigned-admin
+    #[arg(skip)]
+    pub allow_admin_transactions: bool,
+    /// The fees charged per transaction type. The CLI loads it from --fee-schedule
+    #[arg(skip)]
+    pub fee_schedule: Option<ZzFeeSchedule>,
+    /// How percentage fees are rounded to 4 decimal digits
+    #[arg(long, default_value_t = RoundingOptions::Down)]
+    pub fee_rounding: RoundingOptions,
+    /// The client that collects the fees. It only receives fees, the rows of the fee account and
+    /// the transfers to it are rejected. The CLI parses it from --fee-account
+    #[arg(skip)]
+    pub fee_account: Option<ClientId>,
+    /// The names of the string client ids of the options (the fee schedule and the fee account),
+    /// the engine starts with them
+    #[arg(skip)]
+    pub client_names: ZzClientNames,
+    /// Disputes are only accepted up to this many rows after the disputed transaction, or
+    /// timestamp units if the rows have a timestamp
+    #[arg(long)]
+    pub dispute_window: Option<u64>,
+    /// Open disputes are resolved automatically after this many rows, or timestamp units if the
+    /// rows have a timestamp
+    #[arg(long)]
+    pub dispute_resolve_deadline: Option<u64>,
+    /// What to do if a tx id is reused, by the same or by another client
+    #[arg(long, default_value_t = DuplicateTxOptions::Allow)]
+    pub on_duplicate_tx: DuplicateTxOptions,
+    /// A csv with the built-in risk rules that are evaluated before each transaction, see the
+    /// README for its format
+    #[arg(long, value_parser = ZzRiskRules::from_path)]
+    pub risk_rules: Option<ZzRiskRules>,
+    /// Drop the transactions that can't change anymore (charged back, voided, fully captured
+    /// and administrative) from the transaction map. Their tx ids can then be reused by the
+    /// same client
+    #[arg(long, default_value_t = false)]
+    pub evict_finalized: bool,
+    /// Drop deposits and withdrawals from the transaction map once they're older than this many
+    /// rows, or timestamp units if the rows have a timestamp. Later disputes of them are ignored
+    #[arg(long)]
+    pub eviction_horizon: Option<u64>,
+}
+
+/// How the transactions are stored, these don't change the output
+#[derive(Clone, Parser)]
+pub struct ZzStorageOptions {
+    #[arg(long, default_value_t = TransactionMapOptions::HashMap)]
+    pub transaction_map: TransactionMapOptions,
+    /// Where the disk transaction map creates its temporary directory, the system's temporary
+    /// directory by default
+    #[arg(long)]
+    pub spill_dir: Option<PathBuf>,
+    /// How many transactions the disk transaction map keeps in memory
+    #[arg(long, default_value_t = 1_000_000)]
+    pub hot_transactions: usize,
+}
+
+/// Saving the progress of a run so it can be continued if it dies
+#[derive(Clone, Parser)]
+pub struct ZzCheckpointOptions {
+    /// Where the checkpoint is written, periodically and when the process gets SIGTERM. Nothing
+    /// is written by default
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+    /// How many rows are processed between checkpoints
+    #[arg(long, default_value_t = 1_000_000)]
+    pub checkpoint_every: u64,
+    /// Continue from a checkpoint written for the same file and options, a checkpoint written
+    /// for another input or with other options is refused
+    #[arg(long)]
+    pub resume: Option<PathBuf>,
+    /// If set the loop writes a checkpoint after the current row and stops. The CLI sets this
+    /// on SIGTERM
+    #[arg(skip)]
+    pub interrupt: Arc<AtomicBool>,
+}
+
+/// Recording every change to the balances
+#[derive(Clone, Parser)]
+pub struct ZzJournalOptions {
+    /// Where each applied effect is written with the balance that resulted from it, see the
+    /// README for its fields. Nothing is written by default
+    #[arg(long)]
+    pub journal: Option<PathBuf>,
+    #[arg(long, default_value_t = JournalFormatOptions::Csv)]
+    pub journal_format: JournalFormatOptions,
+    /// Sign the SHA-256 of the last journal hash and the balance sheet with this key (as
+    /// HMAC-SHA256). The signature is written next to the journal with an added .sig extension
+    #[arg(long, requires = "journal")]
+    pub signing_key: Option<PathBuf>,
+    /// Writes the events (an account locked, a dispute opened, a withdrawal rejected for
+    /// insufficient funds or a client that ends up owing money) to stderr as they happen
+    #[arg(long)]
+    pub log_events: bool,
+    /// Writes the events to this file as json lines, see ZzJsonlObserver. A resumed run keeps
+    /// the events up to the checkpoint and appends after them
+    #[arg(long)]
+    pub events: Option<PathBuf>,
 }
 
 #[allow(dead_code)]
@@ -107,6 +454,60 @@ pub struct ZzExecuteOptions {
     total_buffers: Option<NonZeroU8>,
 }
 
+impl Default for ZzPolicyOptions {
+    fn default() -> Self {
+        Self {
+            allow_withdrawal_disputes: false,
+            on_negative_balance: NegativeBalanceOptions::Allow,
+            settle_disputes_after_lock: false,
+            allow_admin_transactions: false,
+            fee_schedule: None,
+            fee_rounding: RoundingOptions::Down,
+            fee_account: None,
+            client_names: ZzClientNames::default(),
+            dispute_window: None,
+            dispute_resolve_deadline: None,
+            on_duplicate_tx: DuplicateTxOptions::Allow,
+            risk_rules: None,
+            evict_finalized: false,
+            eviction_horizon: None,
+        }
+    }
+}
+
+impl Default for ZzStorageOptions {
+    fn default() -> Self {
+        Self {
+            transaction_map: TransactionMapOptions::HashMap,
+            spill_dir: None,
+            hot_transactions: 1_000_000,
+        }
+    }
+}
+
+impl Default for ZzJournalOptions {
+    fn default() -> Self {
+        Self {
+            journal: None,
+            journal_format: JournalFormatOptions::Csv,
+            signing_key: None,
+            log_events: false,
+            events: None,
+        }
+    }
+}
+
+impl Default for ZzCheckpointOptions {
+    fn default() -> Self {
+        Self {
+            checkpoint: None,
+            checkpoint_every: 1_000_000,
+            resume: None,
+            interrupt: Arc::default(),
+        }
+    }
+}
+
 impl Default for ZzParseOptions {
     fn default() -> Self {
         Self {
@@ -116,27 +517,239 @@ impl Default for ZzParseOptions {
             on_parse_error: ParsingStrictnessOptions::Fail,
             max_line_width: 4096,
             dont_trim_spaces: false,
+            client_ids: ClientIdOptions::U16,
+            as_of: None,
         }
     }
 }
 
-/// Process a csv and write the resulting csv to stdout. This doesn't
-pub fn process_csv(input: &ZzProcessCsvInput) {
-    let file = std::fs::File::open(&input.file).unwrap();
-    let client_balance_map = match input.parser {
-        ParserImplOptions::Nom => {
-            csv_zztx_parser_streaming(&mut CsvZzTxParserNomImpl, &file, &input.parse_options)
+/// Runs the subcommand, or processes the csv if there's none
+///
+/// # Errors
+///
+/// See ZzError, main exits with ZzError::exit_code
+pub fn run(cli: &ZzCli) -> ZzResult<()> {
+    match &cli.command {
+        Some(ZzCommand::Replay(input)) => replay(input),
+        Some(ZzCommand::Verify(input)) => verify(input),
+        None => process_csv(&cli.process),
+    }
+}
+
+/// The path of the signature of a file, the same path with an added .sig extension
+fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.to_path_buf().into_os_string();
+    signature_path.push(".sig");
+    signature_path.into()
+}
+
+/// Processes the csv writing the journal to the given options, what process_csv and verify have
+/// in common. Returns the policy after checking the admin key
+fn process(
+    input: &ZzProcessCsvInput,
+    journal_options: &ZzJournalOptions,
+) -> ZzResult<(ZzPolicyOptions, CsvZzTxParserOutput)> {
+    // clap requires the file
+    let path = input.file.as_ref().unwrap();
+    let file = std::fs::File::open(path)?;
+
+    let mut policy_options = input.policy_options.clone();
+    let client_ids = input.parse_options.client_ids;
+    if let Some(fee_schedule) = &input.fee_schedule {
+        policy_options.fee_schedule = Some(
+            ZzFeeSchedule::from_path(fee_schedule, client_ids, &policy_options.client_names)
+                .map_err(ZzError::Policy)?,
+        );
+    }
+    if let Some(fee_account) = &input.fee_account {
+        policy_options.fee_account = Some(
+            client_ids
+                .parse(fee_account, &policy_options.client_names)
+                .ok_or_else(|| ZzError::Policy(format!("Invalid fee account: {fee_account}")))?,
+        );
+    }
+    if let Some(admin_key) = &input.admin_key {
+        let key = std::fs::read(admin_key)?;
+
+        policy_options.allow_admin_transactions =
+            verify_file_signature(key.trim_ascii(), &file, &signature_path(path))?;
+        if !policy_options.allow_admin_transactions {
+            eprintln!(
+                "Input isn't signed by the admin key, administrative transactions are ignored"
+            );
         }
+    } else {
+        policy_options.allow_admin_transactions = input.allow_unsigned_admin;
+    }
+
+    let output = match input.parser {
+        ParserImplOptions::Nom => csv_zztx_parser_streaming(
+            &mut CsvZzTxParserNomImpl,
+            &file,
+            &input.parse_options,
+            &policy_options,
+            &input.storage_options,
+            &input.checkpoint_options,
+            journal_options,
+        )?,
         ParserImplOptions::Serde => csv_zztx_parser_streaming(
             &mut CsvZzTxParserSerdeImpl::default(),
             &file,
             &input.parse_options,
-        ),
+            &policy_options,
+            &input.storage_options,
+            &input.checkpoint_options,
+            journal_options,
+        )?,
     };
 
-    write_csv_client_balance_sheet(
-        client_balance_map.iter().filter_map(|x| x.as_ref()),
-        stdout(),
+    Ok((policy_options, output))
+}
+
+/// The balance sheet as process_csv writes it
+fn write_sheet(input: &ZzProcessCsvInput, output: &CsvZzTxParserOutput) -> ZzResult<Vec<u8>> {
+    let mut sheet = vec![];
+    if input.detailed_output {
+        write_csv_detailed_client_balance_sheet(
+            output.client_balances.iter(),
+            &output.holds,
+            &output.client_names,
+            &mut sheet,
+        )
+        .map_err(ZzError::Output)?;
+    } else {
+        write_csv_client_balance_sheet(
+            output.client_balances.iter(),
+            &output.client_names,
+            &mut sheet,
+        )
+        .map_err(ZzError::Output)?;
+    }
+    Ok(sheet)
+}
+
+/// Process a csv and write the resulting csv to stdout. This doesn't
+///
+/// # Errors
+///
+/// ZzError::Interrupted if it got SIGTERM with a checkpoint, the balance sheet isn't written
+/// then. Otherwise see csv_zztx_parser_streaming
+pub fn process_csv(input: &ZzProcessCsvInput) -> ZzResult<()> {
+    let checkpoint_options = &input.checkpoint_options;
+    if checkpoint_options.checkpoint.is_some() {
+        signal_hook::flag::register(
+            signal_hook::consts::SIGTERM,
+            checkpoint_options.interrupt.clone(),
+        )?;
+    }
+
+    let (policy_options, output) = process(input, &input.journal_options)?;
+
+    if output.interrupted {
+        return Err(ZzError::Interrupted {
+            checkpoint: checkpoint_options.checkpoint.clone().unwrap(),
+        });
+    }
+
+    if policy_options.evict_finalized || policy_options.eviction_horizon.is_some() {
+        eprintln!("Evicted {} transactions", output.evicted);
+    }
+
+    if policy_options.dispute_resolve_deadline.is_some() {
+        eprintln!("Auto-resolved {} disputes", output.auto_resolved);
+    }
+
+    let sheet = write_sheet(input, &output)?;
+    stdout().write_all(&sheet).map_err(ZzError::Output)?;
+
+    // clap requires a journal with the signing key
+    if let Some(signing_key) = &input.journal_options.signing_key
+        && let (Some(journal), Some(journal_head)) =
+            (&input.journal_options.journal, &output.journal_head)
+    {
+        let key = std::fs::read(signing_key)?;
+        let digest = sheet_digest(journal_head, &sheet);
+        std::fs::write(signature_path(journal), sign(key.trim_ascii(), &digest))?;
+    }
+
+    write_csv_negative_balance_report(
+        output.client_balances.iter(),
+        &output.client_names,
+        stderr(),
     )
-    .unwrap()
+    .map_err(ZzError::Output)?;
+
+    write_csv_duplicate_tx_report(output.duplicate_txs.iter(), &output.client_names, stderr())
+        .map_err(ZzError::Output)?;
+
+    if input.rejection_report {
+        write_csv_rejection_report(output.rejected.iter(), stderr()).map_err(ZzError::Output)?;
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the balances from a journal and writes them to stdout
+///
+/// # Errors
+///
+/// ZzError::Verification at the first balance that diverges from the one the journal recorded
+pub fn replay(input: &ZzReplayInput) -> ZzResult<()> {
+    let entries = read_journal(&input.journal, input.journal_format)?;
+
+    let (client_balances, client_names) = replay_journal(entries).map_err(ZzError::Verification)?;
+    write_csv_client_balance_sheet(client_balances.iter(), &client_names, stdout())
+        .map_err(ZzError::Output)
+}
+
+/// Recomputes the journal from the input and compares it with the one given by --journal, then
+/// checks the signature of the balance sheet if there's a signing key
+///
+/// # Errors
+///
+/// ZzError::Verification at the first divergence, otherwise see process_csv
+pub fn verify(input: &ZzVerifyInput) -> ZzResult<()> {
+    let Some(journal) = &input.process.journal_options.journal else {
+        return Err(ZzError::Policy(
+            "The journal to verify must be given with --journal".to_string(),
+        ));
+    };
+
+    let dir = tempfile::tempdir()?;
+    // the events were already reported by the run
+    let journal_options = ZzJournalOptions {
+        journal: Some(dir.path().join("journal")),
+        log_events: false,
+        events: None,
+        ..input.process.journal_options.clone()
+    };
+    let (_, output) = process(&input.process, &journal_options)?;
+
+    let format = journal_options.journal_format;
+    let (len, head) = verify_journal(
+        read_journal(journal_options.journal.as_ref().unwrap(), format)?,
+        read_journal(journal, format)?,
+    )
+    .map_err(ZzError::Verification)?;
+
+    if let Some(signing_key) = &journal_options.signing_key {
+        let key = std::fs::read(signing_key)?;
+        let sheet = match &input.sheet {
+            Some(sheet) => std::fs::read(sheet)?,
+            None => write_sheet(&input.process, &output)?,
+        };
+
+        if !verify_signature(
+            key.trim_ascii(),
+            &sheet_digest(&head, &sheet),
+            &signature_path(journal),
+        ) {
+            return Err(ZzError::Verification(
+                "The balance sheet isn't signed by the signing key".to_string(),
+            ));
+        }
+    }
+
+    eprintln!("Verified {len} journal entries");
+    Ok(())
 }
@@ -1,6 +1,9 @@
 use clap::Parser;
-use zzzzzzzzzzz::{ZzProcessCsvInput, process_csv};
+use zzzzzzzzzzz::{ZzCli, run};
 
 pub fn main() {
-    process_csv(&ZzProcessCsvInput::parse());
+    if let Err(err) = run(&ZzCli::parse()) {
+        eprintln!("{err}");
+        std::process::exit(err.exit_code());
+    }
 }
@@ -1,3 +1,5 @@
+#[cfg(feature = "async")]
+pub mod async_parser;
 pub mod csv_parser;
 pub mod nom;
 pub mod serde_parser;
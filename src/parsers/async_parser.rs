@@ -0,0 +1,306 @@
+use futures_util::{Stream, stream};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    ZzParseOptions, ZzPolicyOptions,
+    domain::{
+        client_balance::ZzClientBalance,
+        client_store::ZzClientNames,
+        engine::ZzEngine,
+        transaction::{Outcome, TransactionHashMapImpl, ZzTx},
+    },
+    error::ZzResult,
+    parsers::csv_parser::{
+        CsvZzTxParserTrait, check_row_width, is_past_as_of, is_past_as_of_row, parse_row,
+    },
+};
+
+/// How much is read from the reader at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// What process_stream yields
+#[derive(Debug, PartialEq)]
+pub enum ZzStreamItem {
+    /// A parsed transaction, the row it came from (the header is row 1) and what applying it did
+    Tx {
+        zztx: ZzTx,
+        row_number: u64,
+        outcome: Outcome,
+    },
+    /// The balances when the input ends, sorted by client id, with the names of the string
+    /// client ids. It's the last item
+    Balances(Vec<ZzClientBalance>, ZzClientNames),
+}
+
+/// The rows read so far and the engine they're applied to
+struct StreamState<ZzTxParser, R> {
+    parser: ZzTxParser,
+    reader: R,
+    parse_options: ZzParseOptions,
+    engine: ZzEngine<TransactionHashMapImpl>,
+    /// what was read and not split into rows yet starts at start, it's the start of a row
+    pending: Vec<u8>,
+    start: usize,
+    row_number: u64,
+    is_first: bool,
+    eof: bool,
+}
+
+impl<ZzTxParser: CsvZzTxParserTrait, R: AsyncRead + Unpin> StreamState<ZzTxParser, R> {
+    /// Reads until a row has a transaction, the balances are returned instead of the state when
+    /// the input ends or reaches ZzParseOptions::as_of
+    async fn next(mut self) -> (ZzResult<ZzStreamItem>, Option<Self>) {
+        match self.next_tx().await {
+            Ok(Some(item)) => (Ok(item), Some(self)),
+            Ok(None) => match self.engine.finish() {
+                Ok(output) => (
+                    Ok(ZzStreamItem::Balances(
+                        output.client_balances,
+                        output.client_names,
+                    )),
+                    None,
+                ),
+                Err(err) => (Err(err.into()), None),
+            },
+            Err(err) => (Err(err), None),
+        }
+    }
+
+    async fn next_tx(&mut self) -> ZzResult<Option<ZzStreamItem>> {
+        loop {
+            let end = match self.pending[self.start..].iter().position(|x| *x == b'\n') {
+                Some(len) => self.start + len,
+                None if !self.eof => {
+                    // the row is only counted once its end is found
+                    check_row_width(
+                        &self.parse_options,
+                        &self.pending[self.start..],
+                        self.row_number + 1,
+                    )?;
+                    self.pending.drain(..self.start);
+                    self.start = 0;
+
+                    self.pending.reserve(READ_SIZE);
+                    self.eof = self.reader.read_buf(&mut self.pending).await? == 0;
+                    continue;
+                }
+                // the last row doesn't need a newline
+                None if self.start < self.pending.len() => self.pending.len(),
+                None => return Ok(None),
+            };
+
+            let row = self.start..end;
+            self.start = (end + 1).min(self.pending.len());
+            self.row_number += 1;
+
+            if is_past_as_of_row(&self.parse_options, self.row_number) {
+                return Ok(None);
+            }
+
+            let Some(zztx) = parse_row(
+                &mut self.parser,
+                &self.parse_options,
+                self.engine.client_names(),
+                &mut self.is_first,
+                &self.pending[row],
+                self.row_number,
+            )?
+            else {
+                continue;
+            };
+
+            if is_past_as_of(&self.parse_options, &zztx) {
+                return Ok(None);
+            }
+
+            let outcome = self.engine.apply_at_row(zztx.clone(), self.row_number)?;
+            return Ok(Some(ZzStreamItem::Tx {
+                zztx,
+                row_number: self.row_number,
+                outcome,
+            }));
+        }
+    }
+}
+
+/// Parses the csv rows as they're read and applies each transaction, like
+/// csv_zztx_parser_streaming does for a file. Yields every parsed transaction and then the
+/// balances, the stream ends after the first error
+///
+/// There's no journal, checkpoint or observer, and the balances are kept in a hashmap
+pub fn process_stream<ZzTxParser: CsvZzTxParserTrait>(
+    parser: ZzTxParser,
+    reader: impl AsyncRead + Unpin,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+) -> impl Stream<Item = ZzResult<ZzStreamItem>> {
+    let state = StreamState {
+        parser,
+        reader,
+        parse_options: parse_options.clone(),
+        engine: ZzEngine::new(TransactionHashMapImpl::default(), policy_options.clone()),
+        pending: vec![],
+        start: 0,
+        row_number: 0,
+        is_first: true,
+        eof: false,
+    };
+
+    stream::unfold(Some(state), |state| async move {
+        let (item, state) = state?.next().await;
+        Some((item, state))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures_util::StreamExt;
+    use tokio::io::ReadBuf;
+
+    use super::*;
+    use crate::{
+        ZzAsOf,
+        domain::transaction::RejectReason,
+        error::ZzError,
+        parsers::{csv_parser::csv_zztx_parser_streaming, nom::CsvZzTxParserNomImpl},
+    };
+
+    /// Gives the bytes a few at a time, so rows are split between reads
+    struct Trickle<'a>(&'a [u8]);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let len = self.0.len().min(buf.remaining()).min(3);
+            buf.put_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn collect(input: &[u8], parse_options: &ZzParseOptions) -> Vec<ZzResult<ZzStreamItem>> {
+        process_stream(
+            CsvZzTxParserNomImpl,
+            Trickle(input),
+            parse_options,
+            &Default::default(),
+        )
+        .collect()
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        // it has no newline at the end
+        let path = "tests/test_cases/chargeback/input.csv";
+        let input = std::fs::read(path).unwrap();
+        let items = collect(&input, &Default::default()).await;
+
+        let expected = csv_zztx_parser_streaming(
+            &mut CsvZzTxParserNomImpl,
+            &std::fs::File::open(path).unwrap(),
+            &Default::default(),
+            &Default::default(),
+            &Default::default(),
+            &Default::default(),
+            &Default::default(),
+        )
+        .unwrap();
+
+        let (last, txs) = items.split_last().unwrap();
+        let rows: Vec<_> = txs
+            .iter()
+            .map(|item| match item {
+                Ok(ZzStreamItem::Tx {
+                    row_number,
+                    outcome,
+                    ..
+                }) => (*row_number, outcome.rejected()),
+                _ => panic!("Expected a transaction, got {item:?}"),
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (2, None),
+                (3, None),
+                (4, None),
+                (5, Some(RejectReason::InsufficientFunds)),
+                (6, None),
+                (7, None),
+            ]
+        );
+        assert_eq!(
+            last.as_ref().unwrap(),
+            &ZzStreamItem::Balances(expected.client_balances, expected.client_names)
+        );
+    }
+
+    /// It can be polled from a spawned task
+    #[test]
+    fn test_process_stream_is_send() {
+        fn assert_send(_: &impl Send) {}
+        assert_send(&process_stream(
+            CsvZzTxParserNomImpl,
+            Trickle(b""),
+            &Default::default(),
+            &Default::default(),
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_as_of() {
+        let input = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+        let parse_options = ZzParseOptions {
+            as_of: Some(ZzAsOf::Row(2)),
+            ..Default::default()
+        };
+        let items = collect(input, &parse_options).await;
+
+        assert_eq!(items.len(), 2);
+        match items[1].as_ref().unwrap() {
+            ZzStreamItem::Balances(balances, _) => {
+                assert_eq!(balances[0].available.to_string(), "1")
+            }
+            item => panic!("Expected the balances, got {item:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_errors() {
+        let items = collect(
+            b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1\n",
+            &Default::default(),
+        )
+        .await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(
+            items[1],
+            Err(ZzError::Parse { row_number: 3, .. })
+        ));
+
+        // the row is too long before its end is read
+        let parse_options = ZzParseOptions {
+            max_line_width: 24,
+            ..Default::default()
+        };
+        let items = collect(
+            b"type,client,tx,amount\ndeposit,1,1,1.00000000000000",
+            &parse_options,
+        )
+        .await;
+        assert_eq!(items.len(), 1);
+        assert!(matches!(
+            items[0],
+            Err(ZzError::Framing { row_number: 2, .. })
+        ));
+    }
+}
@@ -1,12 +1,34 @@
-use std::os::unix::fs::FileExt;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+use sha2::{Digest, Sha256};
 
 use crate::{
-    ZzParseOptions,
-    common::zz_amount::ZzIAmount,
+    ClientIdOptions, TransactionMapOptions, ZzAsOf, ZzCheckpointOptions, ZzJournalOptions,
+    ZzParseOptions, ZzPolicyOptions, ZzStorageOptions,
+    common::{
+        encoding::{invalid_data, read_u8, read_u64, write_u8, write_u64},
+        zz_amount::ZzUAmount,
+    },
     domain::{
-        client_balance::ZzClientBalance,
-        transaction::{TransactionHashMapImpl, TransactionMap, ZzTx},
+        client_balance::{ClientId, ZzClientBalance},
+        client_store::{ClientStore, DenseClientStore, ZzClientNames},
+        engine::ZzEngine,
+        fee::ZzFeeSchedule,
+        journal::{GENESIS_HASH, JournalHash, JournalPosition, ZzJournalWriter},
+        observer::{EngineObserver, ZzJsonlObserver, ZzLogObserver},
+        risk::{RiskRule, ZzRiskRules},
+        transaction::{
+            RejectReason, TransactionDiskMapImpl, TransactionHashMapImpl, TransactionMap, TxId,
+            ZzTx, disk_store::DiskStore,
+        },
     },
+    error::{ParseErrorKind, ZzError, ZzResult},
 };
 
 #[derive(Debug, PartialEq)]
@@ -21,164 +43,811 @@ pub trait CsvZzTxParserTrait {
     /// If the header matches the expected ZzTx headers. This will be used to handle if the header
     /// is present or not.
     fn deserialize_headers(&mut self, parse_options: &crate::ZzParseOptions, header: &str) -> bool;
-    /// Parse a row, the CsvParserControl
-    fn deserialize_row(&mut self, parse_options: &ZzParseOptions, row: &str) -> CsvParserResult;
+    /// Parse a row, the CsvParserControl. String client ids are interned in client_names
+    fn deserialize_row(
+        &mut self,
+        parse_options: &ZzParseOptions,
+        client_names: &ZzClientNames,
+        row: &str,
+    ) -> CsvParserResult;
+}
+
+pub struct CsvZzTxParserOutput {
+    /// Sorted by client id, see ClientStore::into_sorted
+    pub client_balances: Vec<ZzClientBalance>,
+    /// The names of the string client ids, to display the clients as they were in the input
+    pub client_names: ZzClientNames,
+    /// The transactions that reused a tx id, only tracked if DuplicateTxOptions is reject or warn
+    pub duplicate_txs: Vec<ZzTx>,
+    /// The transactions that hold funds when the input ends, see TransactionMap::holds
+    pub holds: Vec<(ClientId, TxId, ZzUAmount)>,
+    /// How many transactions were dropped from the transaction map, see TransactionMap::evicted
+    pub evicted: u64,
+    /// How many transactions were rejected for each reason, see ZzEngineOutput::rejected
+    pub rejected: Vec<(RejectReason, u64)>,
+    /// How many disputes were resolved by the deadline, see ZzEngineOutput::auto_resolved
+    pub auto_resolved: u64,
+    /// The hash of the last journal entry, if there's a journal
+    pub journal_head: Option<JournalHash>,
+    /// The loop stopped because ZzCheckpointOptions::interrupt was set, a checkpoint was written
+    /// and the balances are the ones up to it
+    pub interrupted: bool,
+    /// The balances of the watched client after each row that changed them, with the row
+    /// number. Only csv_client_snapshots watches a client
+    pub snapshots: Vec<(u64, ZzClientBalance)>,
 }
 
 /// This is the main function for the current parsing loop.
 ///
 /// If a csv file doesn't contain headers it'll still try to parse it as if it had headers
+///
+/// # Errors
+///
+/// Failed to read the input or write the journal or a checkpoint, a row that can't be split or
+/// parsed (see ParsingStrictnessOptions) or a transaction the policy fails on
 pub fn csv_zztx_parser_streaming<ZzTxParser: CsvZzTxParserTrait>(
     parser: &mut ZzTxParser,
     file: &std::fs::File,
     parse_options: &ZzParseOptions,
-) -> Vec<Option<ZzClientBalance>> {
-    let buf = &mut vec![0; 16 * 1024 * 1024];
-    let mut offset = 0;
-    // used to handle segmentation, it keeps the tail (last row) of the last read(). This is
-    // necessary because we assume the parsers only parse full rows
-    let mut tail = String::with_capacity(128);
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    journal_options: &ZzJournalOptions,
+) -> ZzResult<CsvZzTxParserOutput> {
+    let risk_rules = policy_options
+        .risk_rules
+        .as_ref()
+        .map(ZzRiskRules::build)
+        .unwrap_or_default();
 
-    let mut tx_map = TransactionHashMapImpl::default();
-    let mut client_balance_map: Vec<Option<ZzClientBalance>> = vec![None; u16::MAX as usize + 1];
-    // used to keep track if having/not having headers was verified.
-    let mut is_first = true;
+    csv_zztx_parser_streaming_with_rules(
+        parser,
+        file,
+        parse_options,
+        policy_options,
+        storage_options,
+        checkpoint_options,
+        journal_options,
+        risk_rules,
+    )
+}
 
-    macro_rules! error_on_big_row {
-        ($row:ident) => {
-            if $row.len() > parse_options.max_line_width {
-                panic!("Row too big");
-            }
-        };
-    }
+/// Same as csv_zztx_parser_streaming but evaluating the given risk rules instead of the ones
+/// configured in the policy
+#[allow(clippy::too_many_arguments)]
+pub fn csv_zztx_parser_streaming_with_rules<ZzTxParser: CsvZzTxParserTrait>(
+    parser: &mut ZzTxParser,
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    journal_options: &ZzJournalOptions,
+    risk_rules: Vec<Box<dyn RiskRule>>,
+) -> ZzResult<CsvZzTxParserOutput> {
+    csv_zztx_parser_streaming_with_storage(
+        parser,
+        file,
+        parse_options,
+        policy_options,
+        storage_options,
+        checkpoint_options,
+        journal_options,
+        risk_rules,
+        None,
+    )
+}
 
-    // SAFETY: client_map is instantiated with enough entries to take any u16
-    let mut process_tx = |zztx: ZzTx| {
-        let client_id = zztx.client_id;
-        let entry = &mut client_balance_map[client_id as usize];
+/// The balances of a client after each row that changed them, with the row number (the header
+/// is row 1). The rows are processed the same as in csv_zztx_parser_streaming, so the last
+/// snapshot is the balance in its output
+pub fn csv_client_snapshots<ZzTxParser: CsvZzTxParserTrait>(
+    parser: &mut ZzTxParser,
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    client_id: ClientId,
+) -> ZzResult<Vec<(u64, ZzClientBalance)>> {
+    let risk_rules = policy_options
+        .risk_rules
+        .as_ref()
+        .map(ZzRiskRules::build)
+        .unwrap_or_default();
 
-        if entry.as_ref().is_some_and(|x| x.locked) {
-            return;
-        }
+    csv_zztx_parser_streaming_with_storage(
+        parser,
+        file,
+        parse_options,
+        policy_options,
+        storage_options,
+        &Default::default(),
+        &Default::default(),
+        risk_rules,
+        Some(client_id),
+    )
+    .map(|output| output.snapshots)
+}
 
-        if let Some(effect) = tx_map.insert_transaction(zztx, entry.as_ref()) {
-            entry
-                .get_or_insert_with(|| ZzClientBalance {
-                    client_id,
-                    available: ZzIAmount::zero(),
-                    held: ZzIAmount::zero(),
-                    total: ZzIAmount::zero(),
-                    locked: false,
-                })
-                .process_tx_effect(effect);
+/// Picks the transaction map for the storage options
+#[allow(clippy::too_many_arguments)]
+fn csv_zztx_parser_streaming_with_storage<ZzTxParser: CsvZzTxParserTrait>(
+    parser: &mut ZzTxParser,
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    journal_options: &ZzJournalOptions,
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    watch: Option<ClientId>,
+) -> ZzResult<CsvZzTxParserOutput> {
+    let fingerprint = options_fingerprint(parse_options, policy_options, storage_options);
+    match storage_options.transaction_map {
+        TransactionMapOptions::HashMap => csv_zztx_parser_streaming_with_map(
+            parser,
+            file,
+            parse_options,
+            policy_options,
+            checkpoint_options,
+            fingerprint,
+            journal_options,
+            TransactionHashMapImpl::default(),
+            risk_rules,
+            watch,
+        ),
+        TransactionMapOptions::Disk => {
+            let spill_dir = storage_options
+                .spill_dir
+                .clone()
+                .unwrap_or_else(std::env::temp_dir);
+            csv_zztx_parser_streaming_with_map(
+                parser,
+                file,
+                parse_options,
+                policy_options,
+                checkpoint_options,
+                fingerprint,
+                journal_options,
+                TransactionDiskMapImpl::with_store(DiskStore::new(
+                    &spill_dir,
+                    storage_options.hot_transactions,
+                )?),
+                risk_rules,
+                watch,
+            )
         }
-    };
+    }
+}
 
-    loop {
-        let size = match file.read_at(buf, offset) {
-            Ok(x) => x,
-            Err(err) => panic!("{err}"),
-        };
+/// Picks the client store for the client ids, the dense one if they and the fee account fit in
+/// a u16
+#[allow(clippy::too_many_arguments)]
+fn csv_zztx_parser_streaming_with_map<ZzTxParser: CsvZzTxParserTrait, M: TransactionMap>(
+    parser: &mut ZzTxParser,
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    fingerprint: JournalHash,
+    journal_options: &ZzJournalOptions,
+    tx_map: M,
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    watch: Option<ClientId>,
+) -> ZzResult<CsvZzTxParserOutput> {
+    let fee_account_fits = policy_options
+        .fee_account
+        .is_none_or(|fee_account| u16::try_from(fee_account).is_ok());
+    match parse_options.client_ids {
+        ClientIdOptions::U16 if fee_account_fits => csv_zztx_parser_streaming_with_state(
+            parser,
+            file,
+            parse_options,
+            checkpoint_options,
+            fingerprint,
+            journal_options,
+            ZzEngine::with_clients(tx_map, DenseClientStore::default(), policy_options.clone())
+                .with_rules(risk_rules),
+            watch,
+        ),
+        ClientIdOptions::U16
+        | ClientIdOptions::U32
+        | ClientIdOptions::U64
+        | ClientIdOptions::String => csv_zztx_parser_streaming_with_state(
+            parser,
+            file,
+            parse_options,
+            checkpoint_options,
+            fingerprint,
+            journal_options,
+            ZzEngine::new(tx_map, policy_options.clone()).with_rules(risk_rules),
+            watch,
+        ),
+    }
+}
+
+/// Identifies a checkpoint file and its format version
+const CHECKPOINT_MAGIC: &[u8] = b"ZZCKPT\x0c";
+
+/// The SHA-256 of the options that change the output, a checkpoint is only resumed with the
+/// same ones
+fn options_fingerprint(
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+) -> JournalHash {
+    let fields = [
+        parse_options.zz_amount_max_size.to_string(),
+        parse_options.on_missing_field.to_string(),
+        parse_options.on_excessive_field.to_string(),
+        parse_options.on_parse_error.to_string(),
+        parse_options.max_line_width.to_string(),
+        parse_options.dont_trim_spaces.to_string(),
+        parse_options.client_ids.to_string(),
+        format!("{:?}", parse_options.as_of),
+        policy_options.allow_withdrawal_disputes.to_string(),
+        policy_options.on_negative_balance.to_string(),
+        policy_options.settle_disputes_after_lock.to_string(),
+        policy_options.allow_admin_transactions.to_string(),
+        format!(
+            "{:?}",
+            policy_options
+                .fee_schedule
+                .as_ref()
+                .map(ZzFeeSchedule::rows)
+        ),
+        policy_options.fee_rounding.to_string(),
+        format!("{:?}", policy_options.fee_account),
+        format!("{:?}", policy_options.dispute_window),
+        format!("{:?}", policy_options.dispute_resolve_deadline),
+        policy_options.on_duplicate_tx.to_string(),
+        format!("{:?}", policy_options.risk_rules),
+        policy_options.evict_finalized.to_string(),
+        format!("{:?}", policy_options.eviction_horizon),
+        storage_options.transaction_map.to_string(),
+    ];
+
+    Sha256::digest(fields.join("\n")).into()
+}
+
+/// The hash of the input up to the offset, it goes on with the rows after it
+fn hash_prefix(file: &File, len: u64) -> io::Result<Sha256> {
+    let mut hasher = Sha256::new();
+    let buf = &mut vec![0; 1024 * 1024];
+    let mut offset = 0;
+
+    while offset < len {
+        let size = buf.len().min((len - offset) as usize);
+        let size = file.read_at(&mut buf[..size], offset)?;
         if size == 0 {
             break;
         }
         offset += size as u64;
+        hasher.update(&buf[..size]);
+    }
 
-        let mut buf = str::from_utf8(&buf[..size]).unwrap();
+    Ok(hasher)
+}
 
-        if is_first {
-            let (first_row, rest) = buf.split_once('\n').unwrap_or((buf, ""));
+/// Where a checkpoint was taken
+struct CheckpointPosition {
+    /// See options_fingerprint
+    fingerprint: JournalHash,
+    /// The length of the input
+    input_len: u64,
+    /// The SHA-256 of the input up to the offset
+    prefix_hash: JournalHash,
+    /// The position in the input after the last processed row
+    offset: u64,
+    row_number: u64,
+}
 
-            if first_row.len() == buf.len() {
-                tail += first_row;
-                error_on_big_row!(tail);
+/// What a resumed run continues from, see read_checkpoint
+struct ResumedCheckpoint {
+    /// The position in the input after the last processed row
+    offset: u64,
+    row_number: u64,
+    /// The length and last hash of the journal, if there's one
+    journal: Option<JournalPosition>,
+    /// The length of the events file, if there's one
+    events_len: Option<u64>,
+    /// The hash of the input up to the offset
+    prefix: Sha256,
+}
 
-                continue;
-            }
+/// Writes the position in the input after the last processed row, what identifies the input and
+/// the options, the length and last hash of the journal and the length of the events file up to
+/// it and the state of the engine
+fn write_checkpoint<M: TransactionMap, C: ClientStore, O: EngineObserver>(
+    w: &mut dyn Write,
+    engine: &mut ZzEngine<M, C, O>,
+    position: &CheckpointPosition,
+    journal: Option<JournalPosition>,
+    events_len: Option<u64>,
+) -> io::Result<()> {
+    w.write_all(CHECKPOINT_MAGIC)?;
+    w.write_all(&position.fingerprint)?;
+    write_u64(w, position.input_len)?;
+    w.write_all(&position.prefix_hash)?;
+    write_u64(w, position.offset)?;
+    write_u64(w, position.row_number)?;
+    match journal {
+        Some((len, head)) => {
+            write_u8(w, 1)?;
+            write_u64(w, len)?;
+            w.write_all(&head)?;
+        }
+        None => write_u8(w, 0)?,
+    }
+    match events_len {
+        Some(len) => {
+            write_u8(w, 1)?;
+            write_u64(w, len)?;
+        }
+        None => write_u8(w, 0)?,
+    }
 
-            if parser.deserialize_headers(parse_options, first_row) {
-                buf = rest;
+    engine.write_checkpoint(w)
+}
+
+/// Restores what write_checkpoint wrote into a new engine, returns the offset and row number to
+/// continue from, see ResumedCheckpoint
+///
+/// # Errors
+///
+/// The checkpoint was written with other options or for another input, or it can't be read
+fn read_checkpoint<M: TransactionMap, C: ClientStore, O: EngineObserver>(
+    r: &mut dyn Read,
+    engine: &mut ZzEngine<M, C, O>,
+    parse_options: &ZzParseOptions,
+    fingerprint: &JournalHash,
+    file: &File,
+) -> ZzResult<ResumedCheckpoint> {
+    let mut magic = [0; CHECKPOINT_MAGIC.len()];
+    r.read_exact(&mut magic)?;
+    if magic != CHECKPOINT_MAGIC {
+        return Err(invalid_data("Not a checkpoint file".to_string()).into());
+    }
+
+    let mut checkpoint_fingerprint = GENESIS_HASH;
+    r.read_exact(&mut checkpoint_fingerprint)?;
+    if checkpoint_fingerprint != *fingerprint {
+        return Err(ZzError::Policy(
+            "The checkpoint was written with other parse, policy or storage options".to_string(),
+        ));
+    }
+
+    let input_len = read_u64(r)?;
+    let mut prefix_hash = GENESIS_HASH;
+    r.read_exact(&mut prefix_hash)?;
+    let offset = read_u64(r)?;
+    let row_number = read_u64(r)?;
+
+    let prefix = hash_prefix(file, offset)?;
+    if input_len != file.metadata()?.len() || prefix.clone().finalize()[..] != prefix_hash {
+        return Err(ZzError::Policy(
+            "The checkpoint was written for another input".to_string(),
+        ));
+    }
+
+    let journal = match read_u8(r)? {
+        1 => {
+            let len = read_u64(r)?;
+            let mut head = GENESIS_HASH;
+            r.read_exact(&mut head)?;
+            Some((len, head))
+        }
+        _ => None,
+    };
+    let events_len = match read_u8(r)? {
+        1 => Some(read_u64(r)?),
+        _ => None,
+    };
+
+    engine.read_checkpoint(parse_options.client_ids, r)?;
+
+    Ok(ResumedCheckpoint {
+        offset,
+        row_number,
+        journal,
+        events_len,
+        prefix,
+    })
+}
+
+/// Writes the checkpoint next to the path and renames it, so the previous one is kept whole if
+/// this fails
+fn save_checkpoint<M: TransactionMap, C: ClientStore, O: EngineObserver>(
+    path: &Path,
+    engine: &mut ZzEngine<M, C, O>,
+    position: &CheckpointPosition,
+    events: Option<&Path>,
+) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    // the entries and events of the rows before the checkpoint have to be in their files
+    let journal = engine.flush_journal()?;
+    engine.flush_observer()?;
+    let events_len = match events {
+        Some(events) => {
+            let events = File::open(events)?;
+            events.sync_data()?;
+            Some(events.metadata()?.len())
+        }
+        None => None,
+    };
+
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+    write_checkpoint(&mut w, engine, position, journal, events_len)?;
+    w.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)
+}
+
+/// A row, or the start of one that wasn't read whole yet, is longer than
+/// ZzParseOptions::max_line_width
+pub(crate) fn check_row_width(
+    parse_options: &ZzParseOptions,
+    row: &[u8],
+    row_number: u64,
+) -> ZzResult<()> {
+    if row.len() > parse_options.max_line_width {
+        return Err(ZzError::Framing {
+            row_number,
+            reason: format!(
+                "Row too big, it's longer than {} bytes",
+                parse_options.max_line_width
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Parses a complete row (without its newline), None if it's the header or a row that's
+/// ignored by the ParsingStrictnessOptions. The first row is only the header if the parser
+/// recognizes it, is_first is cleared after it. String client ids are interned in client_names
+///
+/// # Errors
+///
+/// Besides the row being invalid, ZzError::Policy if a string client id got the id of another
+/// one (see ZzClientNames::check)
+pub(crate) fn parse_row<ZzTxParser: CsvZzTxParserTrait>(
+    parser: &mut ZzTxParser,
+    parse_options: &ZzParseOptions,
+    client_names: &ZzClientNames,
+    is_first: &mut bool,
+    row: &[u8],
+    row_number: u64,
+) -> ZzResult<Option<ZzTx>> {
+    check_row_width(parse_options, row, row_number)?;
+    let row = str::from_utf8(row).map_err(|err| ZzError::Framing {
+        row_number,
+        reason: format!("Row isn't utf-8, {err}"),
+    })?;
+    let parse_error = |kind| ZzError::Parse {
+        row_number,
+        row: row.to_string(),
+        kind,
+    };
+
+    if *is_first {
+        *is_first = false;
+        if parser.deserialize_headers(parse_options, row) {
+            return Ok(None);
+        }
+    }
+
+    let result = parser.deserialize_row(parse_options, client_names, row);
+    client_names.check()?;
+
+    let zztx = match result {
+        CsvParserResult::Parsed(zztx) => zztx,
+        CsvParserResult::MissingRequiredField => {
+            if parse_options.on_missing_field.fail() {
+                return Err(parse_error(ParseErrorKind::MissingRequiredField));
+            } else {
+                return Ok(None);
             }
-            is_first = false;
         }
+        CsvParserResult::ContainsExcessiveFields(zztx) => match parse_options.on_excessive_field {
+            crate::ParsingStrictnessOptions::Fail => {
+                return Err(parse_error(ParseErrorKind::ContainsExcessiveFields));
+            }
+            crate::ParsingStrictnessOptions::Allow => zztx,
+            crate::ParsingStrictnessOptions::Ignore => return Ok(None),
+        },
+        CsvParserResult::Failed => {
+            if parse_options.on_parse_error.fail() {
+                return Err(parse_error(ParseErrorKind::Failed));
+            } else {
+                return Ok(None);
+            }
+        }
+    };
 
-        let (segmented, rest) = buf.split_once('\n').unwrap_or((buf, ""));
-        tail += segmented;
+    Ok(Some(zztx))
+}
 
-        let mut it = std::iter::once(segmented)
-            .chain(rest.split('\n'))
-            .peekable();
+/// The row is after ZzParseOptions::as_of, it isn't parsed
+pub(crate) fn is_past_as_of_row(parse_options: &ZzParseOptions, row_number: u64) -> bool {
+    matches!(parse_options.as_of, Some(ZzAsOf::Row(as_of)) if row_number > as_of)
+}
 
-        while let Some(row) = it.next() {
-            error_on_big_row!(row);
-            if it.peek().is_none() {
-                tail.clear();
-                tail.push_str(row);
+/// The transaction is after ZzParseOptions::as_of, processing stops before it
+pub(crate) fn is_past_as_of(parse_options: &ZzParseOptions, zztx: &ZzTx) -> bool {
+    matches!(
+        parse_options.as_of,
+        Some(ZzAsOf::Timestamp(as_of)) if zztx.timestamp.is_some_and(|timestamp| timestamp > as_of)
+    )
+}
+
+/// Resumes the checkpoint and registers the observers of the journal options, the engine keeps
+/// no observer if there are none so it doesn't look for the events. The checkpoint is read
+/// first so a resumed run truncates the events file to its length at the checkpoint
+#[allow(clippy::too_many_arguments)]
+fn csv_zztx_parser_streaming_with_state<
+    ZzTxParser: CsvZzTxParserTrait,
+    M: TransactionMap,
+    C: ClientStore,
+>(
+    parser: &mut ZzTxParser,
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    fingerprint: JournalHash,
+    journal_options: &ZzJournalOptions,
+    mut engine: ZzEngine<M, C>,
+    watch: Option<ClientId>,
+) -> ZzResult<CsvZzTxParserOutput> {
+    let mut resumed = None;
+    if let Some(resume) = &checkpoint_options.resume {
+        let mut r = BufReader::new(File::open(resume)?);
+        resumed = Some(read_checkpoint(
+            &mut r,
+            &mut engine,
+            parse_options,
+            &fingerprint,
+            file,
+        )?);
+    }
 
-                break;
+    let mut observers: Vec<Box<dyn EngineObserver>> = vec![];
+    if journal_options.log_events {
+        observers.push(Box::new(ZzLogObserver::new(engine.client_names().clone())));
+    }
+    if let Some(path) = &journal_options.events {
+        let len = match &resumed {
+            Some(ResumedCheckpoint {
+                events_len: None, ..
+            }) => {
+                return Err(ZzError::Policy(
+                    "The checkpoint was written without an events file".to_string(),
+                ));
             }
+            Some(resumed) => resumed.events_len.unwrap(),
+            None => 0,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+        observers.push(Box::new(ZzJsonlObserver::new(
+            BufWriter::new(file),
+            engine.client_names().clone(),
+        )));
+    }
 
-            // because we already know there's a next element, this row is complete
-            let zztx = match parser.deserialize_row(parse_options, row) {
-                CsvParserResult::Parsed(zztx) => zztx,
-                CsvParserResult::MissingRequiredField => {
-                    if parse_options.on_missing_field.fail() {
-                        panic!("Failed to parse csv. Row: {row}");
-                    } else {
-                        continue;
-                    }
-                }
-                CsvParserResult::ContainsExcessiveFields(zztx) => {
-                    match parse_options.on_excessive_field {
-                        crate::ParsingStrictnessOptions::Fail => {
-                            panic!("Failed to parse csv. Row: {row}")
-                        }
-                        crate::ParsingStrictnessOptions::Allow => zztx,
-                        crate::ParsingStrictnessOptions::Ignore => continue,
-                    }
-                }
-                CsvParserResult::Failed => {
-                    if parse_options.on_parse_error.fail() {
-                        panic!("Failed to parse csv. Row: {row}")
-                    } else {
-                        continue;
-                    }
-                }
-            };
+    if observers.is_empty() {
+        csv_zztx_parser_streaming_with_engine(
+            parser,
+            file,
+            parse_options,
+            checkpoint_options,
+            fingerprint,
+            journal_options,
+            resumed,
+            engine,
+            watch,
+        )
+    } else {
+        csv_zztx_parser_streaming_with_engine(
+            parser,
+            file,
+            parse_options,
+            checkpoint_options,
+            fingerprint,
+            journal_options,
+            resumed,
+            engine.with_observer(observers),
+            watch,
+        )
+    }
+}
+
+/// The parsing loop, each row is applied to the engine. It continues from the resumed
+/// checkpoint if there's one
+#[allow(clippy::too_many_arguments)]
+fn csv_zztx_parser_streaming_with_engine<
+    ZzTxParser: CsvZzTxParserTrait,
+    M: TransactionMap,
+    C: ClientStore,
+    O: EngineObserver,
+>(
+    parser: &mut ZzTxParser,
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    fingerprint: JournalHash,
+    journal_options: &ZzJournalOptions,
+    resumed: Option<ResumedCheckpoint>,
+    mut engine: ZzEngine<M, C, O>,
+    watch: Option<ClientId>,
+) -> ZzResult<CsvZzTxParserOutput> {
+    let buf = &mut vec![0; 16 * 1024 * 1024];
+    let mut offset = 0;
+    // used to handle segmentation, it keeps the start of the last row of the last read(). This
+    // is necessary because we assume the parsers only parse full rows
+    let mut pending = Vec::with_capacity(128);
+    // used to keep track if having/not having headers was verified.
+    let mut is_first = true;
+    let mut row_number = 0;
 
-            process_tx(zztx);
+    let input_len = file.metadata()?.len();
+    // the hash of the input before the current chunk, only kept up to date when checkpoints are
+    // written
+    let mut prefix = Sha256::new();
+
+    let mut journal = None;
+    let is_resumed = resumed.is_some();
+    if let Some(resumed) = resumed {
+        (offset, row_number, journal, prefix) = (
+            resumed.offset,
+            resumed.row_number,
+            resumed.journal,
+            resumed.prefix,
+        );
+        // checkpoints are only written after a complete row
+        is_first = false;
+    }
+
+    if let Some(path) = &journal_options.journal {
+        if is_resumed && journal.is_none() {
+            return Err(ZzError::Policy(
+                "The checkpoint was written without a journal".to_string(),
+            ));
         }
+        let (len, head) = journal.unwrap_or((0, GENESIS_HASH));
+        engine = engine.with_journal(ZzJournalWriter::open(
+            path,
+            journal_options.journal_format,
+            len,
+            head,
+        )?);
     }
 
-    if !tail.is_empty() {
-        error_on_big_row!(tail);
-        match parser.deserialize_row(parse_options, &tail) {
-            CsvParserResult::Parsed(zztx) => {
-                process_tx(zztx);
+    // the balances of the watched client after each row that changed them, they aren't
+    // checkpointed
+    let mut snapshots: Vec<(u64, ZzClientBalance)> = vec![];
+
+    // parses a complete row and applies its transaction, false if the row is past
+    // ZzParseOptions::as_of and the loop has to stop
+    let mut process_row =
+        |engine: &mut ZzEngine<M, C, O>, row: &[u8], row_number: u64| -> ZzResult<bool> {
+            if is_past_as_of_row(parse_options, row_number) {
+                return Ok(false);
             }
-            CsvParserResult::ContainsExcessiveFields(zztx) => {
-                match parse_options.on_excessive_field {
-                    crate::ParsingStrictnessOptions::Fail => panic!("Failed to parse csv {tail}"),
-                    crate::ParsingStrictnessOptions::Allow => process_tx(zztx),
-                    crate::ParsingStrictnessOptions::Ignore => {}
-                }
+
+            let Some(zztx) = parse_row(
+                parser,
+                parse_options,
+                engine.client_names(),
+                &mut is_first,
+                row,
+                row_number,
+            )?
+            else {
+                return Ok(true);
+            };
+
+            if is_past_as_of(parse_options, &zztx) {
+                return Ok(false);
             }
-            CsvParserResult::MissingRequiredField => {
-                if parse_options.on_missing_field.fail() {
-                    panic!("Failed to parse csv {tail}")
-                }
+
+            engine.apply_at_row(zztx, row_number)?;
+
+            if let Some(balance) = watch.and_then(|client_id| engine.balance(client_id))
+                && snapshots.last().is_none_or(|(_, last)| last != balance)
+            {
+                snapshots.push((row_number, balance.clone()));
+            }
+            Ok(true)
+        };
+
+    let mut interrupted = false;
+    let mut reached_as_of = false;
+
+    'read: loop {
+        let size = file.read_at(buf, offset)?;
+        if size == 0 {
+            break;
+        }
+        let chunk = &buf[..size];
+        let chunk_offset = offset;
+        offset += size as u64;
+
+        let mut start = 0;
+        while let Some(len) = chunk[start..].iter().position(|x| *x == b'\n') {
+            let end = start + len;
+            row_number += 1;
+
+            let processed = if pending.is_empty() {
+                process_row(&mut engine, &chunk[start..end], row_number)?
+            } else {
+                pending.extend_from_slice(&chunk[start..end]);
+                let processed = process_row(&mut engine, &pending, row_number)?;
+                pending.clear();
+                processed
+            };
+            if !processed {
+                reached_as_of = true;
+                break 'read;
             }
-            CsvParserResult::Failed => {
-                if parse_options.on_parse_error.fail() {
-                    panic!("Failed to parse csv {tail}")
+            start = end + 1;
+
+            if let Some(path) = &checkpoint_options.checkpoint {
+                interrupted = checkpoint_options.interrupt.load(Ordering::Relaxed);
+                if interrupted || row_number.is_multiple_of(checkpoint_options.checkpoint_every) {
+                    let mut prefix = prefix.clone();
+                    prefix.update(&chunk[..start]);
+                    let position = CheckpointPosition {
+                        fingerprint,
+                        input_len,
+                        prefix_hash: prefix.finalize().into(),
+                        offset: chunk_offset + start as u64,
+                        row_number,
+                    };
+                    save_checkpoint(
+                        path,
+                        &mut engine,
+                        &position,
+                        journal_options.events.as_deref(),
+                    )?;
+                }
+                if interrupted {
+                    break 'read;
                 }
             }
         }
+
+        if checkpoint_options.checkpoint.is_some() {
+            prefix.update(chunk);
+        }
+        pending.extend_from_slice(&chunk[start..]);
+        // the row is only counted once its end is found
+        check_row_width(parse_options, &pending, row_number + 1)?;
     }
 
-    for client in client_balance_map.iter_mut().flatten() {
-        client.compute_total();
+    if !interrupted && !reached_as_of && !pending.is_empty() {
+        row_number += 1;
+        process_row(&mut engine, &pending, row_number)?;
     }
 
-    client_balance_map
+    let output = engine.finish()?;
+
+    Ok(CsvZzTxParserOutput {
+        client_balances: output.client_balances,
+        client_names: output.client_names,
+        holds: output.holds,
+        evicted: output.evicted,
+        rejected: output.rejected,
+        auto_resolved: output.auto_resolved,
+        journal_head: output.journal_head,
+        duplicate_txs: output.duplicate_txs,
+        interrupted,
+        snapshots,
+    })
 }
@@ -1,4 +1,7 @@
-use crate::parsers::csv_parser::{CsvParserResult, CsvZzTxParserTrait};
+use crate::{
+    domain::client_store::ZzClientNames,
+    parsers::csv_parser::{CsvParserResult, CsvZzTxParserTrait},
+};
 
 pub mod tx_parser;
 pub mod zz_amount;
@@ -13,9 +16,10 @@ impl CsvZzTxParserTrait for CsvZzTxParserNomImpl {
     fn deserialize_row(
         &mut self,
         parse_options: &crate::ZzParseOptions,
+        client_names: &ZzClientNames,
         row: &str,
     ) -> CsvParserResult {
-        tx_parser::parse_zztx_csv(parse_options, row)
+        tx_parser::parse_zztx_csv(parse_options, client_names, row)
             .map(|(_, res)| res)
             .unwrap_or(CsvParserResult::Failed)
     }
@@ -1,16 +1,22 @@
 use nom::{
     IResult, Parser,
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{is_not, tag},
     character::complete::{char, digit1, multispace0},
-    combinator::{eof, map_res, opt, recognize},
+    combinator::{eof, map_opt, map_res, opt, recognize, rest},
     sequence::terminated,
 };
 
 use crate::{
     ZzParseOptions,
-    domain::transaction::{ZzTx, ZzTxType},
-    parsers::{csv_parser::CsvParserResult, nom::zz_amount::parse_zzamount_u},
+    domain::{
+        client_store::ZzClientNames,
+        transaction::{ZzTx, ZzTxType},
+    },
+    parsers::{
+        csv_parser::CsvParserResult,
+        nom::zz_amount::{parse_zzamount_i, parse_zzamount_u},
+    },
 };
 
 fn wrap_field<'a, P: Parser<&'a str, Error = nom::error::Error<&'a str>>>(
@@ -52,20 +58,25 @@ pub fn parse_zztx_csv_headers<'a>(
 /// initial_input: is a string that should start with the row
 /// expect_eof: is the end of input actually the EOF of the parsed csv
 ///
+/// The optional 5th column (details) is required by adjustments (reason) and transfers
+/// (destination client) and excessive for all other transactions. The optional 6th column is the
+/// timestamp, accepted by every transaction. String client ids are interned in client_names
+///
 /// # Errors
 ///
 /// If expect_eof == false and the starting row is not terminated by \n
 /// Malformatted row
 pub fn parse_zztx_csv<'a>(
     parse_options: &ZzParseOptions,
+    client_names: &ZzClientNames,
     input: &'a str,
 ) -> IResult<&'a str, CsvParserResult> {
-    fn parse_u16(input: &str) -> IResult<&str, u16> {
-        map_res(digit1, str::parse::<u16>).parse(input)
-    }
     fn parse_u32(input: &str) -> IResult<&str, u32> {
         map_res(digit1, str::parse::<u32>).parse(input)
     }
+    fn parse_u64(input: &str) -> IResult<&str, u64> {
+        map_res(digit1, str::parse::<u64>).parse(input)
+    }
 
     let tx_type_parser = wrap_field(
         alt((
@@ -74,19 +85,31 @@ pub fn parse_zztx_csv<'a>(
             tag("dispute"),
             tag("resolve"),
             tag("chargeback"),
+            tag("authorize"),
+            tag("capture"),
+            tag("void"),
+            tag("transfer"),
+            tag("unlock"),
+            tag("freeze"),
+            tag("adjustment"),
         )),
         parse_options,
     );
-    let client_id_parser = wrap_field(parse_u16, parse_options);
+    let client_id_parser = wrap_field(
+        map_opt(is_not(", \t\r\n"), |client| {
+            parse_options.client_ids.parse(client, client_names)
+        }),
+        parse_options,
+    );
     let tx_id_parser = wrap_field(parse_u32, parse_options);
 
+    // the sign is only accepted by adjustments, so the amount is converted after knowing the type
     let zz_amount_parser = wrap_field(
-        map_res(recognize((digit1, opt((char('.'), digit1)))), |s: &str| {
-            let (_, amt) = parse_zzamount_u(parse_options, s)?;
-            Ok::<_, nom::Err<nom::error::Error<&str>>>(amt)
-        }),
+        recognize((opt(char('-')), digit1, opt((char('.'), digit1)))),
         parse_options,
     );
+    let details_parser = wrap_field(is_not(","), parse_options);
+    let timestamp_parser = wrap_field(parse_u64, parse_options);
 
     // tx type
     let (input, tx_type_str) = tx_type_parser(input)?;
@@ -109,49 +132,124 @@ pub fn parse_zztx_csv<'a>(
     // amount
     let (input, zz_amount) = zz_amount_parser(input)?;
 
+    // details
+    let (input, details) = details_parser(input)?;
+    let details = details
+        .map(str::trim_end)
+        .filter(|details| !details.is_empty());
+
+    // timestamp
+    let (input, timestamp) = timestamp_parser(input)?;
+
     let build_tx = move |r#type: ZzTxType| ZzTx {
         r#type,
         client_id,
         tx_id,
+        timestamp,
     };
 
     let res = match (tx_type_str, zz_amount) {
-        ("deposit", Some(amount)) => CsvParserResult::Parsed(build_tx(ZzTxType::Deposit(amount))),
+        ("deposit", Some(amount)) => {
+            let (_, amount) = parse_zzamount_u(parse_options, amount)?;
+            CsvParserResult::Parsed(build_tx(ZzTxType::Deposit(amount)))
+        }
         ("withdrawal", Some(amount)) => {
+            let (_, amount) = parse_zzamount_u(parse_options, amount)?;
             CsvParserResult::Parsed(build_tx(ZzTxType::Withdrawal(amount)))
         }
-        ("dispute", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Dispute)),
+        ("transfer", Some(amount)) => {
+            let (_, amount) = parse_zzamount_u(parse_options, amount)?;
+            let Some(to) = details else {
+                return Ok((input, CsvParserResult::MissingRequiredField));
+            };
+            let (_, to) =
+                map_opt(rest, |to| parse_options.client_ids.parse(to, client_names)).parse(to)?;
+
+            return Ok((
+                input,
+                CsvParserResult::Parsed(build_tx(ZzTxType::Transfer { to, amount })),
+            ));
+        }
+        ("adjustment", Some(amount)) => {
+            let (_, amount) = parse_zzamount_i(parse_options, amount)?;
+            let Some(reason) = details else {
+                return Ok((input, CsvParserResult::MissingRequiredField));
+            };
+
+            return Ok((
+                input,
+                CsvParserResult::Parsed(build_tx(ZzTxType::Adjustment {
+                    amount,
+                    reason: reason.to_string(),
+                })),
+            ));
+        }
+        ("dispute", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Dispute(None))),
+        ("dispute", Some(amount)) => {
+            let (_, amount) = parse_zzamount_u(parse_options, amount)?;
+            CsvParserResult::Parsed(build_tx(ZzTxType::Dispute(Some(amount))))
+        }
+        ("authorize", Some(amount)) => {
+            let (_, amount) = parse_zzamount_u(parse_options, amount)?;
+            CsvParserResult::Parsed(build_tx(ZzTxType::Authorize(amount)))
+        }
+        ("capture", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Capture(None))),
+        ("capture", Some(amount)) => {
+            let (_, amount) = parse_zzamount_u(parse_options, amount)?;
+            CsvParserResult::Parsed(build_tx(ZzTxType::Capture(Some(amount))))
+        }
+        ("void", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Void)),
         ("resolve", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Resolve)),
         ("chargeback", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Chargeback)),
-        ("deposit", None) | ("withdrawal", None) => {
+        ("unlock", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Unlock)),
+        ("freeze", None) => CsvParserResult::Parsed(build_tx(ZzTxType::Freeze)),
+        ("deposit", None)
+        | ("withdrawal", None)
+        | ("authorize", None)
+        | ("transfer", None)
+        | ("adjustment", None) => {
             return Ok((input, CsvParserResult::MissingRequiredField));
         }
-        ("dispute", Some(_)) => {
-            CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Dispute))
-        }
         ("resolve", Some(_)) => {
             CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Resolve))
         }
         ("chargeback", Some(_)) => {
             CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Chargeback))
         }
+        ("void", Some(_)) => CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Void)),
+        ("unlock", Some(_)) => CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Unlock)),
+        ("freeze", Some(_)) => CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Freeze)),
         _ => unreachable!("tx_type_parser guards the possible values"),
     };
 
+    let res = match res {
+        CsvParserResult::Parsed(zztx) if details.is_some() => {
+            CsvParserResult::ContainsExcessiveFields(zztx)
+        }
+        res => res,
+    };
+
     Ok((input, res))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::transaction::{ZzTxSerializeCsv, ZzTxType};
+    use crate::{
+        ClientIdOptions,
+        common::zz_amount::ZzUAmount,
+        domain::{
+            client_store::ZzClientNames,
+            transaction::{ZzTxSerializeCsv, ZzTxType},
+        },
+    };
     use fake::{Fake, Faker};
 
     #[test]
     fn test_parse_zztx_happy_path() {
         let opts = &ZzParseOptions::default();
 
-        let (_, ctrl) = parse_zztx_csv(opts, "deposit,1,10,50").unwrap();
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "deposit,1,10,50").unwrap();
         match ctrl {
             CsvParserResult::Parsed(tx) => match tx.r#type {
                 ZzTxType::Deposit(amount) => assert_eq!(amount.to_string(), "50"),
@@ -160,7 +258,7 @@ mod tests {
             _ => panic!("Expected Parsed"),
         }
 
-        let (_, ctrl) = parse_zztx_csv(opts, "withdrawal,2,20,30").unwrap();
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "withdrawal,2,20,30").unwrap();
         match ctrl {
             CsvParserResult::Parsed(tx) => match tx.r#type {
                 ZzTxType::Withdrawal(amount) => assert_eq!(amount.to_string(), "30"),
@@ -169,9 +267,9 @@ mod tests {
             _ => panic!("Expected Parsed"),
         }
 
-        let (_, ctrl) = parse_zztx_csv(opts, "dispute,3,30,").unwrap();
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "dispute,3,30,").unwrap();
         match ctrl {
-            CsvParserResult::Parsed(tx) => assert!(matches!(tx.r#type, ZzTxType::Dispute)),
+            CsvParserResult::Parsed(tx) => assert!(matches!(tx.r#type, ZzTxType::Dispute(None))),
             _ => panic!("Expected Parsed"),
         }
     }
@@ -180,7 +278,7 @@ mod tests {
     fn test_missing_field_behavior() {
         let opts = &mut ZzParseOptions::default();
 
-        let (_, ctrl) = parse_zztx_csv(opts, "deposit,1,10").unwrap();
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "deposit,1,10").unwrap();
         assert!(matches!(ctrl, CsvParserResult::MissingRequiredField));
     }
 
@@ -188,28 +286,208 @@ mod tests {
     fn test_excessive_field_behavior() {
         let opts = &mut ZzParseOptions::default();
 
-        // Dispute should not have amount → Fail
-        let (_, ctrl) = parse_zztx_csv(opts, "dispute,1,42,999").unwrap();
+        // Resolve should not have amount → Fail
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "resolve,1,42,999").unwrap();
         assert_eq!(
             ctrl,
             CsvParserResult::ContainsExcessiveFields(ZzTx {
-                r#type: ZzTxType::Dispute,
+                r#type: ZzTxType::Resolve,
                 client_id: 1,
-                tx_id: 42
+                tx_id: 42,
+                timestamp: None
             })
         );
     }
 
+    #[test]
+    fn test_partial_dispute() {
+        let opts = &ZzParseOptions::default();
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "dispute,1,42,2.5").unwrap();
+        match ctrl {
+            CsvParserResult::Parsed(tx) => match tx.r#type {
+                ZzTxType::Dispute(Some(amount)) => assert_eq!(amount.to_string(), "2.5000"),
+                _ => panic!("Expected partial Dispute"),
+            },
+            _ => panic!("Expected Parsed"),
+        }
+
+        assert!(parse_zztx_csv(opts, &Default::default(), "dispute,1,42,-2.5").is_err());
+    }
+
+    #[test]
+    fn test_admin_transactions() {
+        let opts = &ZzParseOptions::default();
+
+        let (_, ctrl) = parse_zztx_csv(
+            opts,
+            &Default::default(),
+            "adjustment,1,42,-2.5, wrong deposit ",
+        )
+        .unwrap();
+        assert_eq!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Adjustment {
+                    amount: crate::common::zz_amount::ZzIAmount::new((-2).into(), 5000).unwrap(),
+                    reason: "wrong deposit".to_string(),
+                },
+                client_id: 1,
+                tx_id: 42,
+                timestamp: None
+            })
+        );
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "adjustment,1,42,2.5").unwrap();
+        assert_eq!(ctrl, CsvParserResult::MissingRequiredField);
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "freeze,1,42,").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Freeze,
+                ..
+            })
+        ));
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "unlock,1,42").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Unlock,
+                ..
+            })
+        ));
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "unlock,1,42,1").unwrap();
+        assert!(matches!(ctrl, CsvParserResult::ContainsExcessiveFields(_)));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let opts = &ZzParseOptions::default();
+
+        let (_, ctrl) =
+            parse_zztx_csv(opts, &Default::default(), "transfer, 1, 42, 2.5, 7").unwrap();
+        match ctrl {
+            CsvParserResult::Parsed(tx) => {
+                assert_eq!(tx.client_id, 1);
+                assert_eq!(tx.tx_id, 42);
+                match tx.r#type {
+                    ZzTxType::Transfer { to, amount } => {
+                        assert_eq!(to, 7);
+                        assert_eq!(amount.to_string(), "2.5000");
+                    }
+                    _ => panic!("Expected Transfer"),
+                }
+            }
+            _ => panic!("Expected Parsed"),
+        }
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "transfer,1,42,2.5").unwrap();
+        assert_eq!(ctrl, CsvParserResult::MissingRequiredField);
+
+        assert!(parse_zztx_csv(opts, &Default::default(), "transfer,1,42,2.5,someone").is_err());
+        assert!(parse_zztx_csv(opts, &Default::default(), "transfer,1,42,-2.5,7").is_err());
+    }
+
+    #[test]
+    fn test_details_only_allowed_for_adjustments() {
+        let opts = &ZzParseOptions::default();
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "deposit,1,42,1,reason").unwrap();
+        assert!(matches!(ctrl, CsvParserResult::ContainsExcessiveFields(_)));
+
+        // empty details are the same as missing
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "deposit,1,42,1, ").unwrap();
+        assert!(matches!(ctrl, CsvParserResult::Parsed(_)));
+
+        // only adjustments can be negative
+        assert!(parse_zztx_csv(opts, &Default::default(), "deposit,1,42,-1").is_err());
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let opts = &ZzParseOptions::default();
+
+        let (_, ctrl) =
+            parse_zztx_csv(opts, &Default::default(), "deposit,1,42,1,,1700000000").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                timestamp: Some(1700000000),
+                ..
+            })
+        ));
+
+        let (_, ctrl) =
+            parse_zztx_csv(opts, &Default::default(), "dispute, 1, 42, , , 1700000001").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Dispute(None),
+                timestamp: Some(1700000001),
+                ..
+            })
+        ));
+
+        assert!(parse_zztx_csv(opts, &Default::default(), "deposit,1,42,1,,yesterday").is_err());
+    }
+
+    #[test]
+    fn test_authorizations() {
+        let opts = &ZzParseOptions::default();
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "authorize,1,42,10").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Authorize(_),
+                ..
+            })
+        ));
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "authorize,1,42,").unwrap();
+        assert_eq!(ctrl, CsvParserResult::MissingRequiredField);
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "capture,1,42,").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Capture(None),
+                ..
+            })
+        ));
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "capture,1,42,2.5").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Capture(Some(_)),
+                ..
+            })
+        ));
+
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "void,1,42,").unwrap();
+        assert!(matches!(
+            ctrl,
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Void,
+                ..
+            })
+        ));
+        let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), "void,1,42,1").unwrap();
+        assert!(matches!(ctrl, CsvParserResult::ContainsExcessiveFields(_)));
+    }
+
     #[test]
     fn test_invalid_or_garbage() {
         let opts = &ZzParseOptions::default();
 
         // Unknown transaction type → fail
-        let res = parse_zztx_csv(opts, "foobar,1,2,3");
+        let res = parse_zztx_csv(opts, &Default::default(), "foobar,1,2,3");
         assert!(res.is_err());
 
         // Trailing garbage → fail
-        let res = parse_zztx_csv(opts, "deposit,1,2,30xxx");
+        let res = parse_zztx_csv(opts, &Default::default(), "deposit,1,2,30xxx");
         assert!(res.is_err());
     }
 
@@ -217,7 +495,8 @@ mod tests {
     fn test_with_spaces_variations() {
         let opts = &ZzParseOptions::default();
 
-        let (_, ctrl) = parse_zztx_csv(opts, "deposit ,   42 ,  99 ,   1000").unwrap();
+        let (_, ctrl) =
+            parse_zztx_csv(opts, &Default::default(), "deposit ,   42 ,  99 ,   1000").unwrap();
         match ctrl {
             CsvParserResult::Parsed(tx) => {
                 assert_eq!(tx.client_id, 42);
@@ -233,20 +512,65 @@ mod tests {
 
     #[test]
     fn test_fuzz_roundtrip() {
-        let opts = &ZzParseOptions::default();
+        let opts = &ZzParseOptions {
+            client_ids: ClientIdOptions::U64,
+            ..Default::default()
+        };
 
         for _ in 0..50 {
             let tx: ZzTx = Faker.fake();
 
             // serialize to CSV
-            let line = format!("{}", ZzTxSerializeCsv(tx.clone()));
+            let line = ZzTxSerializeCsv(&tx, &Default::default()).to_string();
 
             // parse back
-            let (_, ctrl) = parse_zztx_csv(opts, &line).unwrap();
+            let (_, ctrl) = parse_zztx_csv(opts, &Default::default(), &line).unwrap();
             match ctrl {
                 CsvParserResult::Parsed(parsed) => assert_eq!(tx, parsed),
                 _ => panic!("Expected Parsed"),
             }
         }
     }
+
+    #[test]
+    fn test_client_ids() {
+        let client_names = ZzClientNames::default();
+        let parse = |client_ids, row| {
+            let opts = ZzParseOptions {
+                client_ids,
+                ..Default::default()
+            };
+            match parse_zztx_csv(&opts, &client_names, row) {
+                Ok((_, CsvParserResult::Parsed(tx))) => Some(tx),
+                _ => None,
+            }
+        };
+
+        assert!(parse(ClientIdOptions::U16, "deposit,70000,1,1").is_none());
+        let tx = parse(ClientIdOptions::U32, "deposit,70000,1,1").unwrap();
+        assert_eq!(tx.client_id, 70000);
+        assert!(parse(ClientIdOptions::U32, "deposit,5000000000,1,1").is_none());
+        let tx = parse(ClientIdOptions::U64, "deposit,5000000000,1,1").unwrap();
+        assert_eq!(tx.client_id, 5_000_000_000);
+        assert!(parse(ClientIdOptions::U64, "deposit,abc,1,1").is_none());
+
+        let tx = parse(
+            ClientIdOptions::String,
+            "transfer, 3f2a-c0ffee , 1, 1, acct-9",
+        )
+        .unwrap();
+        assert_eq!(tx.client_id, ZzClientNames::id("3f2a-c0ffee"));
+        assert_eq!(
+            tx.r#type,
+            ZzTxType::Transfer {
+                to: ZzClientNames::id("acct-9"),
+                amount: ZzUAmount::new(1u32.into(), 0).unwrap(),
+            }
+        );
+        assert_eq!(
+            ZzTxSerializeCsv(&tx, &client_names).to_string(),
+            "transfer,3f2a-c0ffee,1,1,acct-9"
+        );
+        assert!(parse(ClientIdOptions::String, "deposit,a b,1,1").is_none());
+    }
 }
@@ -20,9 +20,16 @@ pub fn parse_zzamount_i<'a>(
 ) -> IResult<&'a str, ZzIAmount> {
     let (input, (int, decimal)) = parse_zzamount_inner::<BigInt>(parse_options, initial_input)?;
 
+    let is_negative_fraction = int == BigInt::ZERO && initial_input.starts_with('-');
+    let amount = ZzIAmount::new(int, decimal).expect("Parser above guarantees only 4 digits");
+    // the integer part of -0.x is zero, so it doesn't carry the sign
     Ok((
         input,
-        ZzIAmount::new(int, decimal).expect("Parser above guarantees only 4 digits"),
+        if is_negative_fraction {
+            amount.unary()
+        } else {
+            amount
+        },
     ))
 }
 
@@ -92,6 +99,14 @@ mod tests {
     use super::*;
     use fake::{Fake, Faker};
 
+    #[test]
+    fn test_parse_negative_fraction() {
+        let opts = ZzParseOptions::default();
+        let (_, amt) = parse_zzamount_i(&opts, "-0.25").unwrap();
+        assert_eq!(amt.to_string(), "-0.2500");
+        assert!(parse_zzamount_u(&opts, "-0.25").is_err());
+    }
+
     #[test]
     fn test_parse_integer() {
         let opts = ZzParseOptions::default();
@@ -4,7 +4,10 @@ use serde::Deserialize;
 
 use crate::{
     ZzParseOptions,
-    domain::transaction::{ZzTx, ZzTxType, ZzTxTypeDiscriminants},
+    domain::{
+        client_store::ZzClientNames,
+        transaction::{ZzTx, ZzTxType, ZzTxTypeDiscriminants},
+    },
     parsers::csv_parser::{CsvParserResult, CsvZzTxParserTrait},
 };
 
@@ -12,30 +15,89 @@ use crate::{
 pub struct ZzTxSerde<'a> {
     r#type: Option<ZzTxTypeDiscriminants>,
     #[serde(rename = "client")]
-    client_id: Option<u16>,
+    client_id: Option<&'a str>,
     #[serde(rename = "tx")]
     tx_id: Option<u32>,
     amount: Option<&'a str>,
+    details: Option<&'a str>,
+    timestamp: Option<u64>,
 }
 
 impl ZzTxSerde<'_> {
-    pub fn to_zztx(self, parse_options: &ZzParseOptions) -> CsvParserResult {
+    /// String client ids are interned in client_names
+    pub fn to_zztx(
+        self,
+        parse_options: &ZzParseOptions,
+        client_names: &ZzClientNames,
+    ) -> CsvParserResult {
         let Some(r#type) = self.r#type else {
             return CsvParserResult::MissingRequiredField;
         };
         let Some(client_id) = self.client_id else {
             return CsvParserResult::MissingRequiredField;
         };
+        let Some(client_id) = parse_options
+            .client_ids
+            .parse(client_id.trim(), client_names)
+        else {
+            return CsvParserResult::Failed;
+        };
         let Some(tx_id) = self.tx_id else {
             return CsvParserResult::MissingRequiredField;
         };
-        let amount = if let Some(amount) = self.amount {
+        let amount = self.amount;
+        let timestamp = self.timestamp;
+        let details = self
+            .details
+            .map(str::trim)
+            .filter(|details| !details.is_empty());
+
+        // the sign is only accepted by adjustments
+        let amount = if let Some(amount) = amount {
+            if r#type == ZzTxTypeDiscriminants::Adjustment {
+                let Ok(amount) =
+                    crate::parsers::nom::zz_amount::parse_zzamount_i(parse_options, amount)
+                else {
+                    return CsvParserResult::Failed;
+                };
+                let Some(reason) = details else {
+                    return CsvParserResult::MissingRequiredField;
+                };
+
+                return CsvParserResult::Parsed(ZzTx {
+                    r#type: ZzTxType::Adjustment {
+                        amount: amount.1,
+                        reason: reason.to_string(),
+                    },
+                    client_id,
+                    tx_id,
+                    timestamp,
+                });
+            }
+
             let Ok(amount) =
                 crate::parsers::nom::zz_amount::parse_zzamount_u(parse_options, amount)
                     .map(|(_, res)| res)
             else {
                 return CsvParserResult::Failed;
             };
+
+            if r#type == ZzTxTypeDiscriminants::Transfer {
+                let Some(to) = details else {
+                    return CsvParserResult::MissingRequiredField;
+                };
+                let Some(to) = parse_options.client_ids.parse(to, client_names) else {
+                    return CsvParserResult::Failed;
+                };
+
+                return CsvParserResult::Parsed(ZzTx {
+                    r#type: ZzTxType::Transfer { to, amount },
+                    client_id,
+                    tx_id,
+                    timestamp,
+                });
+            }
+
             Some(amount)
         } else {
             None
@@ -45,17 +107,27 @@ impl ZzTxSerde<'_> {
             r#type,
             client_id,
             tx_id,
+            timestamp,
         };
 
-        match (r#type, amount) {
+        let res = match (r#type, amount) {
             (ZzTxTypeDiscriminants::Deposit, Some(amount)) => {
                 CsvParserResult::Parsed(build_tx(ZzTxType::Deposit(amount)))
             }
             (ZzTxTypeDiscriminants::Withdrawal, Some(amount)) => {
                 CsvParserResult::Parsed(build_tx(ZzTxType::Withdrawal(amount)))
             }
-            (ZzTxTypeDiscriminants::Dispute, None) => {
-                CsvParserResult::Parsed(build_tx(ZzTxType::Dispute))
+            (ZzTxTypeDiscriminants::Dispute, amount) => {
+                CsvParserResult::Parsed(build_tx(ZzTxType::Dispute(amount)))
+            }
+            (ZzTxTypeDiscriminants::Authorize, Some(amount)) => {
+                CsvParserResult::Parsed(build_tx(ZzTxType::Authorize(amount)))
+            }
+            (ZzTxTypeDiscriminants::Capture, amount) => {
+                CsvParserResult::Parsed(build_tx(ZzTxType::Capture(amount)))
+            }
+            (ZzTxTypeDiscriminants::Void, None) => {
+                CsvParserResult::Parsed(build_tx(ZzTxType::Void))
             }
             (ZzTxTypeDiscriminants::Resolve, None) => {
                 CsvParserResult::Parsed(build_tx(ZzTxType::Resolve))
@@ -63,18 +135,39 @@ impl ZzTxSerde<'_> {
             (ZzTxTypeDiscriminants::Chargeback, None) => {
                 CsvParserResult::Parsed(build_tx(ZzTxType::Chargeback))
             }
-            (ZzTxTypeDiscriminants::Deposit, None) | (ZzTxTypeDiscriminants::Withdrawal, None) => {
-                CsvParserResult::MissingRequiredField
+            (ZzTxTypeDiscriminants::Unlock, None) => {
+                CsvParserResult::Parsed(build_tx(ZzTxType::Unlock))
             }
-            (ZzTxTypeDiscriminants::Dispute, Some(_)) => {
-                CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Dispute))
+            (ZzTxTypeDiscriminants::Freeze, None) => {
+                CsvParserResult::Parsed(build_tx(ZzTxType::Freeze))
             }
+            (ZzTxTypeDiscriminants::Deposit, None)
+            | (ZzTxTypeDiscriminants::Withdrawal, None)
+            | (ZzTxTypeDiscriminants::Authorize, None)
+            | (ZzTxTypeDiscriminants::Transfer, _)
+            | (ZzTxTypeDiscriminants::Adjustment, _) => CsvParserResult::MissingRequiredField,
             (ZzTxTypeDiscriminants::Resolve, Some(_)) => {
                 CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Resolve))
             }
             (ZzTxTypeDiscriminants::Chargeback, Some(_)) => {
                 CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Chargeback))
             }
+            (ZzTxTypeDiscriminants::Void, Some(_)) => {
+                CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Void))
+            }
+            (ZzTxTypeDiscriminants::Unlock, Some(_)) => {
+                CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Unlock))
+            }
+            (ZzTxTypeDiscriminants::Freeze, Some(_)) => {
+                CsvParserResult::ContainsExcessiveFields(build_tx(ZzTxType::Freeze))
+            }
+        };
+
+        match res {
+            CsvParserResult::Parsed(zztx) if details.is_some() => {
+                CsvParserResult::ContainsExcessiveFields(zztx)
+            }
+            res => res,
         }
     }
 }
@@ -90,6 +183,8 @@ static HEADERS_RECORD: LazyLock<csv::StringRecord> = LazyLock::new(|| {
     record.push_field("client");
     record.push_field("tx");
     record.push_field("amount");
+    record.push_field("details");
+    record.push_field("timestamp");
     record
 });
 
@@ -99,12 +194,23 @@ impl CsvZzTxParserTrait for CsvZzTxParserSerdeImpl {
         _parse_options: &crate::ZzParseOptions,
         header: &str,
     ) -> bool {
+        // the details and timestamp columns are optional
         let mut rdr = csv::Reader::from_reader(header.as_bytes());
-        rdr.headers()
-            .is_ok_and(|headers| headers == &*HEADERS_RECORD)
+        rdr.headers().is_ok_and(|headers| {
+            (HEADERS_RECORD.len() - 2..=HEADERS_RECORD.len()).contains(&headers.len())
+                && headers
+                    .iter()
+                    .zip(HEADERS_RECORD.iter())
+                    .all(|(a, b)| a == b)
+        })
     }
 
-    fn deserialize_row(&mut self, parse_options: &ZzParseOptions, row: &str) -> CsvParserResult {
+    fn deserialize_row(
+        &mut self,
+        parse_options: &ZzParseOptions,
+        client_names: &ZzClientNames,
+        row: &str,
+    ) -> CsvParserResult {
         let mut rdr = csv::Reader::from_reader(row.as_bytes());
         if rdr.read_record(&mut self.raw_record).is_err() {
             return CsvParserResult::Failed;
@@ -117,6 +223,105 @@ impl CsvZzTxParserTrait for CsvZzTxParserSerdeImpl {
             return CsvParserResult::Failed;
         };
 
-        record.to_zztx(parse_options)
+        record.to_zztx(parse_options, client_names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::zz_amount::ZzIAmount;
+
+    fn make_serde_tx<'a>(
+        r#type: ZzTxTypeDiscriminants,
+        amount: Option<&'a str>,
+        details: Option<&'a str>,
+    ) -> ZzTxSerde<'a> {
+        ZzTxSerde {
+            r#type: Some(r#type),
+            client_id: Some("1"),
+            tx_id: Some(1),
+            amount,
+            details,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_optional_details_header() {
+        let opts = &ZzParseOptions::default();
+        let mut parser = CsvZzTxParserSerdeImpl::default();
+
+        assert!(parser.deserialize_headers(opts, "type,client,tx,amount"));
+        assert!(parser.deserialize_headers(opts, "type,client,tx,amount,details"));
+        assert!(parser.deserialize_headers(opts, "type,client,tx,amount,details,timestamp"));
+        assert!(!parser.deserialize_headers(opts, "deposit,1,1,1"));
+    }
+
+    #[test]
+    fn test_details_to_zztx() {
+        let opts = &ZzParseOptions::default();
+
+        assert!(matches!(
+            make_serde_tx(ZzTxTypeDiscriminants::Deposit, Some("1"), None)
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::Parsed(_)
+        ));
+        assert!(matches!(
+            make_serde_tx(ZzTxTypeDiscriminants::Deposit, Some("1"), Some("reason"))
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::ContainsExcessiveFields(_)
+        ));
+        assert_eq!(
+            make_serde_tx(
+                ZzTxTypeDiscriminants::Adjustment,
+                Some("-1"),
+                Some("reason")
+            )
+            .to_zztx(opts, &Default::default()),
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Adjustment {
+                    amount: ZzIAmount::new((-1).into(), 0).unwrap(),
+                    reason: "reason".to_string()
+                },
+                client_id: 1,
+                tx_id: 1,
+                timestamp: None
+            })
+        );
+        assert_eq!(
+            make_serde_tx(ZzTxTypeDiscriminants::Adjustment, Some("-1"), None)
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::MissingRequiredField
+        );
+        assert!(matches!(
+            make_serde_tx(ZzTxTypeDiscriminants::Transfer, Some("1"), Some("2"))
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Transfer { to: 2, .. },
+                ..
+            })
+        ));
+        assert_eq!(
+            make_serde_tx(ZzTxTypeDiscriminants::Transfer, Some("1"), None)
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::MissingRequiredField
+        );
+        assert!(matches!(
+            make_serde_tx(ZzTxTypeDiscriminants::Dispute, Some("1"), None)
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Dispute(Some(_)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            make_serde_tx(ZzTxTypeDiscriminants::Freeze, None, None)
+                .to_zztx(opts, &Default::default()),
+            CsvParserResult::Parsed(ZzTx {
+                r#type: ZzTxType::Freeze,
+                ..
+            })
+        ));
     }
 }
@@ -1,23 +1,210 @@
 use serde::Serialize;
 
-/// Writes a csv to the writer (W)
+use strum::IntoDiscriminant;
+
+use crate::{
+    common::zz_amount::{ZzIAmount, ZzUAmount},
+    domain::{
+        client_balance::{ClientId, ZzClientBalance},
+        client_store::{ZzClientIdDisplay, ZzClientNames},
+        transaction::{RejectReason, TxId, ZzTx, ZzTxTypeDiscriminants},
+    },
+};
+
+#[derive(Serialize)]
+struct BalanceRow<'a> {
+    client: ZzClientIdDisplay<'a>,
+    available: &'a ZzIAmount,
+    held: &'a ZzIAmount,
+    total: &'a ZzIAmount,
+    locked: &'a bool,
+}
+
+/// Writes a csv to the writer (W), the clients are displayed as they were in the input
 ///
 /// # Errors
 ///
 /// Failed to write the csv
-pub fn write_csv_client_balance_sheet<'a, Input, W, S>(
+pub fn write_csv_client_balance_sheet<'a, Input, W>(
     sheet: Input,
+    client_names: &ZzClientNames,
     w: W,
-) -> Result<(), Box<dyn std::error::Error>>
+) -> std::io::Result<()>
 where
-    S: Serialize + 'a,
-    Input: Iterator<Item = &'a S>,
+    Input: Iterator<Item = &'a ZzClientBalance>,
     W: std::io::Write,
 {
     let mut wtr = csv::Writer::from_writer(w);
 
     for balance in sheet {
-        wtr.serialize(balance)?;
+        wtr.serialize(BalanceRow {
+            client: client_names.display(balance.client_id),
+            available: &balance.available,
+            held: &balance.held,
+            total: &balance.total,
+            locked: &balance.locked,
+        })?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct DetailedBalanceRow<'a> {
+    client: ZzClientIdDisplay<'a>,
+    available: &'a ZzIAmount,
+    held: &'a ZzIAmount,
+    total: &'a ZzIAmount,
+    locked: &'a bool,
+    held_by: String,
+    locked_by_tx: Option<TxId>,
+    locked_by_row: Option<u64>,
+}
+
+/// Writes the balance sheet with the transactions that make up held (as tx:amount separated by
+/// ;) and the tx id and row number of the transaction that locked the account
+///
+/// holds must be sorted by client, see TransactionMap::holds
+///
+/// # Errors
+///
+/// Failed to write the csv
+pub fn write_csv_detailed_client_balance_sheet<'a, Input, W>(
+    sheet: Input,
+    holds: &[(ClientId, TxId, ZzUAmount)],
+    client_names: &ZzClientNames,
+    w: W,
+) -> std::io::Result<()>
+where
+    Input: Iterator<Item = &'a ZzClientBalance>,
+    W: std::io::Write,
+{
+    let mut wtr = csv::Writer::from_writer(w);
+
+    for balance in sheet {
+        let start = holds.partition_point(|(client_id, _, _)| *client_id < balance.client_id);
+        let end = holds.partition_point(|(client_id, _, _)| *client_id <= balance.client_id);
+        let held_by = holds[start..end]
+            .iter()
+            .map(|(_, tx_id, amount)| format!("{tx_id}:{amount}"))
+            .collect::<Vec<_>>()
+            .join(";");
+
+        wtr.serialize(DetailedBalanceRow {
+            client: client_names.display(balance.client_id),
+            available: &balance.available,
+            held: &balance.held,
+            total: &balance.total,
+            locked: &balance.locked,
+            held_by,
+            locked_by_tx: balance.locked_by.map(|(tx_id, _)| tx_id),
+            locked_by_row: balance.locked_by.map(|(_, row)| row),
+        })?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct NegativeBalanceRow<'a> {
+    client: ZzClientIdDisplay<'a>,
+    available: &'a ZzIAmount,
+    total: &'a ZzIAmount,
+    receivable: &'a ZzIAmount,
+}
+
+/// Writes a csv with the clients that owe money (see ZzClientBalance::is_negative). Nothing is
+/// written if there are no such clients
+///
+/// # Errors
+///
+/// Failed to write the csv
+pub fn write_csv_negative_balance_report<'a, Input, W>(
+    sheet: Input,
+    client_names: &ZzClientNames,
+    w: W,
+) -> std::io::Result<()>
+where
+    Input: Iterator<Item = &'a ZzClientBalance>,
+    W: std::io::Write,
+{
+    let mut wtr = csv::Writer::from_writer(w);
+
+    for balance in sheet.filter(|balance| balance.is_negative()) {
+        wtr.serialize(NegativeBalanceRow {
+            client: client_names.display(balance.client_id),
+            available: &balance.available,
+            total: &balance.total,
+            receivable: &balance.receivable,
+        })?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct DuplicateTxRow<'a> {
+    r#type: ZzTxTypeDiscriminants,
+    client: ZzClientIdDisplay<'a>,
+    tx: &'a TxId,
+}
+
+/// Writes a csv with the transactions that reused a tx id (see DuplicateTxOptions). Nothing is
+/// written if there are no such transactions
+///
+/// # Errors
+///
+/// Failed to write the csv
+pub fn write_csv_duplicate_tx_report<'a, Input, W>(
+    duplicates: Input,
+    client_names: &ZzClientNames,
+    w: W,
+) -> std::io::Result<()>
+where
+    Input: Iterator<Item = &'a ZzTx>,
+    W: std::io::Write,
+{
+    let mut wtr = csv::Writer::from_writer(w);
+
+    for zztx in duplicates {
+        wtr.serialize(DuplicateTxRow {
+            r#type: zztx.r#type.discriminant(),
+            client: client_names.display(zztx.client_id),
+            tx: &zztx.tx_id,
+        })?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct RejectionRow {
+    reason: RejectReason,
+    count: u64,
+}
+
+/// Writes a csv with how many transactions were rejected for each reason (see RejectReason).
+/// Nothing is written if none was rejected
+///
+/// # Errors
+///
+/// Failed to write the csv
+pub fn write_csv_rejection_report<'a, Input, W>(rejected: Input, w: W) -> std::io::Result<()>
+where
+    Input: Iterator<Item = &'a (RejectReason, u64)>,
+    W: std::io::Write,
+{
+    let mut wtr = csv::Writer::from_writer(w);
+
+    for (reason, count) in rejected {
+        wtr.serialize(RejectionRow {
+            reason: *reason,
+            count: *count,
+        })?;
     }
     wtr.flush()?;
 
@@ -26,7 +213,10 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::{common::zz_amount::ZzIAmount, domain::client_balance::ZzClientBalance};
+    use crate::{
+        common::zz_amount::{ZzIAmount, ZzUAmount},
+        domain::{client_balance::ZzClientBalance, transaction::ZzTxType},
+    };
 
     fn make_iamount(val: i64) -> ZzIAmount {
         ZzIAmount::new(val.into(), 0).unwrap()
@@ -43,6 +233,8 @@ mod tests {
                 held: make_iamount(50),
                 total: make_iamount(150),
                 locked: false,
+                receivable: make_iamount(0),
+                locked_by: None,
             },
             ZzClientBalance {
                 client_id: 2,
@@ -50,11 +242,13 @@ mod tests {
                 held: make_iamount(0),
                 total: make_iamount(200),
                 locked: true,
+                receivable: make_iamount(0),
+                locked_by: None,
             },
         ];
 
         let mut output = Vec::new();
-        write_csv_client_balance_sheet(balances.iter(), &mut output).unwrap();
+        write_csv_client_balance_sheet(balances.iter(), &Default::default(), &mut output).unwrap();
 
         let csv_str = String::from_utf8(output).unwrap();
         let expected_lines: Vec<&str> = vec![
@@ -67,4 +261,154 @@ mod tests {
             assert!(csv_str.contains(line));
         }
     }
+
+    #[test]
+    fn test_write_csv_detailed_client_balance_sheet() {
+        let balances = [
+            ZzClientBalance {
+                client_id: 1,
+                available: make_iamount(100),
+                held: make_iamount(15),
+                total: make_iamount(115),
+                locked: false,
+                receivable: make_iamount(0),
+                locked_by: None,
+            },
+            ZzClientBalance {
+                client_id: 2,
+                available: make_iamount(0),
+                held: make_iamount(0),
+                total: make_iamount(0),
+                locked: true,
+                receivable: make_iamount(0),
+                locked_by: Some((7, 9)),
+            },
+            ZzClientBalance {
+                client_id: 3,
+                available: make_iamount(0),
+                held: make_iamount(2),
+                total: make_iamount(2),
+                locked: false,
+                receivable: make_iamount(0),
+                locked_by: None,
+            },
+        ];
+        let holds = [
+            (1, 3, ZzUAmount::new(5u32.into(), 0).unwrap()),
+            (1, 4, ZzUAmount::new(10u32.into(), 0).unwrap()),
+            (3, 1, ZzUAmount::new(2u32.into(), 0).unwrap()),
+        ];
+
+        let mut output = Vec::new();
+        write_csv_detailed_client_balance_sheet(
+            balances.iter(),
+            &holds,
+            &Default::default(),
+            &mut output,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,held_by,locked_by_tx,locked_by_row\n\
+             1,100,15,115,false,3:5;4:10,,\n\
+             2,0,0,0,true,,7,9\n\
+             3,0,2,2,false,1:2,,\n"
+        );
+    }
+
+    #[test]
+    fn test_write_csv_negative_balance_report() {
+        let balances = [
+            ZzClientBalance {
+                client_id: 1,
+                available: make_iamount(-10),
+                held: make_iamount(0),
+                total: make_iamount(-10),
+                locked: true,
+                receivable: make_iamount(0),
+                locked_by: None,
+            },
+            ZzClientBalance {
+                client_id: 2,
+                available: make_iamount(200),
+                held: make_iamount(0),
+                total: make_iamount(200),
+                locked: false,
+                receivable: make_iamount(0),
+                locked_by: None,
+            },
+            ZzClientBalance {
+                client_id: 3,
+                available: make_iamount(0),
+                held: make_iamount(0),
+                total: make_iamount(0),
+                locked: true,
+                receivable: make_iamount(5),
+                locked_by: None,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_csv_negative_balance_report(balances.iter(), &Default::default(), &mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,total,receivable\n1,-10,-10,0\n3,0,0,5\n"
+        );
+
+        let mut output = Vec::new();
+        write_csv_negative_balance_report(balances[1..2].iter(), &Default::default(), &mut output)
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_write_csv_duplicate_tx_report() {
+        let duplicates = [
+            ZzTx {
+                r#type: ZzTxType::Deposit(ZzUAmount::new(1u32.into(), 0).unwrap()),
+                client_id: 2,
+                tx_id: 1,
+                timestamp: None,
+            },
+            ZzTx {
+                r#type: ZzTxType::Freeze,
+                client_id: 1,
+                tx_id: 3,
+                timestamp: None,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_csv_duplicate_tx_report(duplicates.iter(), &Default::default(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "type,client,tx\ndeposit,2,1\nfreeze,1,3\n"
+        );
+
+        let mut output = Vec::new();
+        write_csv_duplicate_tx_report([].iter(), &Default::default(), &mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_write_csv_rejection_report() {
+        let rejected = [
+            (RejectReason::UnknownTx, 2),
+            (RejectReason::InsufficientFunds, 1),
+        ];
+
+        let mut output = Vec::new();
+        write_csv_rejection_report(rejected.iter(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "reason,count\nunknown-tx,2\ninsufficient-funds,1\n"
+        );
+
+        let mut output = Vec::new();
+        write_csv_rejection_report([].iter(), &mut output).unwrap();
+        assert!(output.is_empty());
+    }
 }
@@ -1,19 +1,213 @@
+#![feature(assert_matches)]
+
 use std::{
-    io::Cursor,
+    assert_matches::assert_matches,
+    io::{Cursor, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{Arc, atomic::AtomicBool},
 };
 
 use zzzzzzzzzzz::{
-    parsers::{csv_parser::csv_zztx_parser_streaming, nom::CsvZzTxParserNomImpl},
-    utils::write_csv_client_balance_sheet,
+    ClientIdOptions, DuplicateTxOptions, JournalFormatOptions, NegativeBalanceOptions,
+    TransactionMapOptions, ZzAsOf, ZzCheckpointOptions, ZzJournalOptions, ZzParseOptions,
+    ZzPolicyOptions, ZzStorageOptions,
+    domain::{
+        client_store::ZzClientNames,
+        fee::ZzFeeSchedule,
+        journal::{read_journal, replay_journal},
+        risk::ZzRiskRules,
+    },
+    error::{ParseErrorKind, ZzError},
+    parsers::{
+        csv_parser::{CsvZzTxParserOutput, csv_client_snapshots, csv_zztx_parser_streaming},
+        nom::CsvZzTxParserNomImpl,
+    },
+    utils::{write_csv_client_balance_sheet, write_csv_detailed_client_balance_sheet},
 };
 
-fn run_test_case(test_dir_path: &Path) {
+/// The fee schedule at the path, with u16 client ids
+fn fee_schedule(path: &str) -> ZzFeeSchedule {
+    ZzFeeSchedule::from_path(Path::new(path), ClientIdOptions::U16, &Default::default()).unwrap()
+}
+
+fn run(
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    checkpoint_options: &ZzCheckpointOptions,
+    journal_options: &ZzJournalOptions,
+) -> CsvZzTxParserOutput {
+    csv_zztx_parser_streaming(
+        &mut CsvZzTxParserNomImpl,
+        file,
+        parse_options,
+        policy_options,
+        storage_options,
+        checkpoint_options,
+        journal_options,
+    )
+    .unwrap()
+}
+
+fn write_sheet(
+    client_balances: &[zzzzzzzzzzz::domain::client_balance::ZzClientBalance],
+    client_names: &ZzClientNames,
+) -> String {
+    let mut res = vec![];
+    write_csv_client_balance_sheet(client_balances.iter(), client_names, Cursor::new(&mut res))
+        .unwrap();
+    String::from_utf8(res).unwrap()
+}
+
+/// Checks that replaying the journal gives the same balances in both formats, returns the csv
+/// journal
+fn check_journal(
+    file: &std::fs::File,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    expected: &CsvZzTxParserOutput,
+) -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let journal = dir.path().join("journal");
+
+    for journal_format in [JournalFormatOptions::Jsonl, JournalFormatOptions::Csv] {
+        let journaled = run(
+            file,
+            parse_options,
+            policy_options,
+            storage_options,
+            &Default::default(),
+            &ZzJournalOptions {
+                journal: Some(journal.clone()),
+                journal_format,
+                ..Default::default()
+            },
+        );
+        assert_eq!(journaled.client_balances, expected.client_balances);
+
+        let (replayed, client_names) =
+            replay_journal(read_journal(&journal, journal_format).unwrap()).unwrap();
+        assert_eq!(
+            write_sheet(&replayed, &client_names),
+            write_sheet(&expected.client_balances, &expected.client_names),
+            "replayed {journal_format} journal"
+        );
+    }
+
+    std::fs::read(&journal).unwrap()
+}
+
+/// Checks that resuming from any checkpoint gives the same result and journal as the
+/// uninterrupted run
+fn check_resume(
+    input_file: &Path,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+    storage_options: &ZzStorageOptions,
+    expected: &CsvZzTxParserOutput,
+    expected_journal: &[u8],
+) {
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join("checkpoint");
+    let journal = dir.path().join("journal");
+    let journal_options = ZzJournalOptions {
+        journal: Some(journal.clone()),
+        ..Default::default()
+    };
+    let file = &std::fs::File::open(input_file).unwrap();
+    let rows = std::fs::read_to_string(input_file).unwrap().lines().count() as u64;
+
+    let assert_same = |resumed: &CsvZzTxParserOutput, description: &str| {
+        assert!(!resumed.interrupted);
+        assert!(
+            std::fs::read(&journal).unwrap() == expected_journal,
+            "{description}: journal"
+        );
+        assert_eq!(
+            resumed.client_balances, expected.client_balances,
+            "{description}"
+        );
+        assert_eq!(resumed.holds, expected.holds, "{description}");
+        assert_eq!(
+            resumed.duplicate_txs, expected.duplicate_txs,
+            "{description}"
+        );
+    };
+    let resume_options = ZzCheckpointOptions {
+        resume: Some(checkpoint.clone()),
+        ..Default::default()
+    };
+
+    // the last periodic checkpoint is the one that's resumed
+    for checkpoint_every in 1..=rows {
+        let checkpointed = run(
+            file,
+            parse_options,
+            policy_options,
+            storage_options,
+            &ZzCheckpointOptions {
+                checkpoint: Some(checkpoint.clone()),
+                checkpoint_every,
+                ..Default::default()
+            },
+            &journal_options,
+        );
+        assert_same(&checkpointed, "checkpointed run");
+
+        let resumed = run(
+            file,
+            parse_options,
+            policy_options,
+            storage_options,
+            &resume_options,
+            &journal_options,
+        );
+        assert_same(&resumed, &format!("resumed every {checkpoint_every} rows"));
+    }
+
+    // the interrupted run stops after the first row
+    let interrupted = run(
+        file,
+        parse_options,
+        policy_options,
+        storage_options,
+        &ZzCheckpointOptions {
+            checkpoint: Some(checkpoint.clone()),
+            interrupt: Arc::new(AtomicBool::new(true)),
+            ..Default::default()
+        },
+        &journal_options,
+    );
+    assert!(interrupted.interrupted);
+
+    let resumed = run(
+        file,
+        parse_options,
+        policy_options,
+        storage_options,
+        &resume_options,
+        &journal_options,
+    );
+    assert_same(&resumed, "resumed after interrupt");
+}
+
+fn run_test_case(test_dir_path: &Path, policy_options: &ZzPolicyOptions) {
+    run_test_case_with_parse_options(test_dir_path, &Default::default(), policy_options)
+}
+
+fn run_test_case_with_parse_options(
+    test_dir_path: &Path,
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+) {
     let test_dir = std::fs::read_dir(test_dir_path).unwrap();
 
     let mut input = None;
     let mut output = None;
+    let mut detailed_output = None;
 
     for test_entry in test_dir {
         let test_entry = test_entry.unwrap();
@@ -28,6 +222,9 @@ fn run_test_case(test_dir_path: &Path) {
             "output.csv" => {
                 output = Some(test_entry.path());
             }
+            "detailed_output.csv" => {
+                detailed_output = Some(test_entry.path());
+            }
             _ => {}
         }
     }
@@ -35,24 +232,83 @@ fn run_test_case(test_dir_path: &Path) {
     let input_file = input.expect("input file not found");
     let file = std::fs::File::open(&input_file).unwrap();
 
-    let client_balance_map =
-        csv_zztx_parser_streaming(&mut CsvZzTxParserNomImpl, &file, &Default::default());
+    let output = output.expect("output file not found");
+    // the disk map keeps 2 transactions in memory so every case spills
+    let storages = [
+        ZzStorageOptions::default(),
+        ZzStorageOptions {
+            transaction_map: TransactionMapOptions::Disk,
+            hot_transactions: 2,
+            ..Default::default()
+        },
+    ];
 
-    let mut res = vec![];
-    let cursor = Cursor::new(&mut res);
-    write_csv_client_balance_sheet(client_balance_map.iter().filter_map(|x| x.as_ref()), cursor)
+    for storage_options in &storages {
+        let processed = run(
+            &file,
+            parse_options,
+            policy_options,
+            storage_options,
+            &Default::default(),
+            &Default::default(),
+        );
+
+        let mut res = vec![];
+        let cursor = Cursor::new(&mut res);
+        write_csv_client_balance_sheet(
+            processed.client_balances.iter(),
+            &processed.client_names,
+            cursor,
+        )
         .unwrap();
 
-    let output = output.expect("output file not found but csv was successfully produced");
-    let v = std::fs::read(output).unwrap();
+        let v = std::fs::read(&output).unwrap();
 
-    assert_eq!(
-        res,
-        v,
-        "\n{}\n!=\n{}",
-        str::from_utf8(&res).unwrap(),
-        str::from_utf8(&v).unwrap()
-    );
+        assert_eq!(
+            res,
+            v,
+            "{}:\n{}\n!=\n{}",
+            storage_options.transaction_map,
+            str::from_utf8(&res).unwrap(),
+            str::from_utf8(&v).unwrap()
+        );
+
+        if let Some(detailed_output) = &detailed_output {
+            let mut res = vec![];
+            write_csv_detailed_client_balance_sheet(
+                processed.client_balances.iter(),
+                &processed.holds,
+                &processed.client_names,
+                Cursor::new(&mut res),
+            )
+            .unwrap();
+
+            let v = std::fs::read(detailed_output).unwrap();
+            assert_eq!(
+                res,
+                v,
+                "\n{}\n!=\n{}",
+                str::from_utf8(&res).unwrap(),
+                str::from_utf8(&v).unwrap()
+            );
+        }
+
+        let journal = check_journal(
+            &file,
+            parse_options,
+            policy_options,
+            storage_options,
+            &processed,
+        );
+        check_resume(
+            &input_file,
+            parse_options,
+            policy_options,
+            storage_options,
+            &processed,
+            &journal,
+        );
+    }
 }
 
 macro_rules! test_case {
@@ -63,7 +319,23 @@ macro_rules! test_case {
             fn [<test_ $test_name _case>]() {
                 run_test_case(&PathBuf::from_str(
                     concat!("tests/test_cases/", stringify!($test_name))
-                ).unwrap())
+                ).unwrap(), &Default::default())
+            }
+        )+
+        }
+    };
+}
+
+/// Same as test_case but running with a non default policy
+macro_rules! test_case_with_policy {
+    ($($test_name:ident: $policy:expr),+ $(,)?) => {
+        paste::paste! {
+        $(
+            #[test]
+            fn [<test_ $test_name _case>]() {
+                run_test_case(&PathBuf::from_str(
+                    concat!("tests/test_cases/", stringify!($test_name))
+                ).unwrap(), &$policy)
             }
         )+
         }
@@ -89,9 +361,587 @@ test_case!(
     many_clients_isolation,
     // are mistakes from the spec ignored?
     partner_mistakes_are_ignored,
+    // are transfers atomic (insufficient funds, locked source or destination)?
+    transfer,
+    // can a deposit be disputed in portions that don't exceed it?
+    partial_dispute,
+    // are captures limited to the authorization and ignored on locked accounts?
+    authorization,
     // AI generated
     1,
     2,
     3,
     4
 );
+
+test_case_with_policy!(
+    // can ops freeze, unlock and adjust accounts?
+    admin: ZzPolicyOptions {
+        allow_admin_transactions: true,
+        ..Default::default()
+    },
+    // are the holds and the transaction that locked each account reported?
+    detailed_output: ZzPolicyOptions {
+        allow_admin_transactions: true,
+        ..Default::default()
+    },
+    // disputes on withdrawals hold the amount and a chargeback returns it to available
+    withdrawal_dispute: ZzPolicyOptions {
+        allow_withdrawal_disputes: true,
+        ..Default::default()
+    },
+    // disputes that would make available negative are ignored
+    negative_balance_reject: ZzPolicyOptions {
+        on_negative_balance: NegativeBalanceOptions::Reject,
+        ..Default::default()
+    },
+    // disputes only hold what's available, the rest is owed by the client
+    negative_balance_cap: ZzPolicyOptions {
+        on_negative_balance: NegativeBalanceOptions::Cap,
+        ..Default::default()
+    },
+    // a locked account can still settle the disputes that were open, nothing else
+    settle_disputes_after_lock: ZzPolicyOptions {
+        settle_disputes_after_lock: true,
+        ..Default::default()
+    },
+    // fees are charged to the client and credited to the fee account (client 0)
+    fees: ZzPolicyOptions {
+        fee_schedule: Some(fee_schedule("tests/test_cases/fees/fees.csv")),
+        fee_account: Some(0),
+        ..Default::default()
+    },
+    // late disputes are rejected and open disputes are resolved after the deadline (in rows)
+    dispute_window: ZzPolicyOptions {
+        dispute_window: Some(3),
+        dispute_resolve_deadline: Some(2),
+        ..Default::default()
+    },
+    // a dispute past the deadline of a locked account is resolved once the account is unlocked
+    dispute_deadline_lock: ZzPolicyOptions {
+        dispute_resolve_deadline: Some(1),
+        allow_admin_transactions: true,
+        ..Default::default()
+    },
+    // same as dispute_window but measured with the timestamp column
+    dispute_window_timestamps: ZzPolicyOptions {
+        dispute_window: Some(60),
+        dispute_resolve_deadline: Some(3600),
+        ..Default::default()
+    },
+    // a tx id reused by the same or another client is ignored
+    duplicate_tx_reject: ZzPolicyOptions {
+        on_duplicate_tx: DuplicateTxOptions::Reject,
+        ..Default::default()
+    },
+    // a tx id reused by another client is still processed
+    duplicate_tx_warn: ZzPolicyOptions {
+        on_duplicate_tx: DuplicateTxOptions::Warn,
+        ..Default::default()
+    },
+    // the built-in risk rules can reject transactions and lock accounts
+    risk_rules: ZzPolicyOptions {
+        risk_rules: Some(ZzRiskRules::from_path("tests/test_cases/risk_rules/rules.csv").unwrap()),
+        ..Default::default()
+    },
+    // finalized transactions are evicted so their tx id can be reused, and deposits past the
+    // horizon (in rows) can't be disputed anymore
+    eviction: ZzPolicyOptions {
+        evict_finalized: true,
+        eviction_horizon: Some(4),
+        allow_admin_transactions: true,
+        ..Default::default()
+    },
+);
+
+/// Same as test_case but with non default parse options
+macro_rules! test_case_with_parse_options {
+    ($($test_name:ident: $parse_options:expr),+ $(,)?) => {
+        paste::paste! {
+        $(
+            #[test]
+            fn [<test_ $test_name _case>]() {
+                run_test_case_with_parse_options(&PathBuf::from_str(
+                    concat!("tests/test_cases/", stringify!($test_name))
+                ).unwrap(), &$parse_options, &Default::default())
+            }
+        )+
+        }
+    };
+}
+
+test_case_with_parse_options!(
+    // ids wider than u16 are kept in the sparse client store
+    wide_client_ids: ZzParseOptions {
+        client_ids: ClientIdOptions::U64,
+        ..Default::default()
+    },
+    // string ids are interned and sorted by name in the output
+    string_client_ids: ZzParseOptions {
+        client_ids: ClientIdOptions::String,
+        ..Default::default()
+    },
+    // the rows after the 5th (the header is the 1st) aren't processed
+    as_of_row: ZzParseOptions {
+        as_of: Some(ZzAsOf::Row(5)),
+        ..Default::default()
+    },
+    // processing stops at the first row with a later timestamp, the ones without a timestamp
+    // don't stop it
+    as_of_timestamp: ZzParseOptions {
+        as_of: Some(ZzAsOf::Timestamp(200)),
+        ..Default::default()
+    },
+);
+
+/// Each snapshot is the balance of the client when processing stops at its row
+#[test]
+fn test_client_snapshots() {
+    let input_file =
+        PathBuf::from_str("tests/test_cases/dispute_window_timestamps/input.csv").unwrap();
+    let file = std::fs::File::open(&input_file).unwrap();
+    let policy_options = ZzPolicyOptions {
+        dispute_window: Some(60),
+        dispute_resolve_deadline: Some(3600),
+        ..Default::default()
+    };
+
+    let snapshots = csv_client_snapshots(
+        &mut CsvZzTxParserNomImpl,
+        &file,
+        &Default::default(),
+        &policy_options,
+        &Default::default(),
+        1,
+    )
+    .unwrap();
+    let rows: Vec<_> = snapshots.iter().map(|(row, _)| *row).collect();
+    // the late dispute doesn't change it, the automatic resolve and the withdrawal of the 6th
+    // row are a single snapshot
+    assert_eq!(rows, vec![2, 3, 5, 6]);
+    assert_eq!(snapshots[3].1.available.to_string(), "15");
+
+    for (row, balance) in &snapshots {
+        let processed = run(
+            &file,
+            &ZzParseOptions {
+                as_of: Some(ZzAsOf::Row(*row)),
+                ..Default::default()
+            },
+            &policy_options,
+            &Default::default(),
+            &Default::default(),
+            &Default::default(),
+        );
+        let expected = processed
+            .client_balances
+            .iter()
+            .find(|balance| balance.client_id == 1)
+            .unwrap();
+        assert_eq!(balance, expected, "row {row}");
+    }
+}
+
+/// Every row that isn't applied is counted under the reason it was rejected for
+#[test]
+fn test_rejections() {
+    let input_file =
+        PathBuf::from_str("tests/test_cases/partner_mistakes_are_ignored/input.csv").unwrap();
+    let file = std::fs::File::open(&input_file).unwrap();
+    let output = run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    );
+    let rejected: Vec<_> = output
+        .rejected
+        .iter()
+        .map(|(reason, count)| (reason.to_string(), *count))
+        .collect();
+    // the mismatched client and the withdrawal disputes reference no transaction of the client,
+    // the chargebacks and resolve reference one that isn't disputed
+    assert_eq!(
+        rejected,
+        vec![
+            ("unknown-tx".to_string(), 4),
+            ("not-disputed".to_string(), 3)
+        ]
+    );
+}
+
+/// Administrative transactions are rejected unless the policy allows them
+#[test]
+fn test_admin_unauthorized() {
+    let file = std::fs::File::open("tests/test_cases/admin/input.csv").unwrap();
+    let output = run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    );
+
+    assert_eq!(
+        write_sheet(&output.client_balances, &output.client_names),
+        "client,available,held,total,locked\n\
+         1,15,0,15,false\n\
+         2,0,0,0,true\n"
+    );
+    let rejected: Vec<_> = output
+        .rejected
+        .iter()
+        .map(|(reason, count)| (reason.to_string(), *count))
+        .collect();
+    assert_eq!(
+        rejected,
+        vec![
+            ("unauthorized".to_string(), 5),
+            ("account-locked".to_string(), 1)
+        ]
+    );
+}
+
+/// The resolve of the deadline is rejected while the account is locked and applied when it's
+/// unlocked, both are counted
+#[test]
+fn test_auto_resolve_counts() {
+    let file = std::fs::File::open("tests/test_cases/dispute_deadline_lock/input.csv").unwrap();
+    let output = run(
+        &file,
+        &Default::default(),
+        &ZzPolicyOptions {
+            dispute_resolve_deadline: Some(1),
+            allow_admin_transactions: true,
+            ..Default::default()
+        },
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    );
+
+    assert_eq!(output.auto_resolved, 1);
+    let rejected: Vec<_> = output
+        .rejected
+        .iter()
+        .map(|(reason, count)| (reason.to_string(), *count))
+        .collect();
+    assert_eq!(rejected, vec![("account-locked".to_string(), 1)]);
+}
+
+/// The events are written as json lines, a resumed run drops the ones written after the
+/// checkpoint and writes them again
+#[test]
+fn test_events() {
+    let input_file = PathBuf::from_str("tests/test_cases/chargeback/input.csv").unwrap();
+    let file = std::fs::File::open(&input_file).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let events = dir.path().join("events");
+    let journal_options = ZzJournalOptions {
+        events: Some(events.clone()),
+        ..Default::default()
+    };
+
+    run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &journal_options,
+    );
+    let expected = std::fs::read_to_string(&events).unwrap();
+    assert_eq!(
+        expected,
+        concat!(
+            r#"{"event":"withdrawal-rejected","client":"2","tx":5,"row":5,"amount":"3","available":"2"}"#,
+            "\n",
+            r#"{"event":"balance-went-negative","client":"1","tx":1,"row":6,"available":"-1000","receivable":"0"}"#,
+            "\n",
+            r#"{"event":"dispute-opened","client":"1","tx":1,"row":6,"amount":"1000.0100"}"#,
+            "\n",
+            r#"{"event":"account-locked","client":"1","tx":1,"row":7}"#,
+            "\n",
+        )
+    );
+
+    let checkpoint = dir.path().join("checkpoint");
+    let interrupted = run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &ZzCheckpointOptions {
+            checkpoint: Some(checkpoint.clone()),
+            interrupt: Arc::new(AtomicBool::new(true)),
+            ..Default::default()
+        },
+        &journal_options,
+    );
+    assert!(interrupted.interrupted);
+    run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &ZzCheckpointOptions {
+            resume: Some(checkpoint.clone()),
+            ..Default::default()
+        },
+        &journal_options,
+    );
+    assert_eq!(std::fs::read_to_string(&events).unwrap(), expected);
+
+    // the run crashed after the checkpoint of row 5, its events up to the end were written
+    run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &ZzCheckpointOptions {
+            checkpoint: Some(checkpoint.clone()),
+            checkpoint_every: 5,
+            ..Default::default()
+        },
+        &journal_options,
+    );
+    run(
+        &file,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &ZzCheckpointOptions {
+            resume: Some(checkpoint),
+            ..Default::default()
+        },
+        &journal_options,
+    );
+    assert_eq!(std::fs::read_to_string(&events).unwrap(), expected);
+}
+
+/// A fee account that doesn't fit in a u16 is kept with the u16 client ids
+#[test]
+fn test_wide_fee_account() {
+    let file = std::fs::File::open("tests/test_cases/fees/input.csv").unwrap();
+    let output = run(
+        &file,
+        &Default::default(),
+        &ZzPolicyOptions {
+            fee_schedule: Some(fee_schedule("tests/test_cases/fees/fees.csv")),
+            fee_account: Some(70000),
+            ..Default::default()
+        },
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    );
+
+    assert_eq!(
+        write_sheet(&output.client_balances, &output.client_names),
+        "client,available,held,total,locked\n\
+         1,39,0,39,false\n\
+         2,50,0,50,false\n\
+         3,-2,0,-2,true\n\
+         70000,3,0,3,false\n"
+    );
+}
+
+/// The fee overrides and the fee account are parsed as the client column, string client ids
+/// included
+#[test]
+fn test_string_fee_clients() {
+    let client_names = ZzClientNames::default();
+    let policy_options = ZzPolicyOptions {
+        fee_schedule: Some(
+            ZzFeeSchedule::from_path(
+                Path::new("tests/test_cases/string_fees/fees.csv"),
+                ClientIdOptions::String,
+                &client_names,
+            )
+            .unwrap(),
+        ),
+        fee_account: ClientIdOptions::String.parse("fees", &client_names),
+        client_names,
+        ..Default::default()
+    };
+
+    run_test_case_with_parse_options(
+        Path::new("tests/test_cases/string_fees"),
+        &ZzParseOptions {
+            client_ids: ClientIdOptions::String,
+            ..Default::default()
+        },
+        &policy_options,
+    );
+}
+
+/// A checkpoint is only resumed with the options and the input it was written for
+#[test]
+fn test_resume_mismatch() {
+    let input = std::fs::read_to_string("tests/test_cases/chargeback/input.csv").unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input_file = dir.path().join("input.csv");
+    std::fs::write(&input_file, &input).unwrap();
+    let checkpoint = dir.path().join("checkpoint");
+
+    run(
+        &std::fs::File::open(&input_file).unwrap(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+        &ZzCheckpointOptions {
+            checkpoint: Some(checkpoint.clone()),
+            checkpoint_every: 3,
+            ..Default::default()
+        },
+        &Default::default(),
+    );
+
+    // the error the resumed run stops with
+    let resume = |policy_options: &ZzPolicyOptions, storage_options: &ZzStorageOptions| {
+        csv_zztx_parser_streaming(
+            &mut CsvZzTxParserNomImpl,
+            &std::fs::File::open(&input_file).unwrap(),
+            &Default::default(),
+            policy_options,
+            storage_options,
+            &ZzCheckpointOptions {
+                resume: Some(checkpoint.clone()),
+                ..Default::default()
+            },
+            &Default::default(),
+        )
+        .err()
+    };
+
+    assert!(resume(&Default::default(), &Default::default()).is_none());
+    assert_matches!(
+        resume(
+            &ZzPolicyOptions {
+                allow_withdrawal_disputes: true,
+                ..Default::default()
+            },
+            &Default::default()
+        ),
+        Some(ZzError::Policy(_))
+    );
+    assert_matches!(
+        resume(
+            &Default::default(),
+            &ZzStorageOptions {
+                transaction_map: TransactionMapOptions::Disk,
+                ..Default::default()
+            }
+        ),
+        Some(ZzError::Policy(_))
+    );
+
+    // a row before the checkpoint changed
+    std::fs::write(&input_file, input.replace("2.0", "3.0")).unwrap();
+    assert_matches!(
+        resume(&Default::default(), &Default::default()),
+        Some(ZzError::Policy(_))
+    );
+
+    // rows were added after it
+    std::fs::write(&input_file, format!("{input}deposit, 2, 6, 1.0\n")).unwrap();
+    assert_matches!(
+        resume(&Default::default(), &Default::default()),
+        Some(ZzError::Policy(_))
+    );
+}
+
+/// Runs the input and returns the error it stops with
+fn run_err(
+    input: &[u8],
+    parse_options: &ZzParseOptions,
+    policy_options: &ZzPolicyOptions,
+) -> ZzError {
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(input).unwrap();
+
+    csv_zztx_parser_streaming(
+        &mut CsvZzTxParserNomImpl,
+        &file,
+        parse_options,
+        policy_options,
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    )
+    .err()
+    .unwrap()
+}
+
+#[test]
+fn test_duplicate_tx_fail_case() {
+    let input = std::fs::read_to_string("tests/test_cases/duplicate_tx_reject/input.csv").unwrap();
+    let err = run_err(
+        input.as_bytes(),
+        &Default::default(),
+        &ZzPolicyOptions {
+            on_duplicate_tx: DuplicateTxOptions::Fail,
+            ..Default::default()
+        },
+    );
+    assert_matches!(err, ZzError::Policy(_));
+    assert_eq!(err.to_string(), "Duplicate tx id 1");
+}
+
+/// The errors that stop the loop say which row they're in, the header is row 1
+#[test]
+fn test_errors() {
+    let input = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1\n";
+    let err = run_err(input, &Default::default(), &Default::default());
+    assert_matches!(
+        err,
+        ZzError::Parse {
+            row_number: 3,
+            kind: ParseErrorKind::MissingRequiredField,
+            ..
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Failed to parse csv. Row 3 is missing a required field: deposit,1"
+    );
+
+    let err = run_err(
+        b"type,client,tx,amount\ndeposit,1,1,1.0,extra\n",
+        &Default::default(),
+        &Default::default(),
+    );
+    assert_matches!(
+        err,
+        ZzError::Parse {
+            row_number: 2,
+            kind: ParseErrorKind::ContainsExcessiveFields,
+            ..
+        }
+    );
+
+    // the last row has no newline
+    let parse_options = ZzParseOptions {
+        max_line_width: 24,
+        ..Default::default()
+    };
+    let inputs: [&[u8]; 2] = [
+        b"type,client,tx,amount\ndeposit,1,1,1.00000000000000\n",
+        b"type,client,tx,amount\ndeposit,1,1,1.00000000000000",
+    ];
+    for input in inputs {
+        let err = run_err(input, &parse_options, &Default::default());
+        assert_matches!(err, ZzError::Framing { row_number: 2, .. });
+        assert_eq!(err.exit_code(), 4);
+    }
+
+    let err = run_err(
+        b"type,client,tx,amount\ndeposit,1,1,\xff\n",
+        &Default::default(),
+        &Default::default(),
+    );
+    assert_matches!(err, ZzError::Framing { row_number: 2, .. });
+}